@@ -0,0 +1,51 @@
+#import bevy_pbr::{
+    pbr_fragment::pbr_input_from_standard_material,
+    pbr_functions::{alpha_discard, apply_pbr_lighting, main_pass_post_lighting_processing},
+    forward_io::{VertexOutput, FragmentOutput},
+}
+
+struct TriplanarSettings {
+    scale: f32,
+    blend_sharpness: f32,
+    layer_count: u32,
+};
+
+@group(2) @binding(100)
+var<uniform> triplanar: TriplanarSettings;
+
+@group(2) @binding(101)
+var triplanar_textures: texture_2d_array<f32>;
+
+@group(2) @binding(102)
+var triplanar_sampler: sampler;
+
+fn triplanar_sample(world_position: vec3<f32>, world_normal: vec3<f32>, layer: i32) -> vec4<f32> {
+    var weights = pow(abs(world_normal), vec3<f32>(triplanar.blend_sharpness));
+    weights = weights / (weights.x + weights.y + weights.z);
+
+    let p = world_position * triplanar.scale;
+    let x = textureSample(triplanar_textures, triplanar_sampler, p.zy, layer);
+    let y = textureSample(triplanar_textures, triplanar_sampler, p.xz, layer);
+    let z = textureSample(triplanar_textures, triplanar_sampler, p.xy, layer);
+
+    return x * weights.x + y * weights.y + z * weights.z;
+}
+
+@fragment
+fn fragment(in: VertexOutput, @builtin(front_facing) is_front: bool) -> FragmentOutput {
+    var pbr_input = pbr_input_from_standard_material(in, is_front);
+
+    var layer: i32 = 0;
+#ifdef VERTEX_UVS_B
+    layer = clamp(i32(round(in.uv_b.x)), 0, max(i32(triplanar.layer_count) - 1, 0));
+#endif
+
+    let albedo = triplanar_sample(in.world_position.xyz, normalize(in.world_normal), layer);
+    pbr_input.material.base_color = pbr_input.material.base_color * albedo;
+    pbr_input.material.base_color = alpha_discard(pbr_input.material, pbr_input.material.base_color);
+
+    var out: FragmentOutput;
+    out.color = apply_pbr_lighting(pbr_input);
+    out.color = main_pass_post_lighting_processing(pbr_input, out.color);
+    return out;
+}
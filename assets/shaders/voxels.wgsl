@@ -30,6 +30,13 @@ struct Atomics {
     indices_head: atomic<u32>,
 };
 
+struct ChunkParams {
+    lod: u32,
+    transition_faces: u32,
+    transition_width: f32,
+    _padding: u32,
+};
+
 struct EdgeTable {
     data: array<u32, 256>,
 };
@@ -62,6 +69,9 @@ var<storage, read_write> out_indices: IndexBuffer;
 @group(0) @binding(7)
 var<storage, read_write> out_uvs: UvBuffer;
 
+@group(0) @binding(8)
+var<uniform> params: ChunkParams;
+
 
 const chunk_sz = 32;
 
@@ -79,11 +89,99 @@ fn get_voxel_density(pos: vec3<i32>) -> f32 {
     return density;
 }
 
+// Block voxels are meshed as cubes, the smooth mesher treats them as empty
+fn get_smooth_density(pos: vec3<i32>) -> f32 {
+    var density: f32 = 0.0;
+    if (pos.x >= 0 && pos.x < chunk_sz
+     && pos.y >= 0 && pos.y < chunk_sz
+     && pos.z >= 0 && pos.z < chunk_sz) {
+        let voxel = in_voxels.data[get_flat_index(pos)];
+        if (voxel.flags == 0u) {
+            density = voxel.density;
+        }
+    }
+    return density;
+}
+
+fn has_transition(face: u32) -> bool {
+    return params.lod > 0u && (params.transition_faces & (1u << face)) != 0u;
+}
+
+// Corners on a face that borders a finer chunk are moved inwards to make room for the transition
+// cells, which are built on the CPU. The far faces use the width of the possibly shorter last cell
+fn corner_position(p: vec3<i32>, stride: i32, last: i32) -> vec3<f32> {
+    var position = vec3<f32>(p);
+    let width = params.transition_width * f32(stride);
+    let last_width = params.transition_width * f32(last - ((last - 1) / stride) * stride);
+    for (var axis: i32 = 0; axis < 3; axis = axis + 1) {
+        if (p[axis] == 0 && has_transition(u32(axis * 2))) {
+            position[axis] = position[axis] + width;
+        } else if (p[axis] == last && has_transition(u32(axis * 2 + 1))) {
+            position[axis] = position[axis] - last_width;
+        }
+    }
+    return position;
+}
+
+// Corners past the last sample are clamped to it so that every level meshes up to the boundary
+fn corner_index(pos: vec3<i32>, offset: vec3<i32>, stride: i32, last: i32) -> vec3<i32> {
+    return min(pos + offset * stride, vec3<i32>(last));
+}
+
+// Edges are always interpolated from their lower end, so cubes that share an edge get the same
+// vertex no matter in which order they pass the ends
 fn interp_vertex(p1: vec3<f32>, p2: vec3<f32>, v1: f32, v2: f32) -> vec3<f32> {
+    if (p2.x < p1.x || (p2.x == p1.x && (p2.y < p1.y || (p2.y == p1.y && p2.z < p1.z)))) {
+        let mu = (0.5 - v2) / (v1 - v2);
+        return p2 + mu * (p1 - p2);
+    }
     let mu = (0.5 - v1) / (v2 - v1);
     return p1 + mu * (p2 - p1);
 }
 
+// Cubes with a face of alternating corners, or with two opposite corners on one side, can't be
+// triangulated consistently from the tables. They are skipped here and meshed on the CPU
+fn is_ambiguous(cube_idx: u32) -> bool {
+    var faces = array<vec4<u32>, 6>(
+        vec4<u32>(0u, 1u, 2u, 3u),
+        vec4<u32>(4u, 5u, 6u, 7u),
+        vec4<u32>(0u, 1u, 5u, 4u),
+        vec4<u32>(3u, 2u, 6u, 7u),
+        vec4<u32>(0u, 3u, 7u, 4u),
+        vec4<u32>(1u, 2u, 6u, 5u),
+    );
+    for (var i: u32 = 0u; i < 6u; i = i + 1u) {
+        let face = faces[i];
+        let a = (cube_idx >> face.x) & 1u;
+        let b = (cube_idx >> face.y) & 1u;
+        let c = (cube_idx >> face.z) & 1u;
+        let d = (cube_idx >> face.w) & 1u;
+        if (a == c && b == d && a != b) {
+            return true;
+        }
+    }
+
+    var odd = cube_idx;
+    let count = countOneBits(cube_idx);
+    if (count == 6u) {
+        odd = ~cube_idx & 0xffu;
+    } else if (count != 2u) {
+        return false;
+    }
+
+    var offsets = array<vec3<i32>, 8>(
+        vec3<i32>(0, 0, 1),
+        vec3<i32>(1, 0, 1),
+        vec3<i32>(1, 0, 0),
+        vec3<i32>(0, 0, 0),
+        vec3<i32>(0, 1, 1),
+        vec3<i32>(1, 1, 1),
+        vec3<i32>(1, 1, 0),
+        vec3<i32>(0, 1, 0)
+    );
+    return all(offsets[firstTrailingBit(odd)] != offsets[firstLeadingBit(odd)]);
+}
+
 @compute @workgroup_size(8, 8, 8)
 fn main(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
 
@@ -92,6 +190,13 @@ fn main(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
 
     if (voxel.flags == 0u) {
 
+        // Coarser levels of detail only mesh every stride-th sample, plus the boundary samples
+        let stride = i32(1u << params.lod);
+        let last = chunk_sz - 1;
+        if (any(pos % vec3<i32>(stride) != vec3<i32>(0)) || any(pos >= vec3<i32>(last))) {
+            return;
+        }
+
         let smooth_adj_offsets = array<vec3<i32>, 8>(
             vec3<i32>(0, 0, 1),
             vec3<i32>(1, 0, 1),
@@ -106,24 +211,24 @@ fn main(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
         var cube_idx: u32 = 0u;
         var orient: u32 = 0u;
         let positions = array<vec3<f32>, 8>(
-            vec3<f32>(pos + smooth_adj_offsets[0u]),
-            vec3<f32>(pos + smooth_adj_offsets[1u]),
-            vec3<f32>(pos + smooth_adj_offsets[2u]),
-            vec3<f32>(pos + smooth_adj_offsets[3u]),
-            vec3<f32>(pos + smooth_adj_offsets[4u]),
-            vec3<f32>(pos + smooth_adj_offsets[5u]),
-            vec3<f32>(pos + smooth_adj_offsets[6u]),
-            vec3<f32>(pos + smooth_adj_offsets[7u]),
+            corner_position(corner_index(pos, smooth_adj_offsets[0u], stride, last), stride, last),
+            corner_position(corner_index(pos, smooth_adj_offsets[1u], stride, last), stride, last),
+            corner_position(corner_index(pos, smooth_adj_offsets[2u], stride, last), stride, last),
+            corner_position(corner_index(pos, smooth_adj_offsets[3u], stride, last), stride, last),
+            corner_position(corner_index(pos, smooth_adj_offsets[4u], stride, last), stride, last),
+            corner_position(corner_index(pos, smooth_adj_offsets[5u], stride, last), stride, last),
+            corner_position(corner_index(pos, smooth_adj_offsets[6u], stride, last), stride, last),
+            corner_position(corner_index(pos, smooth_adj_offsets[7u], stride, last), stride, last),
         );
         let densities = array<f32, 8>(
-            get_voxel_density(pos + smooth_adj_offsets[0u]),
-            get_voxel_density(pos + smooth_adj_offsets[1u]),
-            get_voxel_density(pos + smooth_adj_offsets[2u]),
-            get_voxel_density(pos + smooth_adj_offsets[3u]),
-            get_voxel_density(pos + smooth_adj_offsets[4u]),
-            get_voxel_density(pos + smooth_adj_offsets[5u]),
-            get_voxel_density(pos + smooth_adj_offsets[6u]),
-            get_voxel_density(pos + smooth_adj_offsets[7u]),
+            get_smooth_density(corner_index(pos, smooth_adj_offsets[0u], stride, last)),
+            get_smooth_density(corner_index(pos, smooth_adj_offsets[1u], stride, last)),
+            get_smooth_density(corner_index(pos, smooth_adj_offsets[2u], stride, last)),
+            get_smooth_density(corner_index(pos, smooth_adj_offsets[3u], stride, last)),
+            get_smooth_density(corner_index(pos, smooth_adj_offsets[4u], stride, last)),
+            get_smooth_density(corner_index(pos, smooth_adj_offsets[5u], stride, last)),
+            get_smooth_density(corner_index(pos, smooth_adj_offsets[6u], stride, last)),
+            get_smooth_density(corner_index(pos, smooth_adj_offsets[7u], stride, last)),
         );
         cube_idx = cube_idx | u32(densities[0u] < 0.5) * (1u << 0u);
         cube_idx = cube_idx | u32(densities[1u] < 0.5) * (1u << 1u);
@@ -134,7 +239,7 @@ fn main(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
         cube_idx = cube_idx | u32(densities[6u] < 0.5) * (1u << 6u);
         cube_idx = cube_idx | u32(densities[7u] < 0.5) * (1u << 7u);
 
-        if (cube_idx == 0x00u || cube_idx == 0xffu) {
+        if (cube_idx == 0x00u || cube_idx == 0xffu || is_ambiguous(cube_idx)) {
             return;
         }
 
@@ -236,7 +341,7 @@ fn main(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
         var dir: u32 = 0u;
         loop {
             let adj_pos = pos + block_adj_offsets[dir];
-            let adj_density = get_voxel_density(pos);
+            let adj_density = get_voxel_density(adj_pos);
 
             if (adj_density < 0.5) {
                 var pos = vec3<f32>(invocation_id);
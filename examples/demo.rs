@@ -1,24 +1,18 @@
-mod camera;
-mod lut;
-mod marching_cubes_cpu;
-mod marching_cubes_gpu;
-
 use bevy::app::App;
 
 use bevy::log::LogPlugin;
 use bevy::math::primitives;
 use bevy::prelude::*;
 use bevy::render::mesh::{
-    Indices, PlaneMeshBuilder, RhombusMeshBuilder, SphereMeshBuilder, VertexAttributeValues,
+    Indices, PlaneMeshBuilder, PrimitiveTopology, RhombusMeshBuilder, SphereMeshBuilder,
+    VertexAttributeValues,
 };
 use bevy::render::render_asset::RenderAssetUsages;
 use bevy_rapier3d::plugin::{NoUserData, RapierPhysicsPlugin};
 use bevy_rapier3d::prelude::Collider;
 use bevy_rapier3d::render::RapierDebugRenderPlugin;
-use camera::camera_control;
-use marching_cubes_cpu::{Bounds, MarchingCubesCpuPlugin, VoxelGrid};
-use marching_cubes_gpu::{Chunk, MarchingCubesGpuPlugin};
-use wgpu::PrimitiveTopology;
+use mcgpu::camera::camera_control;
+use mcgpu::prelude::*;
 
 fn main() {
     App::new()
@@ -27,7 +21,7 @@ fn main() {
             ..Default::default()
         }))
         // .add_plugins(MarchingCubesGpuPlugin)
-        .add_plugins(MarchingCubesCpuPlugin)
+        .add_plugins((VoxelPlugins, VoxelEditorPlugins))
         .add_plugins(RapierPhysicsPlugin::<NoUserData>::default())
         // .add_plugins(RapierDebugRenderPlugin::default())
         .insert_resource(AmbientLight {
@@ -39,9 +33,6 @@ fn main() {
         .run();
 }
 
-#[derive(Component)]
-struct CameraMarker;
-
 fn setup(
     mut commands: Commands,
     mut ambient_light: ResMut<AmbientLight>,
@@ -78,7 +69,7 @@ fn setup(
 fn spawn_voxel_sys(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut materials: ResMut<Assets<TriplanarMaterial>>,
 ) {
     let mut mesh = Mesh::new(
         PrimitiveTopology::TriangleList,
@@ -116,21 +107,26 @@ fn spawn_voxel_sys(
     )
     .unwrap();
 
-    let voxel_grid = VoxelGrid::from_mesh(&mesh, [32, 32, 32]);
+    let voxel_grid = VoxelGrid::<f32>::from_mesh(&mesh, [32, 32, 32]).unwrap();
     let mesh_handle = meshes.add(mesh);
-    let ground_mat_handle = materials.add(StandardMaterial {
-        base_color: Color::BLACK,
-        ..default()
+    let ground_mat_handle = materials.add(TriplanarMaterial {
+        base: StandardMaterial {
+            base_color: Color::BLACK,
+            ..default()
+        },
+        extension: TriplanarExtension::default(),
     });
 
     commands.spawn((
+        Name::new("sphere"),
         Chunk::new(IVec3::ZERO),
-        PbrBundle {
+        MaterialMeshBundle {
             mesh: mesh_handle.clone(),
             material: ground_mat_handle.clone(),
             ..default()
         },
         voxel_grid,
         collider.clone(),
+        AmbientOcclusionSettings::default(),
     ));
 }
@@ -0,0 +1,113 @@
+use bevy::{
+    math::{Quat, Vec3},
+    prelude::Component,
+};
+
+use crate::{marching_cubes_cpu::VoxelGrid, voxel_value::VoxelValue};
+
+// Adding this component to an entity with a VoxelGrid bakes an occlusion term into the vertex
+// colours of the generated mesh, the StandardMaterial multiplies the base colour with it
+#[derive(Component, Clone, Copy, Debug)]
+pub struct AmbientOcclusionSettings {
+    // Number of directions sampled in the cone around the normal
+    pub directions: usize,
+    // Number of samples taken along each direction
+    pub steps: usize,
+    // Length of each direction in grid local units
+    pub radius: f32,
+    // Half angle of the sampled cone in radians, PI / 2 gives a full hemisphere
+    pub cone_angle: f32,
+    pub strength: f32,
+}
+
+impl Default for AmbientOcclusionSettings {
+    fn default() -> Self {
+        Self {
+            directions: 12,
+            steps: 4,
+            radius: 0.5,
+            cone_angle: std::f32::consts::FRAC_PI_3,
+            strength: 1.0,
+        }
+    }
+}
+
+pub fn bake_ambient_occlusion<V: VoxelValue>(
+    voxel_grid: &VoxelGrid<V>,
+    positions: &[[f32; 3]],
+    normals: &[[f32; 3]],
+    settings: &AmbientOcclusionSettings,
+) -> Vec<[f32; 4]> {
+    let directions = cone_directions(settings.directions, settings.cone_angle);
+
+    positions
+        .iter()
+        .zip(normals.iter())
+        .map(|(position, normal)| {
+            let position = Vec3::from(*position);
+            let mut normal = Vec3::from(*normal).normalize_or_zero();
+
+            // The triangle winding of the meshers is not guaranteed to point out of the surface,
+            // the density gradient always points inwards
+            if normal.dot(voxel_grid.gradient(position)) > 0.0 {
+                normal = -normal;
+            }
+
+            let occlusion = occlusion_at(voxel_grid, position, normal, &directions, settings);
+            let light = (1.0 - settings.strength * occlusion).clamp(0.0, 1.0);
+
+            [light, light, light, 1.0]
+        })
+        .collect()
+}
+
+fn occlusion_at<V: VoxelValue>(
+    voxel_grid: &VoxelGrid<V>,
+    position: Vec3,
+    normal: Vec3,
+    directions: &[Vec3],
+    settings: &AmbientOcclusionSettings,
+) -> f32 {
+    if normal == Vec3::ZERO || directions.is_empty() || settings.steps == 0 {
+        return 0.0;
+    }
+
+    let rotation = Quat::from_rotation_arc(Vec3::Z, normal);
+
+    let mut occlusion = 0.0;
+    let mut total_weight = 0.0;
+
+    for direction in directions.iter() {
+        let direction = rotation * *direction;
+
+        for step in 1..=settings.steps {
+            let distance = settings.radius * step as f32 / settings.steps as f32;
+            // Closer samples contribute more to the occlusion
+            let weight = 1.0 / step as f32;
+
+            let density = voxel_grid.sample(position + direction * distance);
+
+            occlusion += density.clamp(0.0, 1.0) * weight;
+            total_weight += weight;
+        }
+    }
+
+    occlusion / total_weight
+}
+
+// Directions in a cone around +Z distributed on a spiral
+fn cone_directions(count: usize, cone_angle: f32) -> Vec<Vec3> {
+    let golden_angle = std::f32::consts::PI * (3.0 - 5.0f32.sqrt());
+    let min_cos = cone_angle.cos();
+
+    (0..count)
+        .map(|i| {
+            let t = (i as f32 + 0.5) / count as f32;
+            let cos_theta = 1.0 - t * (1.0 - min_cos);
+            let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+            let phi = golden_angle * i as f32;
+
+            Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+        })
+        .collect()
+}
@@ -0,0 +1,260 @@
+use std::{
+    env,
+    error::Error,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    time::{Duration, Instant},
+};
+
+use bevy::{math::IVec3, prelude::GlobalTransform};
+use mcgpu::{
+    dual_contouring::{dual_contouring, HermiteData},
+    export::{export_mesh, MeshFormat},
+    gltf_export::{export_gltf, GltfChunk},
+    import::load_mesh,
+    lod::ChunkLod,
+    marching_cubes_cpu::VoxelGrid,
+    meshing::{mesh_voxel_grid, MeshData, MeshingAlgorithm, MeshingSettings},
+    volume::load_nrrd,
+    vox::VoxScene,
+    voxel_file::VoxelFile,
+};
+
+const USAGE: &str = "\
+usage: mcgpu-cli <input> <output> [options]
+
+inputs:  .obj .gltf .glb meshes are voxelized first
+         .nrrd .nhdr .vox .mcvx volumes are meshed as they are
+outputs: .obj .ply .stl .gltf .glb
+
+options:
+  --resolution <n | x,y,z>   voxels along each axis when voxelizing a mesh, 64 by default
+  --algorithm <name>         marching-cubes, surface-nets, dual-contouring or marching-tetrahedra
+  --iso <level>              samples above the iso level are inside, 0.5 by default
+  --format <name>            obj, ply, ply-ascii or stl, taken from the output extension by default";
+
+struct Options {
+    input: PathBuf,
+    output: PathBuf,
+    resolution: [usize; 3],
+    settings: MeshingSettings,
+    format: Option<MeshFormat>,
+}
+
+fn main() -> ExitCode {
+    if env::args().any(|arg| arg == "-h" || arg == "--help") {
+        println!("{}", USAGE);
+        return ExitCode::SUCCESS;
+    }
+
+    let options = match parse_options(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, USAGE);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match run(&options) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn parse_options(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut paths = Vec::new();
+    let mut resolution = [64; 3];
+    let mut settings = MeshingSettings::default();
+    let mut format = None;
+
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or_else(|| format!("{} needs a value", name));
+
+        match arg.as_str() {
+            "--resolution" => resolution = parse_resolution(&value("--resolution")?)?,
+            "--algorithm" => settings.algorithm = parse_algorithm(&value("--algorithm")?)?,
+            "--iso" => {
+                let iso_level = value("--iso")?;
+                settings.iso_level = iso_level
+                    .parse()
+                    .map_err(|_| format!("invalid iso level {:?}", iso_level))?;
+            }
+            "--format" => {
+                let name = value("--format")?;
+                format = Some(
+                    MeshFormat::from_name(&name)
+                        .ok_or_else(|| format!("unknown format {:?}", name))?,
+                );
+            }
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+
+    let [input, output] = <[PathBuf; 2]>::try_from(paths)
+        .map_err(|_| "expected an input and an output path".to_string())?;
+
+    Ok(Options {
+        input,
+        output,
+        resolution,
+        settings,
+        format,
+    })
+}
+
+fn parse_resolution(value: &str) -> Result<[usize; 3], String> {
+    let invalid = || format!("invalid resolution {:?}", value);
+
+    let sizes = value
+        .split(',')
+        .map(|size| size.trim().parse::<usize>().map_err(|_| invalid()))
+        .collect::<Result<Vec<usize>, String>>()?;
+
+    // Marching needs at least two samples along every axis
+    let resolution = match sizes.as_slice() {
+        [size] => [*size; 3],
+        [x, y, z] => [*x, *y, *z],
+        _ => return Err(invalid()),
+    };
+
+    if resolution.iter().any(|size| *size < 2) {
+        return Err(invalid());
+    }
+
+    Ok(resolution)
+}
+
+fn parse_algorithm(value: &str) -> Result<MeshingAlgorithm, String> {
+    match value {
+        "marching-cubes" => Ok(MeshingAlgorithm::MarchingCubes),
+        "surface-nets" => Ok(MeshingAlgorithm::SurfaceNets),
+        "dual-contouring" => Ok(MeshingAlgorithm::DualContouring),
+        "marching-tetrahedra" => Ok(MeshingAlgorithm::MarchingTetrahedra),
+        _ => Err(format!("unknown algorithm {:?}", value)),
+    }
+}
+
+fn extension(path: &Path) -> String {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase()
+}
+
+// None when the file is not a volume and has to be voxelized
+fn load_volume(path: &Path) -> Result<Option<VoxelGrid>, Box<dyn Error>> {
+    let voxel_grid = match extension(path).as_str() {
+        "nrrd" | "nhdr" => load_nrrd(path)?,
+        "vox" => VoxScene::load(path)?
+            .to_voxel_grid(0)
+            .map(|(voxel_grid, _)| voxel_grid)
+            .ok_or("the .vox file has no models")?,
+        "mcvx" => match VoxelFile::load(path)? {
+            VoxelFile::Chunk(chunk) => chunk.density_grid(),
+            file => file.to_grid().ok_or("the file has no grid")?,
+        },
+        _ => return Ok(None),
+    };
+
+    Ok(Some(voxel_grid))
+}
+
+fn run(options: &Options) -> Result<(), Box<dyn Error>> {
+    let start = Instant::now();
+
+    // Dual contouring of a mesh takes the intersections and normals from the mesh itself
+    let mut hermite_data = None;
+
+    let voxel_grid = match load_volume(&options.input)? {
+        Some(voxel_grid) => {
+            report("load", start.elapsed());
+            voxel_grid
+        }
+        None => {
+            let mesh_data = load_mesh(&options.input)?;
+            report("load", start.elapsed());
+
+            if mesh_data.indices.is_empty() {
+                return Err("the mesh has no triangles".into());
+            }
+
+            let start = Instant::now();
+            let mesh = mesh_data.to_mesh();
+            let voxel_grid = VoxelGrid::<f32>::from_mesh(&mesh, options.resolution)?;
+
+            if options.settings.algorithm == MeshingAlgorithm::DualContouring {
+                hermite_data = Some(HermiteData::from_mesh(
+                    &voxel_grid,
+                    &mesh,
+                    options.settings.iso_level,
+                ));
+            }
+
+            report("voxelize", start.elapsed());
+
+            voxel_grid
+        }
+    };
+
+    let start = Instant::now();
+    let mesh_data = match hermite_data {
+        Some(hermite_data) => dual_contouring(
+            &voxel_grid,
+            &options.settings,
+            &ChunkLod::default(),
+            Some(&hermite_data),
+        ),
+        None => mesh_voxel_grid(&voxel_grid, &options.settings, &ChunkLod::default()),
+    };
+    report("mesh", start.elapsed());
+
+    let start = Instant::now();
+    write_output(&mesh_data, &options.output, options.format)?;
+    report("write", start.elapsed());
+
+    println!(
+        "{:?} -> {:?}: {} voxels, {} vertices, {} triangles, {}",
+        options.input,
+        options.output,
+        voxel_grid.resolution.iter().product::<usize>(),
+        mesh_data.vertex_count(),
+        mesh_data.indices.len() / 3,
+        if mesh_data.is_watertight() {
+            "watertight"
+        } else {
+            "not watertight"
+        },
+    );
+
+    Ok(())
+}
+
+fn report(stage: &str, elapsed: Duration) {
+    println!("{:<9} {:.2?}", stage, elapsed);
+}
+
+fn write_output(
+    mesh_data: &MeshData,
+    path: &Path,
+    format: Option<MeshFormat>,
+) -> Result<(), Box<dyn Error>> {
+    if format.is_none() && matches!(extension(path).as_str(), "gltf" | "glb") {
+        let chunk = GltfChunk {
+            position: IVec3::ZERO,
+            transform: GlobalTransform::IDENTITY,
+            mesh_data,
+        };
+
+        return Ok(export_gltf(&[chunk], &[], path)?);
+    }
+
+    let format = format
+        .or_else(|| MeshFormat::from_path(path))
+        .ok_or("unsupported output format")?;
+
+    Ok(export_mesh(mesh_data, path, format)?)
+}
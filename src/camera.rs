@@ -3,7 +3,8 @@ use bevy::{
     prelude::*,
 };
 
-use crate::CameraMarker;
+#[derive(Component)]
+pub struct CameraMarker;
 
 pub fn camera_control(
     time: Res<Time>,
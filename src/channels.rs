@@ -0,0 +1,207 @@
+use bevy::{
+    math::Vec3,
+    prelude::{Component, Mesh},
+    render::mesh::{MeshVertexAttribute, VertexAttributeValues},
+};
+
+use crate::{
+    chunk::CHUNK_SZ,
+    marching_cubes_cpu::{Bounds, VoxelGrid},
+    sparse::SparseGrid,
+    triplanar::ATTRIBUTE_MATERIAL_ID,
+    voxel_value::VoxelValue,
+};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ChannelInterpolation {
+    // For ids like materials, which can't be blended
+    #[default]
+    Nearest,
+    Linear,
+}
+
+// Where the values of a channel end up when the entity is meshed
+#[derive(Clone, Debug, Default)]
+pub enum ChannelTarget {
+    // Only queried by gameplay
+    #[default]
+    None,
+    // The material ID read by the triplanar material
+    MaterialId,
+    // A custom Float32 vertex attribute
+    Attribute(MeshVertexAttribute),
+}
+
+#[derive(Clone, Debug)]
+pub struct VoxelChannel {
+    pub name: String,
+    pub default: f32,
+    pub interpolation: ChannelInterpolation,
+    pub target: ChannelTarget,
+}
+
+// Named channels stored next to the density. The density itself stays in the VoxelGrid or the
+// Voxel of a Chunk and is the only channel that drives meshing.
+#[derive(Clone, Debug, Default)]
+pub struct ChannelSchema {
+    pub channels: Vec<VoxelChannel>,
+}
+
+impl ChannelSchema {
+    pub fn index(&self, name: &str) -> Option<usize> {
+        self.channels
+            .iter()
+            .position(|channel| channel.name == name)
+    }
+}
+
+// Per voxel values for every channel of the schema, laid out like the grid or chunk on the same
+// entity. Channels are stored sparsely, so a channel that is constant costs next to nothing.
+#[derive(Component, Clone)]
+pub struct VoxelChannels {
+    pub schema: ChannelSchema,
+    pub resolution: [usize; 3],
+    pub bounds: Bounds,
+    values: Vec<SparseGrid<f32>>,
+}
+
+impl VoxelChannels {
+    pub fn new(schema: ChannelSchema, resolution: [usize; 3], bounds: Bounds) -> Self {
+        let values = schema
+            .channels
+            .iter()
+            .map(|channel| SparseGrid::new(resolution, channel.default))
+            .collect();
+
+        Self {
+            schema,
+            resolution,
+            bounds,
+            values,
+        }
+    }
+
+    pub fn for_grid<V: VoxelValue>(schema: ChannelSchema, voxel_grid: &VoxelGrid<V>) -> Self {
+        Self::new(schema, voxel_grid.resolution, voxel_grid.bounds)
+    }
+
+    // Chunk meshes are in voxel units
+    pub fn for_chunk(schema: ChannelSchema) -> Self {
+        Self::new(
+            schema,
+            [CHUNK_SZ; 3],
+            Bounds {
+                min: Vec3::ZERO,
+                max: Vec3::splat(CHUNK_SZ as f32),
+            },
+        )
+    }
+
+    pub fn get(&self, name: &str, index: [usize; 3]) -> Option<f32> {
+        let channel = self.schema.index(name)?;
+        Some(self.values[channel].get(index))
+    }
+
+    pub fn set(&mut self, name: &str, index: [usize; 3], value: f32) -> bool {
+        let Some(channel) = self.schema.index(name) else {
+            return false;
+        };
+
+        self.values[channel].set(index, value);
+        true
+    }
+
+    pub fn channel(&self, name: &str) -> Option<&SparseGrid<f32>> {
+        self.schema.index(name).map(|channel| &self.values[channel])
+    }
+
+    pub fn channel_mut(&mut self, name: &str) -> Option<&mut SparseGrid<f32>> {
+        self.schema
+            .index(name)
+            .map(|channel| &mut self.values[channel])
+    }
+
+    // Value of a channel at a point in the local space of the grid, clamped to the grid
+    pub fn sample(&self, name: &str, point: Vec3) -> Option<f32> {
+        let channel = self.schema.index(name)?;
+        Some(self.sample_channel(channel, point))
+    }
+
+    fn sample_channel(&self, channel: usize, point: Vec3) -> f32 {
+        let values = &self.values[channel];
+
+        let cell_size = (self.bounds.max - self.bounds.min)
+            / Vec3::new(
+                self.resolution[0] as f32,
+                self.resolution[1] as f32,
+                self.resolution[2] as f32,
+            );
+
+        let last = self.resolution.map(|r| r.saturating_sub(1));
+        let local = ((point - self.bounds.min) / cell_size).max(Vec3::ZERO);
+        let local = local.min(Vec3::new(last[0] as f32, last[1] as f32, last[2] as f32));
+
+        match self.schema.channels[channel].interpolation {
+            ChannelInterpolation::Nearest => {
+                let index = local.round();
+                values.get([index.x as usize, index.y as usize, index.z as usize])
+            }
+            ChannelInterpolation::Linear => {
+                let i0 = [local.x as usize, local.y as usize, local.z as usize];
+                let i1 = [0, 1, 2].map(|i| (i0[i] + 1).min(last[i]));
+                let t = local - Vec3::new(i0[0] as f32, i0[1] as f32, i0[2] as f32);
+
+                let corner = |x: bool, y: bool, z: bool| {
+                    values.get([
+                        if x { i1[0] } else { i0[0] },
+                        if y { i1[1] } else { i0[1] },
+                        if z { i1[2] } else { i0[2] },
+                    ])
+                };
+
+                let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+
+                let c00 = lerp(corner(false, false, false), corner(true, false, false), t.x);
+                let c10 = lerp(corner(false, true, false), corner(true, true, false), t.x);
+                let c01 = lerp(corner(false, false, true), corner(true, false, true), t.x);
+                let c11 = lerp(corner(false, true, true), corner(true, true, true), t.x);
+
+                lerp(lerp(c00, c10, t.y), lerp(c01, c11, t.y), t.z)
+            }
+        }
+    }
+
+    // Samples every channel with a target at the vertex positions of the mesh and writes them to
+    // its attributes
+    pub fn insert_attributes(&self, mesh: &mut Mesh) {
+        let Some(VertexAttributeValues::Float32x3(positions)) =
+            mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+        else {
+            return;
+        };
+
+        let positions = positions
+            .iter()
+            .map(|position| Vec3::from(*position))
+            .collect::<Vec<Vec3>>();
+
+        for (channel, description) in self.schema.channels.iter().enumerate() {
+            let values = positions
+                .iter()
+                .map(|position| self.sample_channel(channel, *position));
+
+            match &description.target {
+                ChannelTarget::None => {}
+                ChannelTarget::MaterialId => {
+                    let ids = values
+                        .map(|value| [value.round().max(0.0), 0.0])
+                        .collect::<Vec<[f32; 2]>>();
+                    mesh.insert_attribute(ATTRIBUTE_MATERIAL_ID, ids);
+                }
+                ChannelTarget::Attribute(attribute) => {
+                    mesh.insert_attribute(attribute.clone(), values.collect::<Vec<f32>>());
+                }
+            }
+        }
+    }
+}
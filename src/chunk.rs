@@ -0,0 +1,60 @@
+use bevy::{
+    math::{IVec3, Vec3},
+    prelude::Component,
+};
+use bytemuck::{Pod, Zeroable};
+
+use crate::{
+    lod::ChunkLod,
+    marching_cubes_cpu::{Bounds, VoxelGrid},
+    sparse::SparseGrid,
+};
+
+pub const CHUNK_SZ: usize = 32;
+pub const CHUNK_SZ_2: usize = CHUNK_SZ * CHUNK_SZ;
+pub const CHUNK_SZ_3: usize = CHUNK_SZ * CHUNK_SZ * CHUNK_SZ;
+
+#[derive(Component, Clone)]
+pub struct Chunk {
+    pub position: IVec3,
+    pub voxels: SparseGrid<Voxel>,
+    pub lod: ChunkLod,
+}
+
+impl Chunk {
+    pub fn new(position: IVec3) -> Self {
+        Self {
+            position,
+            voxels: SparseGrid::new([CHUNK_SZ; 3], Voxel::default()),
+            lod: ChunkLod::default(),
+        }
+    }
+
+    // The densities in the local space of the chunk, where samples are one unit apart. Flagged
+    // voxels are blocky and always solid.
+    pub fn density_grid(&self) -> VoxelGrid {
+        let values = self
+            .voxels
+            .iter()
+            .map(|voxel| if voxel.flags != 0 { 1.0 } else { voxel.density })
+            .collect::<Vec<f32>>();
+
+        VoxelGrid {
+            resolution: [CHUNK_SZ; 3],
+            data: SparseGrid::from_dense([CHUNK_SZ; 3], &values),
+            bounds: Bounds {
+                min: Vec3::ZERO,
+                max: Vec3::splat(CHUNK_SZ as f32),
+            },
+        }
+    }
+}
+
+// Laid out like the `Voxel` of the compute shader, the chunk is uploaded as it is. Chunks keep f32
+// densities for that reason, quantized values are only available on a `VoxelGrid<V>`.
+#[derive(Copy, Clone, Default, PartialEq, Pod, Zeroable)]
+#[repr(C)]
+pub struct Voxel {
+    pub flags: u32,
+    pub density: f32,
+}
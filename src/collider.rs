@@ -0,0 +1,385 @@
+use bevy::{
+    app::{App, Plugin, Update},
+    math::{Quat, Vec3},
+    prelude::{Commands, Component, Entity, GlobalTransform, IntoSystemConfigs, Query},
+    tasks::{block_on, futures_lite::future, AsyncComputeTaskPool, Task},
+};
+use bevy_rapier3d::prelude::{Collider, RigidBody, VHACDParameters};
+
+use crate::{
+    chunk::{Chunk, CHUNK_SZ},
+    marching_cubes_cpu::VoxelGrid,
+    meshing::{MeshData, MeshGeneration},
+    voxel_value::VoxelValue,
+};
+
+pub struct ColliderPlugin;
+
+impl Plugin for ColliderPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (spawn_collider_tasks_system, apply_collider_tasks_system).chain(),
+        );
+    }
+}
+
+#[derive(Clone, Default, Debug, PartialEq)]
+pub enum ColliderStrategy {
+    // Uses the index buffer of the mesher output as is
+    #[default]
+    TriMesh,
+    // One box per run of solid voxels along x
+    Cuboids,
+    // Height of the topmost surface in every column, only suited for terrain without overhangs
+    Heightfield,
+    ConvexDecomposition(VHACDParameters),
+}
+
+impl ColliderStrategy {
+    pub fn uses_mesh(&self) -> bool {
+        matches!(
+            self,
+            ColliderStrategy::TriMesh | ColliderStrategy::ConvexDecomposition(_)
+        )
+    }
+}
+
+// Per chunk collider options, chunks without it get a trimesh that is built right away
+#[derive(Component, Clone, Debug, Default)]
+pub struct ColliderSettings {
+    pub strategy: ColliderStrategy,
+    // When set, the collider is only built once a dynamic or kinematic body is within this
+    // distance of the chunk, measured in the local space of the chunk
+    pub activation_distance: Option<f32>,
+}
+
+// Geometry of a new mesh generation that still needs a collider
+#[derive(Component)]
+pub struct PendingCollider(Option<ColliderSource>);
+
+// A collider being built in the background, replacing it drops and cancels the previous build
+#[derive(Component)]
+pub struct ColliderTask {
+    generation: MeshGeneration,
+    task: Task<Option<Collider>>,
+}
+
+// The mesh generation the current collider was built from
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ColliderGeneration(pub MeshGeneration);
+
+// Densities copied out of a grid or chunk so the collider can be built off the main thread
+#[derive(Clone, Debug)]
+pub struct VoxelSamples {
+    pub resolution: [usize; 3],
+    pub min: Vec3,
+    pub cell_size: Vec3,
+    pub values: Vec<f32>,
+    pub iso_level: f32,
+}
+
+impl VoxelSamples {
+    pub fn from_grid<V: VoxelValue>(voxel_grid: &VoxelGrid<V>, iso_level: f32) -> Self {
+        Self {
+            resolution: voxel_grid.resolution,
+            min: voxel_grid.bounds.min,
+            cell_size: voxel_grid.cell_size(),
+            values: voxel_grid.data.iter().map(|value| value.to_f32()).collect(),
+            iso_level,
+        }
+    }
+
+    // Flagged voxels are blocky and always solid
+    pub fn from_chunk(chunk: &Chunk) -> Self {
+        Self {
+            resolution: [CHUNK_SZ; 3],
+            min: Vec3::ZERO,
+            cell_size: Vec3::ONE,
+            values: chunk
+                .voxels
+                .iter()
+                .map(|voxel| if voxel.flags != 0 { 1.0 } else { voxel.density })
+                .collect(),
+            iso_level: 0.5,
+        }
+    }
+
+    fn get(&self, [x, y, z]: [usize; 3]) -> f32 {
+        let [rx, ry, _] = self.resolution;
+        self.values[x + y * rx + z * rx * ry]
+    }
+
+    fn is_solid(&self, index: [usize; 3]) -> bool {
+        self.get(index) > self.iso_level
+    }
+
+    fn position(&self, [x, y, z]: [usize; 3]) -> Vec3 {
+        self.min + Vec3::new(x as f32, y as f32, z as f32) * self.cell_size
+    }
+
+    // Voxels are centered on their samples, neighbouring solid voxels along x share one box
+    pub fn cuboids(&self) -> Option<Collider> {
+        let [rx, ry, rz] = self.resolution;
+        let mut shapes = Vec::new();
+
+        for z in 0..rz {
+            for y in 0..ry {
+                let mut x = 0;
+
+                while x < rx {
+                    if !self.is_solid([x, y, z]) {
+                        x += 1;
+                        continue;
+                    }
+
+                    let start = x;
+                    while x < rx && self.is_solid([x, y, z]) {
+                        x += 1;
+                    }
+
+                    let center =
+                        (self.position([start, y, z]) + self.position([x - 1, y, z])) * 0.5;
+                    let half_extents =
+                        Vec3::new((x - start) as f32, 1.0, 1.0) * self.cell_size * 0.5;
+
+                    shapes.push((
+                        center,
+                        Quat::IDENTITY,
+                        Collider::cuboid(half_extents.x, half_extents.y, half_extents.z),
+                    ));
+                }
+            }
+        }
+
+        (!shapes.is_empty()).then(|| Collider::compound(shapes))
+    }
+
+    // Rows of the heightfield run along z and columns along x, columns without a surface sit
+    // at the bottom of the grid
+    pub fn heightfield(&self) -> Option<Collider> {
+        let [rx, ry, rz] = self.resolution;
+
+        if rx < 2 || rz < 2 {
+            return None;
+        }
+
+        let mut heights = Vec::with_capacity(rx * rz);
+
+        for x in 0..rx {
+            for z in 0..rz {
+                let top = (0..ry).rev().find(|&y| self.is_solid([x, y, z]));
+
+                let height = match top {
+                    Some(y) if y + 1 < ry => {
+                        let below = self.get([x, y, z]);
+                        let above = self.get([x, y + 1, z]);
+                        let t = (self.iso_level - below) / (above - below);
+
+                        (y as f32 + t.clamp(0.0, 1.0)) * self.cell_size.y
+                    }
+                    Some(y) => y as f32 * self.cell_size.y,
+                    None => 0.0,
+                };
+
+                heights.push(self.min.y + height);
+            }
+        }
+
+        let size = self.position([rx - 1, 0, rz - 1]) - self.min;
+        let center = self.min + size * 0.5;
+
+        Some(Collider::compound(vec![(
+            Vec3::new(center.x, 0.0, center.z),
+            Quat::IDENTITY,
+            Collider::heightfield(heights, rz, rx, Vec3::new(size.x, 1.0, size.z)),
+        )]))
+    }
+}
+
+pub enum ColliderSource {
+    Mesh {
+        vertices: Vec<Vec3>,
+        indices: Vec<[u32; 3]>,
+    },
+    Samples(VoxelSamples),
+}
+
+impl ColliderSource {
+    pub fn empty() -> Self {
+        ColliderSource::Mesh {
+            vertices: Vec::new(),
+            indices: Vec::new(),
+        }
+    }
+
+    // Local bounds of the geometry, None when there is nothing to collide with
+    pub fn bounds(&self) -> Option<(Vec3, Vec3)> {
+        match self {
+            ColliderSource::Mesh { vertices, .. } => {
+                let min = vertices.iter().copied().reduce(Vec3::min)?;
+                let max = vertices.iter().copied().reduce(Vec3::max)?;
+
+                Some((min, max))
+            }
+            ColliderSource::Samples(samples) => {
+                let last = samples.resolution.map(|r| r.saturating_sub(1));
+
+                Some((samples.min, samples.position(last)))
+            }
+        }
+    }
+
+    pub fn from_mesh_data(mesh_data: &MeshData) -> Self {
+        ColliderSource::Mesh {
+            vertices: mesh_data
+                .positions
+                .iter()
+                .copied()
+                .map(Vec3::from)
+                .collect(),
+            indices: mesh_data
+                .indices
+                .chunks_exact(3)
+                .map(|triangle| [triangle[0], triangle[1], triangle[2]])
+                .collect(),
+        }
+    }
+
+    // Only copies what the strategy reads
+    pub fn for_grid<V: VoxelValue>(
+        strategy: &ColliderStrategy,
+        voxel_grid: &VoxelGrid<V>,
+        iso_level: f32,
+        mesh_data: &MeshData,
+    ) -> Self {
+        if strategy.uses_mesh() {
+            Self::from_mesh_data(mesh_data)
+        } else {
+            ColliderSource::Samples(VoxelSamples::from_grid(voxel_grid, iso_level))
+        }
+    }
+
+    pub fn for_chunk(strategy: &ColliderStrategy, chunk: &Chunk, mesh_data: &MeshData) -> Self {
+        if strategy.uses_mesh() {
+            Self::from_mesh_data(mesh_data)
+        } else {
+            ColliderSource::Samples(VoxelSamples::from_chunk(chunk))
+        }
+    }
+}
+
+// Empty geometry has no collider
+pub fn build_collider(strategy: &ColliderStrategy, source: ColliderSource) -> Option<Collider> {
+    match source {
+        ColliderSource::Mesh { vertices, indices } => {
+            if indices.is_empty() {
+                return None;
+            }
+
+            Some(match strategy {
+                ColliderStrategy::ConvexDecomposition(params) => {
+                    Collider::convex_decomposition_with_params(&vertices, &indices, params)
+                }
+                _ => Collider::trimesh(vertices, indices),
+            })
+        }
+        ColliderSource::Samples(samples) => match strategy {
+            ColliderStrategy::Heightfield => samples.heightfield(),
+            _ => samples.cuboids(),
+        },
+    }
+}
+
+// Called by the meshers together with starting the next mesh generation when the geometry
+// really changed
+pub fn queue_collider(commands: &mut Commands, entity: Entity, source: ColliderSource) {
+    commands
+        .entity(entity)
+        .insert(PendingCollider(Some(source)));
+}
+
+fn is_body_nearby(
+    transform: &GlobalTransform,
+    bounds: (Vec3, Vec3),
+    distance: f32,
+    bodies: &Query<(Entity, &RigidBody, &GlobalTransform)>,
+    entity: Entity,
+) -> bool {
+    let inverse = transform.affine().inverse();
+    let (min, max) = bounds;
+
+    bodies.iter().any(|(body_entity, body, body_transform)| {
+        if body_entity == entity || *body == RigidBody::Fixed {
+            return false;
+        }
+
+        let local = inverse.transform_point3(body_transform.translation());
+        local.distance(local.clamp(min, max)) <= distance
+    })
+}
+
+pub fn spawn_collider_tasks_system(
+    mut commands: Commands,
+    mut pending: Query<(
+        Entity,
+        &mut PendingCollider,
+        &MeshGeneration,
+        &GlobalTransform,
+        Option<&ColliderSettings>,
+    )>,
+    bodies: Query<(Entity, &RigidBody, &GlobalTransform)>,
+) {
+    for (entity, mut pending_collider, generation, transform, settings) in pending.iter_mut() {
+        let settings = settings.cloned().unwrap_or_default();
+
+        let bounds = pending_collider.0.as_ref().and_then(ColliderSource::bounds);
+
+        // Empty geometry is applied right away so stale colliders don't linger
+        if let (Some(distance), Some(bounds)) = (settings.activation_distance, bounds) {
+            if !is_body_nearby(transform, bounds, distance, &bodies, entity) {
+                continue;
+            }
+        }
+
+        let Some(source) = pending_collider.0.take() else {
+            continue;
+        };
+
+        let strategy = settings.strategy;
+        let task =
+            AsyncComputeTaskPool::get().spawn(async move { build_collider(&strategy, source) });
+
+        commands
+            .entity(entity)
+            .remove::<PendingCollider>()
+            .insert(ColliderTask {
+                generation: *generation,
+                task,
+            });
+    }
+}
+
+pub fn apply_collider_tasks_system(
+    mut commands: Commands,
+    mut tasks: Query<(Entity, &mut ColliderTask, Option<&MeshGeneration>)>,
+) {
+    for (entity, mut task, generation) in tasks.iter_mut() {
+        let Some(collider) = block_on(future::poll_once(&mut task.task)) else {
+            continue;
+        };
+
+        let mut entity = commands.entity(entity);
+        entity.remove::<ColliderTask>();
+
+        // The mesh changed again while this one was being built, its own build is pending
+        if generation.is_some_and(|generation| *generation != task.generation) {
+            continue;
+        }
+
+        match collider {
+            Some(collider) => entity.insert((collider, ColliderGeneration(task.generation))),
+            None => entity.remove::<(Collider, ColliderGeneration)>(),
+        };
+    }
+}
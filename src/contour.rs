@@ -0,0 +1,551 @@
+use bevy::{math::Vec3, utils::HashMap};
+
+use crate::meshing::{interp_vertex, MeshData};
+
+#[derive(Clone, Copy)]
+pub struct CellPoint {
+    pub position: Vec3,
+    pub value: f32,
+}
+
+type EdgeKey = (u8, u8);
+
+struct Crossing {
+    position: Vec3,
+    // Points from the inside sample of the edge to the outside one
+    outward: Vec3,
+}
+
+// Traces the contour on every face of a cell, chains the segments into loops and fans them.
+// Ambiguous faces are resolved with the asymptotic decider, which only depends on the values of
+// the face, so neighbouring cells always agree and the result is a closed manifold.
+pub fn mesh_cell(points: &[CellPoint], faces: &[&[u8]], iso_level: f32, mesh_data: &mut MeshData) {
+    let (crossings, loops) = trace_loops(points, faces, iso_level);
+
+    for chain in loops.iter() {
+        fan_loop(chain, &crossings, mesh_data);
+    }
+}
+
+// Like `mesh_cell` for a cube, `offsets` gives the corner of the unit cube every point sits on.
+// Two loops on the faces can either be capped separately or joined by a tunnel through the cube,
+// which the faces alone can't tell apart. As in marching cubes 33 this is decided by the body
+// saddle of the trilinear interpolant: when it lies on the side of the corners the loops enclose,
+// those corners are connected through the interior and the loops become the ends of one tube.
+//
+// Cubes with more than two loops, which needs three ambiguous faces, are capped separately.
+pub fn mesh_cube(
+    points: &[CellPoint; 8],
+    offsets: &[[usize; 3]; 8],
+    faces: &[&[u8]],
+    iso_level: f32,
+    mesh_data: &mut MeshData,
+) {
+    let (crossings, loops) = trace_loops(points, faces, iso_level);
+
+    if let [a, b] = loops.as_slice() {
+        if has_tunnel(points, offsets, faces, iso_level) {
+            bridge_loops(a, b, offsets, &crossings, mesh_data);
+            return;
+        }
+    }
+
+    for chain in loops.iter() {
+        fan_loop(chain, &crossings, mesh_data);
+    }
+}
+
+fn trace_loops(
+    points: &[CellPoint],
+    faces: &[&[u8]],
+    iso_level: f32,
+) -> (HashMap<EdgeKey, Crossing>, Vec<Vec<EdgeKey>>) {
+    let mut crossings = HashMap::<EdgeKey, Crossing>::default();
+    let mut segments = Vec::<(EdgeKey, EdgeKey)>::new();
+
+    let center = points.iter().map(|point| point.position).sum::<Vec3>() / points.len() as f32;
+
+    for face in faces.iter() {
+        face_segments(
+            face,
+            points,
+            center,
+            iso_level,
+            &mut crossings,
+            &mut segments,
+        );
+    }
+
+    let mut loops = Vec::new();
+
+    while let Some((start, mut next)) = segments.pop() {
+        let mut chain = vec![start];
+
+        while next != start {
+            chain.push(next);
+
+            let Some(found) = segments.iter().position(|(a, _)| *a == next) else {
+                break;
+            };
+
+            next = segments.swap_remove(found).1;
+        }
+
+        if chain.len() >= 3 {
+            loops.push(chain);
+        }
+    }
+
+    (crossings, loops)
+}
+
+fn loop_positions(chain: &[EdgeKey], crossings: &HashMap<EdgeKey, Crossing>) -> Vec<Vec3> {
+    chain.iter().map(|key| crossings[key].position).collect()
+}
+
+fn fan_loop(chain: &[EdgeKey], crossings: &HashMap<EdgeKey, Crossing>, mesh_data: &mut MeshData) {
+    let positions = loop_positions(chain, crossings);
+    let center = positions.iter().sum::<Vec3>() / positions.len() as f32;
+
+    for i in 0..positions.len() {
+        mesh_data.push_triangle(center, positions[i], positions[(i + 1) % positions.len()]);
+    }
+}
+
+// Connects two loops with a tube. The loops are wound the way their caps would be, the two ends of
+// a tube face away from each other so seen along the tube the second loop runs backwards.
+fn bridge_loops(
+    a: &[EdgeKey],
+    b: &[EdgeKey],
+    offsets: &[[usize; 3]; 8],
+    crossings: &HashMap<EdgeKey, Crossing>,
+    mesh_data: &mut MeshData,
+) {
+    let b = b.iter().rev().copied().collect::<Vec<EdgeKey>>();
+    let (pa, pb) = (loop_positions(a, crossings), loop_positions(&b, crossings));
+    let (m, n) = (pa.len(), pb.len());
+
+    // Diagonals lying on a face of the cube would be shared with the neighbouring cube
+    let on_face = |x: EdgeKey, y: EdgeKey| {
+        let corners = [x.0, x.1, y.0, y.1].map(|corner| offsets[corner as usize]);
+        (0..3).any(|axis| corners.iter().all(|c| c[axis] == corners[0][axis]))
+    };
+
+    let Some(((faces, _), triangles)) = band(m, n, |i, j| {
+        (on_face(a[i], b[j]) as usize, pa[i].distance_squared(pb[j]))
+    }) else {
+        return;
+    };
+
+    if faces == 0 {
+        push_band(&pa, &pb, &triangles, mesh_data);
+        return;
+    }
+
+    // Some loops can't be joined without crossing a face, as in marching cubes 33 the tube then
+    // goes through vertices inside of the cube
+    let center = (pa.iter().sum::<Vec3>() + pb.iter().sum::<Vec3>()) / (m + n) as f32;
+    let inner_a = pa
+        .iter()
+        .map(|p| p.lerp(center, 0.5))
+        .collect::<Vec<Vec3>>();
+    let inner_b = pb
+        .iter()
+        .map(|p| p.lerp(center, 0.5))
+        .collect::<Vec<Vec3>>();
+
+    let Some((_, triangles)) = band(m, n, |i, j| (0, inner_a[i].distance_squared(inner_b[j])))
+    else {
+        return;
+    };
+
+    for i in 0..m {
+        let next = (i + 1) % m;
+        mesh_data.push_triangle(pa[i], pa[next], inner_a[next]);
+        mesh_data.push_triangle(pa[i], inner_a[next], inner_a[i]);
+    }
+
+    for j in 0..n {
+        let next = (j + 1) % n;
+        mesh_data.push_triangle(pb[next], pb[j], inner_b[j]);
+        mesh_data.push_triangle(pb[next], inner_b[j], inner_b[next]);
+    }
+
+    push_band(&inner_a, &inner_b, &triangles, mesh_data);
+}
+
+// A vertex of a band, on the second loop or not, and its index
+type BandVertex = (bool, usize);
+
+// Diagonals on a face of the cube, then the squared length of the diagonals
+type BandCost = (usize, f32);
+
+// Triangulates the band between two loops of `m` and `n` vertices. Every triangle adds a diagonal
+// between the loops, the band whose diagonals add up to the lowest cost is returned.
+fn band(
+    m: usize,
+    n: usize,
+    cost: impl Fn(usize, usize) -> BandCost,
+) -> Option<(BandCost, Vec<[BandVertex; 3]>)> {
+    let add = |x: BandCost, y: BandCost| (x.0 + y.0, x.1 + y.1);
+
+    let mut best: Option<(BandCost, usize, Vec<bool>)> = None;
+
+    for start in 0..n {
+        let diagonal = |i: usize, j: usize| cost(i % m, (start + j) % n);
+
+        // Cheapest way to reach every pair of steps along the loops, and whether the last step was
+        // on the first loop. Starting with a step on the first loop and ending with one on the
+        // second keeps the band from coming back to the diagonals it started with.
+        let mut totals = vec![vec![None::<(BandCost, bool)>; n + 1]; m + 1];
+        totals[0][0] = Some((diagonal(0, 0), false));
+
+        for i in 1..=m {
+            // Going all the way around the first loop would end on the starting diagonal
+            for j in (i == m) as usize..=n {
+                let from_a = totals[i - 1][j]
+                    .filter(|_| j < n)
+                    .map(|(total, _)| (total, true));
+                let from_b = (j > 0)
+                    .then(|| totals[i][j - 1])
+                    .flatten()
+                    .map(|(total, _)| (total, false));
+
+                let Some((total, on_a)) = [from_a, from_b]
+                    .into_iter()
+                    .flatten()
+                    .min_by(|x, y| x.0.partial_cmp(&y.0).unwrap())
+                else {
+                    continue;
+                };
+
+                // The last pair is the first one again
+                let total = if i == m && j == n {
+                    total
+                } else {
+                    add(total, diagonal(i, j))
+                };
+
+                totals[i][j] = Some((total, on_a));
+            }
+        }
+
+        let Some((total, _)) = totals[m][n] else {
+            continue;
+        };
+
+        if best.as_ref().is_some_and(|(best, ..)| *best <= total) {
+            continue;
+        }
+
+        let mut steps = Vec::with_capacity(m + n);
+        let (mut i, mut j) = (m, n);
+
+        while i > 0 || j > 0 {
+            let on_a = totals[i][j].unwrap().1;
+            steps.push(on_a);
+
+            if on_a {
+                i -= 1;
+            } else {
+                j -= 1;
+            }
+        }
+
+        steps.reverse();
+        best = Some((total, start, steps));
+    }
+
+    let (total, start, steps) = best?;
+    let (mut i, mut j) = (0, 0);
+
+    let triangles = steps
+        .into_iter()
+        .map(|on_a| {
+            if on_a {
+                i += 1;
+                [(false, i - 1), (false, i % m), (true, (start + j) % n)]
+            } else {
+                j += 1;
+                [
+                    (false, i % m),
+                    (true, (start + j) % n),
+                    (true, (start + j - 1) % n),
+                ]
+            }
+        })
+        .collect();
+
+    Some((total, triangles))
+}
+
+fn push_band(a: &[Vec3], b: &[Vec3], triangles: &[[BandVertex; 3]], mesh_data: &mut MeshData) {
+    for triangle in triangles.iter() {
+        let [p0, p1, p2] = triangle.map(|(on_b, i)| if on_b { b[i] } else { a[i] });
+        mesh_data.push_triangle(p0, p1, p2);
+    }
+}
+
+// The faces split the corners of each side into connected groups. With two loops one side has
+// two groups, which the tunnel joins when the body saddle lies on that side.
+fn has_tunnel(
+    points: &[CellPoint; 8],
+    offsets: &[[usize; 3]; 8],
+    faces: &[&[u8]],
+    iso_level: f32,
+) -> bool {
+    fn root(groups: &[usize; 8], mut corner: usize) -> usize {
+        while groups[corner] != corner {
+            corner = groups[corner];
+        }
+        corner
+    }
+
+    fn join(groups: &mut [usize; 8], a: u8, b: u8) {
+        let (a, b) = (root(groups, a as usize), root(groups, b as usize));
+        groups[a] = b;
+    }
+
+    let inside = points.map(|point| point.value > iso_level);
+
+    let mut groups = [0, 1, 2, 3, 4, 5, 6, 7];
+
+    for face in faces.iter() {
+        for i in 0..face.len() {
+            let (a, b) = (face[i], face[(i + 1) % face.len()]);
+
+            if inside[a as usize] == inside[b as usize] {
+                join(&mut groups, a, b);
+            }
+        }
+
+        if let [a, b, c, d] = **face {
+            let is_ambiguous = inside[a as usize] == inside[c as usize]
+                && inside[b as usize] == inside[d as usize]
+                && inside[a as usize] != inside[b as usize];
+
+            if is_ambiguous {
+                let values = [a, b, c, d].map(|corner| points[corner as usize].value);
+                let center_inside = face_center_value(&values) > iso_level;
+
+                // The diagonal on the side of the face saddle is connected
+                if inside[a as usize] == center_inside {
+                    join(&mut groups, a, c);
+                } else {
+                    join(&mut groups, b, d);
+                }
+            }
+        }
+    }
+
+    let group_count = |side: bool| {
+        (0..8)
+            .filter(|corner| inside[*corner] == side && root(&groups, *corner) == *corner)
+            .count()
+    };
+
+    let side = match (group_count(true), group_count(false)) {
+        (2, 1) => true,
+        (1, 2) => false,
+        _ => return false,
+    };
+
+    let mut values = [[[0.0; 2]; 2]; 2];
+
+    for (point, [x, y, z]) in points.iter().zip(offsets.iter()) {
+        values[*x][*y][*z] = point.value;
+    }
+
+    body_saddles(&values)
+        .iter()
+        .any(|saddle| (trilinear(&values, *saddle) > iso_level) == side)
+}
+
+// Coefficients of a + bx + cy + dz + exy + fyz + gxz + hxyz
+fn trilinear_coefficients(v: &[[[f32; 2]; 2]; 2]) -> [f32; 8] {
+    let a = v[0][0][0];
+    let b = v[1][0][0] - a;
+    let c = v[0][1][0] - a;
+    let d = v[0][0][1] - a;
+    let e = v[1][1][0] - v[1][0][0] - v[0][1][0] + a;
+    let f = v[0][1][1] - v[0][1][0] - v[0][0][1] + a;
+    let g = v[1][0][1] - v[1][0][0] - v[0][0][1] + a;
+    let h =
+        v[1][1][1] - v[1][1][0] - v[1][0][1] - v[0][1][1] + v[1][0][0] + v[0][1][0] + v[0][0][1]
+            - a;
+
+    [a, b, c, d, e, f, g, h]
+}
+
+fn trilinear(v: &[[[f32; 2]; 2]; 2], p: Vec3) -> f32 {
+    let [a, b, c, d, e, f, g, h] = trilinear_coefficients(v);
+
+    a + b * p.x
+        + c * p.y
+        + d * p.z
+        + e * p.x * p.y
+        + f * p.y * p.z
+        + g * p.x * p.z
+        + h * p.x * p.y * p.z
+}
+
+// Points inside of the cube where the gradient of the trilinear interpolant vanishes
+fn body_saddles(v: &[[[f32; 2]; 2]; 2]) -> Vec<Vec3> {
+    let [_, b, c, d, e, f, g, h] = trilinear_coefficients(v);
+
+    let is_inside = |p: &Vec3| p.cmpgt(Vec3::ZERO).all() && p.cmplt(Vec3::ONE).all();
+
+    // Without the cubic term the gradient is linear
+    if h.abs() < f32::EPSILON {
+        let determinant = 2.0 * e * f * g;
+
+        if determinant.abs() < f32::EPSILON {
+            return Vec::new();
+        }
+
+        // [0 e g; e 0 f; g f 0] p = -[b c d]
+        let x = (b * f * f - c * f * g - d * e * f) / determinant;
+        let y = (c * g * g - b * f * g - d * e * g) / determinant;
+        let z = (d * e * e - b * e * f - c * e * g) / determinant;
+
+        return [Vec3::new(x, y, z)].into_iter().filter(is_inside).collect();
+    }
+
+    // Eliminating x and y leaves a quadratic in z
+    let k = d * h - f * g;
+    let qa = h * k;
+    let qb = 2.0 * e * k;
+    let qc = d * e * e - e * (f * b + g * c) + h * b * c;
+
+    if qa.abs() < f32::EPSILON {
+        return Vec::new();
+    }
+
+    let discriminant = qb * qb - 4.0 * qa * qc;
+
+    if discriminant < 0.0 {
+        return Vec::new();
+    }
+
+    [-1.0, 1.0]
+        .into_iter()
+        .filter_map(|sign| {
+            let z = (-qb + sign * discriminant.sqrt()) / (2.0 * qa);
+            let denominator = e + h * z;
+
+            (denominator.abs() >= f32::EPSILON)
+                .then(|| Vec3::new(-(c + f * z) / denominator, -(b + g * z) / denominator, z))
+        })
+        .filter(is_inside)
+        .collect()
+}
+
+fn face_segments(
+    face: &[u8],
+    points: &[CellPoint],
+    center: Vec3,
+    iso_level: f32,
+    crossings: &mut HashMap<EdgeKey, Crossing>,
+    segments: &mut Vec<(EdgeKey, EdgeKey)>,
+) {
+    // (key, is the boundary leaving the inside here)
+    let mut face_crossings = Vec::<(EdgeKey, bool)>::new();
+
+    for i in 0..face.len() {
+        let a = face[i];
+        let b = face[(i + 1) % face.len()];
+
+        let pa = points[a as usize];
+        let pb = points[b as usize];
+
+        let inside_a = pa.value > iso_level;
+        let inside_b = pb.value > iso_level;
+
+        if inside_a == inside_b {
+            continue;
+        }
+
+        let key = (a.min(b), a.max(b));
+
+        crossings.entry(key).or_insert_with(|| {
+            let (inside, outside) = if inside_a { (pa, pb) } else { (pb, pa) };
+
+            Crossing {
+                position: interp_vertex(pa.position, pb.position, pa.value, pb.value, iso_level),
+                outward: outside.position - inside.position,
+            }
+        });
+
+        face_crossings.push((key, inside_a));
+    }
+
+    let normal = face
+        .iter()
+        .map(|key| points[*key as usize].position - center)
+        .sum::<Vec3>();
+
+    // Segments are directed so that, seen from outside of the cell, the inside is on their right.
+    // Every segment is shared with the cell on the other side of the face, which sees it the other
+    // way around, and the loops chained from them wind counterclockwise around the outward normal.
+    let mut push = |a: EdgeKey, b: EdgeKey| {
+        let (pa, pb) = (&crossings[&a], &crossings[&b]);
+
+        if normal
+            .cross(pb.position - pa.position)
+            .dot(pa.outward + pb.outward)
+            < 0.0
+        {
+            segments.push((b, a));
+        } else {
+            segments.push((a, b));
+        }
+    };
+
+    match face_crossings.len() {
+        0 => {}
+        2 => push(face_crossings[0].0, face_crossings[1].0),
+        count => {
+            let values = face
+                .iter()
+                .map(|key| points[*key as usize].value)
+                .collect::<Vec<f32>>();
+
+            // When the saddle of the bilinear interpolant is inside, the inside corners are
+            // connected across the face and the outside corners get cut off instead
+            let center_inside = face_center_value(&values) > iso_level;
+
+            for k in 0..count {
+                let (key, is_exit) = face_crossings[k];
+
+                if !is_exit {
+                    continue;
+                }
+
+                let other = if center_inside {
+                    (k + 1) % count
+                } else {
+                    (k + count - 1) % count
+                };
+
+                push(key, face_crossings[other].0);
+            }
+        }
+    }
+}
+
+// Asymptotic decider for quads, falls back to the mean for other faces
+pub fn face_center_value(values: &[f32]) -> f32 {
+    let mean = values.iter().sum::<f32>() / values.len() as f32;
+
+    if values.len() != 4 {
+        return mean;
+    }
+
+    let denominator = values[0] + values[2] - values[1] - values[3];
+
+    if denominator.abs() < f32::EPSILON {
+        return mean;
+    }
+
+    (values[0] * values[2] - values[1] * values[3]) / denominator
+}
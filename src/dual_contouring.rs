@@ -0,0 +1,461 @@
+#[cfg(feature = "physics")]
+use bevy::prelude::Mesh;
+use bevy::{math::Vec3, prelude::Component, utils::HashMap};
+#[cfg(feature = "physics")]
+use bevy_rapier3d::prelude::{Collider, ComputedColliderShape};
+
+use crate::{
+    lod::ChunkLod,
+    marching_cubes_cpu::VoxelGrid,
+    meshing::{interp_vertex, MeshData, MeshingSettings},
+    sparse::for_each_active_cell,
+    voxel_value::VoxelValue,
+};
+
+// Eigenvalues of the QEF below this fraction of the largest one are treated as zero
+const SVD_TOLERANCE: f32 = 0.1;
+
+#[derive(Clone, Copy, Debug)]
+pub struct HermiteEdge {
+    pub position: Vec3,
+    // Surface normal at the intersection, pointing out of the surface
+    pub normal: Vec3,
+}
+
+// Intersections of the sample edges with the surface, keyed by the lower sample of the edge and
+// the axis it runs along. Attached to an entity it replaces the normals estimated from the density
+// when meshing with dual contouring at full resolution.
+#[derive(Component, Clone, Default, Debug)]
+pub struct HermiteData {
+    pub edges: HashMap<([usize; 3], usize), HermiteEdge>,
+}
+
+impl HermiteData {
+    pub fn from_density<V: VoxelValue>(
+        voxel_grid: &VoxelGrid<V>,
+        iso_level: f32,
+        stride: usize,
+    ) -> Self {
+        let mut edges = HashMap::default();
+
+        for_each_crossing(voxel_grid, iso_level, stride, |sample, axis, next| {
+            let (pa, va) = (voxel_grid.position(sample), get(voxel_grid, sample));
+            let (pb, vb) = (voxel_grid.position(next), get(voxel_grid, next));
+
+            let position = interp_vertex(pa, pb, va, vb, iso_level);
+            let normal = -voxel_grid.gradient(position).normalize_or_zero();
+
+            edges.insert((sample, axis), HermiteEdge { position, normal });
+        });
+
+        Self { edges }
+    }
+
+    // Exact intersections and face normals from the mesh the grid was voxelized from, this keeps
+    // the corners of blocky models that the density alone can't describe
+    #[cfg(feature = "physics")]
+    pub fn from_mesh<V: VoxelValue>(
+        voxel_grid: &VoxelGrid<V>,
+        mesh: &Mesh,
+        iso_level: f32,
+    ) -> Self {
+        let mut hermite_data = Self::from_density(voxel_grid, iso_level, 1);
+
+        let Some(collider) = Collider::from_bevy_mesh(mesh, &ComputedColliderShape::TriMesh) else {
+            return hermite_data;
+        };
+
+        for ((sample, axis), edge) in hermite_data.edges.iter_mut() {
+            let mut next = *sample;
+            next[*axis] += 1;
+
+            let (start, end) = if get(voxel_grid, *sample) > iso_level {
+                (voxel_grid.position(next), voxel_grid.position(*sample))
+            } else {
+                (voxel_grid.position(*sample), voxel_grid.position(next))
+            };
+
+            // Cast from the outside sample towards the inside one
+            let direction = end - start;
+
+            if let Some(hit) = collider.cast_local_ray_and_get_normal(start, direction, 1.0, true) {
+                let mut normal = hit.normal.normalize_or_zero();
+
+                if normal.dot(direction) > 0.0 {
+                    normal = -normal;
+                }
+
+                *edge = HermiteEdge {
+                    position: hit.point,
+                    normal,
+                };
+            }
+        }
+
+        hermite_data
+    }
+}
+
+// Dual contouring: like surface nets one vertex per cell and one quad per crossing edge, but the
+// vertex minimizes the distance to the tangent planes of the hermite data so sharp features survive.
+// Cells whose normals all lie within the sharp feature angle of each other are treated as smooth.
+pub fn dual_contouring<V: VoxelValue>(
+    voxel_grid: &VoxelGrid<V>,
+    settings: &MeshingSettings,
+    lod: &ChunkLod,
+    hermite_data: Option<&HermiteData>,
+) -> MeshData {
+    let iso_level = settings.iso_level;
+
+    let stride = lod.stride();
+    let last = lod.last_samples(voxel_grid.resolution);
+    let cells = lod.cell_counts(voxel_grid.resolution);
+
+    let mut mesh_data = MeshData::default();
+
+    if cells.contains(&0) {
+        return mesh_data;
+    }
+
+    let computed;
+    let hermite_data = match hermite_data {
+        Some(hermite_data) if stride == 1 => hermite_data,
+        _ => {
+            computed = HermiteData::from_density(voxel_grid, iso_level, stride);
+            &computed
+        }
+    };
+
+    let cell_index = |c: [usize; 3]| c[0] + c[1] * cells[0] + c[2] * cells[0] * cells[1];
+
+    let mut cell_vertices = vec![None; cells[0] * cells[1] * cells[2]];
+
+    let min_cos = settings.sharp_feature_angle.cos();
+
+    for_each_active_cell(voxel_grid, stride, last, |base| {
+        let [cx, cy, cz] = base.map(|b| b / stride);
+
+        if cx >= cells[0] || cy >= cells[1] || cz >= cells[2] {
+            return;
+        }
+
+        let mut points = Vec::new();
+        let mut normals = Vec::new();
+
+        for axis in 0..3 {
+            let u_axis = (axis + 1) % 3;
+            let v_axis = (axis + 2) % 3;
+
+            for [du, dv] in [[0, 0], [1, 0], [0, 1], [1, 1]] {
+                let mut offset = [0; 3];
+                offset[u_axis] = du;
+                offset[v_axis] = dv;
+
+                let sample = lod.corner(base, offset, last);
+
+                if let Some(edge) = hermite_data.edges.get(&(sample, axis)) {
+                    points.push(edge.position);
+                    normals.push(edge.normal);
+                }
+            }
+        }
+
+        if points.is_empty() {
+            return;
+        }
+
+        let is_sharp = normals
+            .iter()
+            .enumerate()
+            .any(|(i, a)| normals[i + 1..].iter().any(|b| a.dot(*b) < min_cos));
+
+        let cell_min = voxel_grid.position(base);
+        let cell_max = voxel_grid.position(lod.corner(base, [1, 1, 1], last));
+
+        let mut position = solve_qef(&points, &normals, is_sharp);
+
+        // Keep the vertex inside of its cell, the QEF can place it far away when the
+        // planes are almost parallel
+        if position.cmplt(cell_min).any() || position.cmpgt(cell_max).any() {
+            position = points.iter().sum::<Vec3>() / points.len() as f32;
+        }
+
+        cell_vertices[cell_index([cx, cy, cz])] = Some(position);
+    });
+
+    for_each_crossing(voxel_grid, iso_level, stride, |sample, axis, _| {
+        let u_axis = (axis + 1) % 3;
+        let v_axis = (axis + 2) % 3;
+
+        // Rounded up so that the boundary samples of a shortened last cell count as the far side
+        let cell = sample.map(|s| s.div_ceil(stride));
+
+        if cell[u_axis] == 0
+            || cell[u_axis] == cells[u_axis]
+            || cell[v_axis] == 0
+            || cell[v_axis] == cells[v_axis]
+        {
+            return;
+        }
+
+        let quad = [[1, 1], [0, 1], [0, 0], [1, 0]].map(|[du, dv]| {
+            let mut cell = cell;
+            cell[u_axis] -= du;
+            cell[v_axis] -= dv;
+            cell_vertices[cell_index(cell)]
+        });
+
+        let [Some(a), Some(b), Some(c), Some(d)] = quad else {
+            return;
+        };
+
+        // Flat normals so the sharp features stay visible
+        if get(voxel_grid, sample) > iso_level {
+            mesh_data.push_triangle(a, b, c);
+            mesh_data.push_triangle(a, c, d);
+        } else {
+            mesh_data.push_triangle(d, c, b);
+            mesh_data.push_triangle(d, b, a);
+        }
+    });
+
+    mesh_data
+}
+
+fn get<V: VoxelValue>(voxel_grid: &VoxelGrid<V>, index: [usize; 3]) -> f32 {
+    voxel_grid.get(index[0], index[1], index[2])
+}
+
+fn for_each_crossing<V: VoxelValue>(
+    voxel_grid: &VoxelGrid<V>,
+    iso_level: f32,
+    stride: usize,
+    mut f: impl FnMut([usize; 3], usize, [usize; 3]),
+) {
+    let last = ChunkLod::default().last_samples(voxel_grid.resolution);
+
+    // Every edge is visited from the cell it starts in. Edges starting on the far faces of the grid
+    // have no cell of their own and are visited from the last cell before them.
+    for_each_active_cell(voxel_grid, stride, last, |cell| {
+        for axis in 0..3 {
+            let u_axis = (axis + 1) % 3;
+            let v_axis = (axis + 2) % 3;
+
+            let u_count = 1 + (cell[u_axis] + stride >= last[u_axis]) as usize;
+            let v_count = 1 + (cell[v_axis] + stride >= last[v_axis]) as usize;
+
+            for du in 0..u_count {
+                for dv in 0..v_count {
+                    let mut sample = cell;
+                    sample[u_axis] = (sample[u_axis] + du * stride).min(last[u_axis]);
+                    sample[v_axis] = (sample[v_axis] + dv * stride).min(last[v_axis]);
+
+                    // The last cell along the axis is shorter when the stride doesn't divide it
+                    let mut next = sample;
+                    next[axis] = (next[axis] + stride).min(last[axis]);
+
+                    let inside = get(voxel_grid, sample) > iso_level;
+
+                    if inside != (get(voxel_grid, next) > iso_level) {
+                        f(sample, axis, next);
+                    }
+                }
+            }
+        }
+    });
+}
+
+// Minimizes the squared distance to the planes through the points, relative to their mass point
+// so that truncated directions fall back to it
+fn solve_qef(points: &[Vec3], normals: &[Vec3], is_sharp: bool) -> Vec3 {
+    let mass_point = points.iter().sum::<Vec3>() / points.len() as f32;
+
+    let mut ata = [[0.0f32; 3]; 3];
+    let mut atb = Vec3::ZERO;
+
+    for (point, normal) in points.iter().zip(normals.iter()) {
+        for i in 0..3 {
+            for j in 0..3 {
+                ata[i][j] += normal[i] * normal[j];
+            }
+        }
+
+        atb += *normal * normal.dot(*point - mass_point);
+    }
+
+    let (values, vectors) = symmetric_eigen(ata);
+
+    let max_value = values
+        .iter()
+        .fold(0.0f32, |max, value| max.max(value.abs()));
+
+    if max_value < f32::EPSILON {
+        return mass_point;
+    }
+
+    // Smooth cells only keep the dominant plane, which projects the mass point onto the surface
+    let mut order = [0, 1, 2];
+    order.sort_by(|a, b| values[*b].abs().total_cmp(&values[*a].abs()));
+    let rank = if is_sharp { 3 } else { 1 };
+
+    let mut offset = Vec3::ZERO;
+
+    for i in order.into_iter().take(rank) {
+        if values[i].abs() < SVD_TOLERANCE * max_value {
+            continue;
+        }
+
+        let vector = Vec3::new(vectors[0][i], vectors[1][i], vectors[2][i]);
+        offset += vector * (vector.dot(atb) / values[i]);
+    }
+
+    mass_point + offset
+}
+
+// Jacobi eigenvalue algorithm, returns the eigenvalues and the eigenvectors as columns
+fn symmetric_eigen(matrix: [[f32; 3]; 3]) -> ([f32; 3], [[f32; 3]; 3]) {
+    let mut a = matrix;
+    let mut v = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+
+    for _ in 0..16 {
+        for (p, q) in [(0, 1), (0, 2), (1, 2)] {
+            if a[p][q].abs() < 1e-9 {
+                continue;
+            }
+
+            let theta = (a[q][q] - a[p][p]) / (2.0 * a[p][q]);
+            let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+            let c = 1.0 / (t * t + 1.0).sqrt();
+            let s = t * c;
+
+            for row in a.iter_mut() {
+                let (akp, akq) = (row[p], row[q]);
+                row[p] = c * akp - s * akq;
+                row[q] = s * akp + c * akq;
+            }
+
+            let (row_p, row_q) = (a[p], a[q]);
+            a[p] = std::array::from_fn(|k| c * row_p[k] - s * row_q[k]);
+            a[q] = std::array::from_fn(|k| s * row_p[k] + c * row_q[k]);
+
+            for row in v.iter_mut() {
+                let (vkp, vkq) = (row[p], row[q]);
+                row[p] = c * vkp - s * vkq;
+                row[q] = s * vkp + c * vkq;
+            }
+        }
+    }
+
+    ([a[0][0], a[1][1], a[2][2]], v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{marching_cubes_cpu::Bounds, sparse::SparseGrid};
+
+    const RESOLUTION: usize = 24;
+
+    // Samples one unit apart, the density falls from 1 to 0 over a unit around the surface
+    fn grid(distance: impl Fn(Vec3) -> f32) -> VoxelGrid {
+        let mut values = Vec::new();
+
+        for z in 0..RESOLUTION {
+            for y in 0..RESOLUTION {
+                for x in 0..RESOLUTION {
+                    let position = Vec3::new(x as f32, y as f32, z as f32);
+                    values.push((0.5 - distance(position)).clamp(0.0, 1.0));
+                }
+            }
+        }
+
+        VoxelGrid {
+            resolution: [RESOLUTION; 3],
+            data: SparseGrid::from_dense([RESOLUTION; 3], &values),
+            bounds: Bounds {
+                min: Vec3::ZERO,
+                max: Vec3::splat(RESOLUTION as f32),
+            },
+        }
+    }
+
+    fn mesh(voxel_grid: &VoxelGrid) -> MeshData {
+        dual_contouring(
+            voxel_grid,
+            &MeshingSettings::default(),
+            &ChunkLod::default(),
+            None,
+        )
+    }
+
+    #[test]
+    fn qef_finds_the_corner_of_three_planes() {
+        let corner = Vec3::new(0.3, 0.6, 0.2);
+        let points = [
+            corner + Vec3::new(0.0, -0.4, 0.1),
+            corner + Vec3::new(-0.2, 0.0, -0.3),
+            corner + Vec3::new(-0.1, 0.2, 0.0),
+        ];
+
+        let position = solve_qef(&points, &[Vec3::X, Vec3::Y, Vec3::Z], true);
+        assert!(position.distance(corner) < 1e-4);
+
+        // A smooth cell only moves the mass point onto the dominant plane
+        let points = [Vec3::new(0.0, 0.5, 0.0), Vec3::new(1.0, 0.5, 1.0)];
+        let position = solve_qef(&points, &[Vec3::Y, Vec3::Y], false);
+        assert!(position.distance(Vec3::new(0.5, 0.5, 0.5)) < 1e-4);
+    }
+
+    #[test]
+    fn sphere_vertices_lie_on_the_surface() {
+        let center = Vec3::splat(11.7);
+        let radius = 7.3;
+
+        let mesh_data = mesh(&grid(|p| p.distance(center) - radius));
+        assert!(!mesh_data.indices.is_empty());
+
+        for position in mesh_data.positions.iter() {
+            let distance = Vec3::from(*position).distance(center) - radius;
+            assert!(distance.abs() < 0.15, "{:?} is {} off", position, distance);
+        }
+    }
+
+    // Normals estimated from the density round off the edges, the ones of the mesh keep them
+    #[cfg(feature = "physics")]
+    #[test]
+    fn box_keeps_its_corners() {
+        use bevy::math::primitives::Cuboid;
+
+        let min = Vec3::new(5.3, 6.4, 4.7);
+        let max = Vec3::new(16.6, 15.2, 17.4);
+        let center = (min + max) * 0.5;
+        let half = (max - min) * 0.5;
+
+        let voxel_grid = grid(|p| ((p - center).abs() - half).max_element());
+        let mesh = Mesh::from(Cuboid::from_size(max - min)).translated_by(center);
+        let hermite_data = HermiteData::from_mesh(&voxel_grid, &mesh, 0.5);
+
+        let mesh_data = dual_contouring(
+            &voxel_grid,
+            &MeshingSettings::default(),
+            &ChunkLod::default(),
+            Some(&hermite_data),
+        );
+
+        for corner in 0..8 {
+            let corner = Vec3::new(
+                if corner & 1 == 0 { min.x } else { max.x },
+                if corner & 2 == 0 { min.y } else { max.y },
+                if corner & 4 == 0 { min.z } else { max.z },
+            );
+
+            let nearest = mesh_data
+                .positions
+                .iter()
+                .map(|position| Vec3::from(*position).distance(corner))
+                .fold(f32::MAX, f32::min);
+
+            assert!(nearest < 1e-3, "{:?} is {} away", corner, nearest);
+        }
+    }
+}
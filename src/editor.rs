@@ -0,0 +1,325 @@
+use bevy::{
+    app::{App, Plugin, PluginGroup, PluginGroupBuilder, Update},
+    input::{mouse::MouseButton, ButtonInput},
+    math::Quat,
+    prelude::{
+        Camera, Commands, Entity, EventWriter, GlobalTransform, IntoSystemConfigs, KeyCode, Or,
+        Query, Res, ResMut, Resource, With,
+    },
+    window::{PrimaryWindow, Window},
+};
+
+use crate::{
+    chunk::Chunk,
+    export::{export_chunks_system, ExportMeshes},
+    gltf_export::{export_gltf_system, ExportGltf},
+    history::{start_history_action_system, HistoryAction},
+    marching_cubes_cpu::VoxelGrid,
+    meshing::DirtyChunk,
+    sculpt::{
+        brush_stroke_system, clear_brush_hit_system, BrushHit, BrushMode, BrushShape, BrushStroke,
+        SculptSettings,
+    },
+    stamp::{start_stamp_system, BlendMode, StampSettings},
+    vox::{export_vox_system, ExportVox},
+    voxel_file::{save_voxels_system, SaveVoxels},
+    voxel_value::F16,
+    CameraMarker,
+};
+
+// Keyboard and mouse controls for the editing plugins of `VoxelPlugins`, which have to be added
+// as well. Apps with their own controls drive the same resources and events instead.
+pub struct VoxelEditorPlugins;
+
+impl PluginGroup for VoxelEditorPlugins {
+    fn build(self) -> PluginGroupBuilder {
+        PluginGroupBuilder::start::<Self>()
+            .add(SculptInputPlugin)
+            .add(HistoryInputPlugin)
+            .add(StampInputPlugin)
+            .add(ExportInputPlugin)
+            .add(RemeshInputPlugin)
+    }
+}
+
+// Left mouse sculpts at the cursor, 1 to 4 select add, remove, smooth and flatten, Q toggles the
+// shape and the brackets change the radius
+pub struct SculptInputPlugin;
+
+impl Plugin for SculptInputPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (
+                cursor_brush_system.before(clear_brush_hit_system),
+                (brush_settings_system, brush_button_system).before(brush_stroke_system),
+            ),
+        );
+    }
+}
+
+pub fn cursor_brush_system(
+    mut brush_hit: ResMut<BrushHit>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    cameras: Query<(&Camera, &GlobalTransform), With<CameraMarker>>,
+) {
+    brush_hit.ray = None;
+
+    let (Ok(window), Ok((camera, camera_transform))) = (windows.get_single(), cameras.get_single())
+    else {
+        return;
+    };
+
+    brush_hit.ray = window
+        .cursor_position()
+        .and_then(|cursor| camera.viewport_to_world(camera_transform, cursor));
+}
+
+pub fn brush_settings_system(
+    mut settings: ResMut<SculptSettings>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+) {
+    let modes = [
+        (KeyCode::Digit1, BrushMode::Add),
+        (KeyCode::Digit2, BrushMode::Remove),
+        (KeyCode::Digit3, BrushMode::Smooth),
+        (KeyCode::Digit4, BrushMode::Flatten),
+    ];
+
+    for (key, mode) in modes {
+        if keyboard_input.just_pressed(key) {
+            settings.mode = mode;
+        }
+    }
+
+    if keyboard_input.just_pressed(KeyCode::KeyQ) {
+        settings.shape = match settings.shape {
+            BrushShape::Sphere => BrushShape::Cube,
+            BrushShape::Cube => BrushShape::Sphere,
+        };
+    }
+
+    if keyboard_input.just_pressed(KeyCode::BracketLeft) {
+        settings.radius = (settings.radius * 0.8).max(0.05);
+    }
+
+    if keyboard_input.just_pressed(KeyCode::BracketRight) {
+        settings.radius *= 1.25;
+    }
+}
+
+// A stroke lasts while the mouse button is held
+pub fn brush_button_system(
+    mut stroke: ResMut<BrushStroke>,
+    mouse_buttons: Res<ButtonInput<MouseButton>>,
+) {
+    stroke.active = mouse_buttons.pressed(MouseButton::Left);
+}
+
+#[derive(Clone, Debug)]
+pub struct KeyBinding {
+    // Any of these has to be held, none when empty
+    pub modifiers: Vec<KeyCode>,
+    pub key: KeyCode,
+}
+
+impl KeyBinding {
+    pub fn just_pressed(&self, keyboard_input: &ButtonInput<KeyCode>) -> bool {
+        keyboard_input.just_pressed(self.key)
+            && (self.modifiers.is_empty() || keyboard_input.any_pressed(self.modifiers.clone()))
+    }
+}
+
+#[derive(Resource, Clone, Debug)]
+pub struct HistoryBindings {
+    pub undo: Vec<KeyBinding>,
+    pub redo: Vec<KeyBinding>,
+}
+
+impl Default for HistoryBindings {
+    fn default() -> Self {
+        let control = vec![KeyCode::ControlLeft, KeyCode::ControlRight];
+
+        Self {
+            undo: vec![KeyBinding {
+                modifiers: control.clone(),
+                key: KeyCode::KeyZ,
+            }],
+            redo: vec![KeyBinding {
+                modifiers: control,
+                key: KeyCode::KeyY,
+            }],
+        }
+    }
+}
+
+// Undo and redo on `HistoryBindings`, Ctrl+Z and Ctrl+Y by default
+pub struct HistoryInputPlugin;
+
+impl Plugin for HistoryInputPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<HistoryBindings>().add_systems(
+            Update,
+            history_input_system.before(start_history_action_system),
+        );
+    }
+}
+
+pub fn history_input_system(
+    bindings: Res<HistoryBindings>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut actions: EventWriter<HistoryAction>,
+) {
+    if bindings
+        .undo
+        .iter()
+        .any(|binding| binding.just_pressed(&keyboard_input))
+    {
+        actions.send(HistoryAction::Undo);
+    }
+
+    if bindings
+        .redo
+        .iter()
+        .any(|binding| binding.just_pressed(&keyboard_input))
+    {
+        actions.send(HistoryAction::Redo);
+    }
+}
+
+// G picks the corners of the selection at the cursor, C copies it, R and T rotate the prefab by
+// 90 and 15 degrees around the vertical axis, X cycles the blend mode and V stamps at the cursor
+pub struct StampInputPlugin;
+
+impl Plugin for StampInputPlugin {
+    fn build(&self, app: &mut App) {
+        // The cursor hit is picked by the sculpting systems
+        app.add_systems(
+            Update,
+            stamp_input_system
+                .after(brush_stroke_system)
+                .before(start_stamp_system),
+        );
+    }
+}
+
+pub fn stamp_input_system(
+    mut settings: ResMut<StampSettings>,
+    brush_hit: Res<BrushHit>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+) {
+    let cursor = brush_hit.hit.map(|hit| hit.point);
+
+    if keyboard_input.just_pressed(KeyCode::KeyG) {
+        if let Some(point) = cursor {
+            settings.selection = match settings.selection {
+                [Some(first), None] => [Some(first), Some(point)],
+                _ => [Some(point), None],
+            };
+        }
+    }
+
+    if keyboard_input.just_pressed(KeyCode::KeyR) {
+        settings.rotation = Quat::from_rotation_y(std::f32::consts::FRAC_PI_2) * settings.rotation;
+    }
+
+    if keyboard_input.just_pressed(KeyCode::KeyT) {
+        settings.rotation = Quat::from_rotation_y(15f32.to_radians()) * settings.rotation;
+    }
+
+    if keyboard_input.just_pressed(KeyCode::KeyX) {
+        settings.blend_mode = match settings.blend_mode {
+            BlendMode::Replace => BlendMode::Union,
+            BlendMode::Union => BlendMode::Subtract,
+            BlendMode::Subtract => BlendMode::Replace,
+        };
+    }
+
+    if keyboard_input.just_pressed(KeyCode::KeyC) {
+        settings.copy_selection();
+    }
+
+    if keyboard_input.just_pressed(KeyCode::KeyV) {
+        if let Some(point) = cursor {
+            settings.stamp_at(point);
+        }
+    }
+}
+
+// F5 exports the chunk meshes, F6 the glTF scene, F7 the .vox scene and F9 saves voxel files
+pub struct ExportInputPlugin;
+
+impl Plugin for ExportInputPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            export_input_system
+                .before(export_chunks_system)
+                .before(export_gltf_system)
+                .before(export_vox_system)
+                .before(save_voxels_system),
+        );
+    }
+}
+
+pub fn export_input_system(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut export_meshes: EventWriter<ExportMeshes>,
+    mut export_gltf: EventWriter<ExportGltf>,
+    mut export_vox: EventWriter<ExportVox>,
+    mut save_voxels: EventWriter<SaveVoxels>,
+) {
+    if keyboard_input.just_pressed(KeyCode::F5) {
+        export_meshes.send(ExportMeshes);
+    }
+
+    if keyboard_input.just_pressed(KeyCode::F6) {
+        export_gltf.send(ExportGltf);
+    }
+
+    if keyboard_input.just_pressed(KeyCode::F7) {
+        export_vox.send(ExportVox);
+    }
+
+    if keyboard_input.just_pressed(KeyCode::F9) {
+        save_voxels.send(SaveVoxels);
+    }
+}
+
+type MeshedGridQuery<'w, 's> = Query<
+    'w,
+    's,
+    Entity,
+    (
+        With<Chunk>,
+        Or<(
+            With<VoxelGrid>,
+            With<VoxelGrid<u8>>,
+            With<VoxelGrid<i8>>,
+            With<VoxelGrid<F16>>,
+        )>,
+    ),
+>;
+
+// Enter remeshes every grid on the CPU
+pub struct RemeshInputPlugin;
+
+impl Plugin for RemeshInputPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, remesh_input_system);
+    }
+}
+
+pub fn remesh_input_system(
+    mut commands: Commands,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    query: MeshedGridQuery,
+) {
+    if !keyboard_input.just_pressed(KeyCode::Enter) {
+        return;
+    }
+
+    for entity in query.iter() {
+        commands.entity(entity).insert(DirtyChunk);
+    }
+}
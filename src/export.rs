@@ -0,0 +1,257 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use bevy::{
+    app::{App, Plugin, Update},
+    asset::{Assets, Handle},
+    log::{error, info},
+    math::Vec3,
+    prelude::{Event, EventReader, GlobalTransform, Mesh, Query, Res, Resource},
+};
+
+use crate::{chunk::Chunk, meshing::MeshData};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MeshFormat {
+    Obj,
+    PlyAscii,
+    PlyBinary,
+    StlBinary,
+}
+
+impl MeshFormat {
+    // PLY files are written in binary, ASCII has to be requested explicitly by name
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+
+        Self::from_name(&extension).filter(|format| *format != MeshFormat::PlyAscii)
+    }
+
+    // Names as they are given to the command line tool
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "obj" => Some(MeshFormat::Obj),
+            "ply" => Some(MeshFormat::PlyBinary),
+            "ply-ascii" => Some(MeshFormat::PlyAscii),
+            "stl" => Some(MeshFormat::StlBinary),
+            _ => None,
+        }
+    }
+}
+
+// Writes the meshes of every chunk to `MeshExportSettings::path`
+#[derive(Event, Clone, Copy, Debug, Default)]
+pub struct ExportMeshes;
+
+pub struct MeshExportPlugin;
+
+impl Plugin for MeshExportPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<MeshExportSettings>()
+            .add_event::<ExportMeshes>()
+            .add_systems(Update, export_chunks_system);
+    }
+}
+
+#[derive(Resource, Clone, Debug)]
+pub struct MeshExportSettings {
+    pub path: PathBuf,
+    pub format: MeshFormat,
+    // Writes every chunk in world space into one file instead of one file per chunk
+    pub merge_chunks: bool,
+}
+
+impl Default for MeshExportSettings {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::from("export.obj"),
+            format: MeshFormat::Obj,
+            merge_chunks: true,
+        }
+    }
+}
+
+pub fn export_chunks_system(
+    settings: Res<MeshExportSettings>,
+    query: Query<(&Handle<Mesh>, &GlobalTransform, &Chunk)>,
+    meshes: Res<Assets<Mesh>>,
+    mut requests: EventReader<ExportMeshes>,
+) {
+    if requests.read().count() == 0 {
+        return;
+    }
+
+    let chunks = query
+        .iter()
+        .filter_map(|(mesh_handle, transform, chunk)| {
+            let mesh_data = MeshData::from_mesh(meshes.get(mesh_handle)?)?;
+            Some((mesh_data, transform, chunk.position))
+        })
+        .collect::<Vec<_>>();
+
+    let result = if settings.merge_chunks {
+        let mut merged = MeshData::default();
+
+        for (mesh_data, transform, _) in chunks.iter() {
+            merged.extend(&mesh_data.transformed(transform));
+        }
+
+        export_mesh(&merged, &settings.path, settings.format)
+    } else {
+        chunks.iter().try_for_each(|(mesh_data, _, position)| {
+            let path = chunk_path(&settings.path, position.to_array());
+            export_mesh(mesh_data, &path, settings.format)
+        })
+    };
+
+    match result {
+        Ok(()) => info!("Exported {} chunks to {:?}", chunks.len(), settings.path),
+        Err(err) => error!("Failed to export chunks to {:?}: {}", settings.path, err),
+    }
+}
+
+fn chunk_path(path: &Path, position: [i32; 3]) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or("chunk");
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or("obj");
+
+    path.with_file_name(format!(
+        "{}_{}_{}_{}.{}",
+        stem, position[0], position[1], position[2], extension
+    ))
+}
+
+pub fn export_mesh(mesh_data: &MeshData, path: &Path, format: MeshFormat) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write_mesh(mesh_data, format, &mut writer)?;
+    writer.flush()
+}
+
+pub fn write_mesh(
+    mesh_data: &MeshData,
+    format: MeshFormat,
+    writer: &mut impl Write,
+) -> io::Result<()> {
+    match format {
+        MeshFormat::Obj => write_obj(mesh_data, writer),
+        MeshFormat::PlyAscii => write_ply_ascii(mesh_data, writer),
+        MeshFormat::PlyBinary => write_ply_binary(mesh_data, writer),
+        MeshFormat::StlBinary => write_stl_binary(mesh_data, writer),
+    }
+}
+
+pub fn write_obj(mesh_data: &MeshData, writer: &mut impl Write) -> io::Result<()> {
+    writeln!(writer, "# marching-cubes-gpu")?;
+
+    for [x, y, z] in mesh_data.positions.iter() {
+        writeln!(writer, "v {} {} {}", x, y, z)?;
+    }
+
+    for [x, y, z] in mesh_data.normals.iter() {
+        writeln!(writer, "vn {} {} {}", x, y, z)?;
+    }
+
+    for [u, v] in mesh_data.uvs.iter() {
+        writeln!(writer, "vt {} {}", u, v)?;
+    }
+
+    // OBJ indices start at one
+    for triangle in mesh_data.indices.chunks_exact(3) {
+        let [a, b, c] = [triangle[0] + 1, triangle[1] + 1, triangle[2] + 1];
+        writeln!(writer, "f {a}/{a}/{a} {b}/{b}/{b} {c}/{c}/{c}")?;
+    }
+
+    Ok(())
+}
+
+fn write_ply_header(mesh_data: &MeshData, format: &str, writer: &mut impl Write) -> io::Result<()> {
+    writeln!(writer, "ply")?;
+    writeln!(writer, "format {} 1.0", format)?;
+    writeln!(writer, "element vertex {}", mesh_data.positions.len())?;
+    writeln!(writer, "property float x")?;
+    writeln!(writer, "property float y")?;
+    writeln!(writer, "property float z")?;
+    writeln!(writer, "property float nx")?;
+    writeln!(writer, "property float ny")?;
+    writeln!(writer, "property float nz")?;
+    writeln!(writer, "property float s")?;
+    writeln!(writer, "property float t")?;
+    writeln!(writer, "element face {}", mesh_data.indices.len() / 3)?;
+    writeln!(writer, "property list uchar uint vertex_indices")?;
+    writeln!(writer, "end_header")
+}
+
+pub fn write_ply_ascii(mesh_data: &MeshData, writer: &mut impl Write) -> io::Result<()> {
+    write_ply_header(mesh_data, "ascii", writer)?;
+
+    for i in 0..mesh_data.positions.len() {
+        let [x, y, z] = mesh_data.positions[i];
+        let [nx, ny, nz] = mesh_data.normals.get(i).copied().unwrap_or_default();
+        let [s, t] = mesh_data.uvs.get(i).copied().unwrap_or_default();
+
+        writeln!(writer, "{x} {y} {z} {nx} {ny} {nz} {s} {t}")?;
+    }
+
+    for triangle in mesh_data.indices.chunks_exact(3) {
+        writeln!(writer, "3 {} {} {}", triangle[0], triangle[1], triangle[2])?;
+    }
+
+    Ok(())
+}
+
+pub fn write_ply_binary(mesh_data: &MeshData, writer: &mut impl Write) -> io::Result<()> {
+    write_ply_header(mesh_data, "binary_little_endian", writer)?;
+
+    for i in 0..mesh_data.positions.len() {
+        let normal = mesh_data.normals.get(i).copied().unwrap_or_default();
+        let uv = mesh_data.uvs.get(i).copied().unwrap_or_default();
+
+        for value in mesh_data.positions[i].iter().chain(&normal).chain(&uv) {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+    }
+
+    for triangle in mesh_data.indices.chunks_exact(3) {
+        writer.write_all(&[3u8])?;
+
+        for index in triangle {
+            writer.write_all(&index.to_le_bytes())?;
+        }
+    }
+
+    Ok(())
+}
+
+pub fn write_stl_binary(mesh_data: &MeshData, writer: &mut impl Write) -> io::Result<()> {
+    let mut header = [0u8; 80];
+    let name = b"marching-cubes-gpu";
+    header[..name.len()].copy_from_slice(name);
+
+    writer.write_all(&header)?;
+    writer.write_all(&((mesh_data.indices.len() / 3) as u32).to_le_bytes())?;
+
+    for triangle in mesh_data.indices.chunks_exact(3) {
+        let [v0, v1, v2] = [0, 1, 2].map(|i| Vec3::from(mesh_data.positions[triangle[i] as usize]));
+
+        // STL stores one normal per facet
+        let normal = (v1 - v0).cross(v2 - v0).normalize_or_zero();
+
+        for vector in [normal, v0, v1, v2] {
+            for value in vector.to_array() {
+                writer.write_all(&value.to_le_bytes())?;
+            }
+        }
+
+        writer.write_all(&0u16.to_le_bytes())?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,406 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use bevy::{
+    app::{App, Plugin, Update},
+    asset::{Assets, Handle},
+    log::{error, info},
+    math::IVec3,
+    prelude::{Event, EventReader, GlobalTransform, Mesh, Query, Res, Resource},
+};
+
+use crate::{chunk::Chunk, meshing::MeshData};
+
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+const FLOAT: u32 = 5126;
+const UNSIGNED_INT: u32 = 5125;
+
+// Writes every chunk to `GltfExportSettings::path`
+#[derive(Event, Clone, Copy, Debug, Default)]
+pub struct ExportGltf;
+
+pub struct GltfExportPlugin;
+
+impl Plugin for GltfExportPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<GltfExportSettings>()
+            .add_event::<ExportGltf>()
+            .add_systems(Update, export_gltf_system);
+    }
+}
+
+#[derive(Resource, Clone, Debug)]
+pub struct GltfExportSettings {
+    // A .glb path writes a single binary file, anything else writes .gltf json next to a .bin
+    pub path: PathBuf,
+    // Base colour of the glTF material for each material ID, missing entries are grey
+    pub palette: Vec<[f32; 4]>,
+}
+
+impl Default for GltfExportSettings {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::from("world.glb"),
+            palette: Vec::new(),
+        }
+    }
+}
+
+pub struct GltfChunk<'a> {
+    pub position: IVec3,
+    pub transform: GlobalTransform,
+    pub mesh_data: &'a MeshData,
+}
+
+pub fn export_gltf_system(
+    settings: Res<GltfExportSettings>,
+    query: Query<(&Handle<Mesh>, &GlobalTransform, &Chunk)>,
+    meshes: Res<Assets<Mesh>>,
+    mut requests: EventReader<ExportGltf>,
+) {
+    if requests.read().count() == 0 {
+        return;
+    }
+
+    let mesh_data = query
+        .iter()
+        .filter_map(|(mesh_handle, transform, chunk)| {
+            let mesh_data = MeshData::from_mesh(meshes.get(mesh_handle)?)?;
+            Some((chunk.position, *transform, mesh_data))
+        })
+        .collect::<Vec<_>>();
+
+    let chunks = mesh_data
+        .iter()
+        .map(|(position, transform, mesh_data)| GltfChunk {
+            position: *position,
+            transform: *transform,
+            mesh_data,
+        })
+        .collect::<Vec<_>>();
+
+    match export_gltf(&chunks, &settings.palette, &settings.path) {
+        Ok(()) => info!("Exported {} chunks to {:?}", chunks.len(), settings.path),
+        Err(err) => error!("Failed to export glTF to {:?}: {}", settings.path, err),
+    }
+}
+
+pub fn export_gltf(chunks: &[GltfChunk], palette: &[[f32; 4]], path: &Path) -> io::Result<()> {
+    // glTF has no valid way to describe an empty buffer
+    if chunks
+        .iter()
+        .all(|chunk| chunk.mesh_data.indices.is_empty())
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "no chunk has any triangles",
+        ));
+    }
+
+    let is_binary = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("glb"));
+
+    if is_binary {
+        let (json, buffer) = build_gltf(chunks, palette, None);
+
+        let mut writer = BufWriter::new(File::create(path)?);
+        write_glb(&json, &buffer, &mut writer)?;
+        writer.flush()
+    } else {
+        let bin_path = path.with_extension("bin");
+        let bin_name = bin_path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or("world.bin")
+            .to_string();
+
+        let (json, buffer) = build_gltf(chunks, palette, Some(&bin_name));
+
+        std::fs::write(bin_path, buffer)?;
+        std::fs::write(path, json)
+    }
+}
+
+fn write_glb(json: &str, buffer: &[u8], writer: &mut impl Write) -> io::Result<()> {
+    // Chunks have to be 4 byte aligned, json is padded with spaces and the binary with zeros
+    let mut json = json.as_bytes().to_vec();
+    json.resize(json.len().next_multiple_of(4), b' ');
+
+    let mut buffer = buffer.to_vec();
+    buffer.resize(buffer.len().next_multiple_of(4), 0);
+
+    let length = 12 + 8 + json.len() + 8 + buffer.len();
+
+    writer.write_all(b"glTF")?;
+    writer.write_all(&2u32.to_le_bytes())?;
+    writer.write_all(&(length as u32).to_le_bytes())?;
+
+    writer.write_all(&(json.len() as u32).to_le_bytes())?;
+    writer.write_all(b"JSON")?;
+    writer.write_all(&json)?;
+
+    writer.write_all(&(buffer.len() as u32).to_le_bytes())?;
+    writer.write_all(b"BIN\0")?;
+    writer.write_all(&buffer)
+}
+
+#[derive(Default)]
+struct GltfBuilder {
+    buffer: Vec<u8>,
+    buffer_views: Vec<String>,
+    accessors: Vec<String>,
+}
+
+impl GltfBuilder {
+    fn push_view(&mut self, bytes: &[u8], target: u32) -> usize {
+        let offset = self.buffer.len();
+        self.buffer.extend_from_slice(bytes);
+
+        self.buffer_views.push(format!(
+            r#"{{"buffer":0,"byteOffset":{},"byteLength":{},"target":{}}}"#,
+            offset,
+            bytes.len(),
+            target
+        ));
+
+        self.buffer_views.len() - 1
+    }
+
+    fn push_floats<const N: usize>(&mut self, values: &[[f32; N]], with_bounds: bool) -> usize {
+        let bytes = values
+            .iter()
+            .flatten()
+            .flat_map(|value| value.to_le_bytes())
+            .collect::<Vec<u8>>();
+
+        let view = self.push_view(&bytes, ARRAY_BUFFER);
+
+        // POSITION accessors are required to have bounds
+        let bounds = if with_bounds {
+            let mut min = [f32::MAX; N];
+            let mut max = [f32::MIN; N];
+
+            for value in values.iter() {
+                for i in 0..N {
+                    min[i] = min[i].min(value[i]);
+                    max[i] = max[i].max(value[i]);
+                }
+            }
+
+            format!(
+                r#","min":{},"max":{}"#,
+                json_floats(&min),
+                json_floats(&max)
+            )
+        } else {
+            String::new()
+        };
+
+        let kind = match N {
+            2 => "VEC2",
+            3 => "VEC3",
+            _ => "VEC4",
+        };
+
+        self.accessors.push(format!(
+            r#"{{"bufferView":{},"componentType":{},"count":{},"type":"{}"{}}}"#,
+            view,
+            FLOAT,
+            values.len(),
+            kind,
+            bounds
+        ));
+
+        self.accessors.len() - 1
+    }
+
+    fn push_indices(&mut self, indices: &[u32]) -> usize {
+        let bytes = indices
+            .iter()
+            .flat_map(|index| index.to_le_bytes())
+            .collect::<Vec<u8>>();
+
+        let view = self.push_view(&bytes, ELEMENT_ARRAY_BUFFER);
+
+        self.accessors.push(format!(
+            r#"{{"bufferView":{},"componentType":{},"count":{},"type":"SCALAR"}}"#,
+            view,
+            UNSIGNED_INT,
+            indices.len()
+        ));
+
+        self.accessors.len() - 1
+    }
+}
+
+// Every chunk becomes a node with its transform and a mesh with one primitive per material ID
+fn build_gltf(
+    chunks: &[GltfChunk],
+    palette: &[[f32; 4]],
+    bin_uri: Option<&str>,
+) -> (String, Vec<u8>) {
+    let mut builder = GltfBuilder::default();
+
+    let mut material_indices = BTreeMap::<u32, usize>::new();
+    let mut meshes = Vec::new();
+    let mut nodes = Vec::new();
+
+    for chunk in chunks.iter() {
+        let mesh_data = chunk.mesh_data;
+
+        if mesh_data.indices.is_empty() {
+            continue;
+        }
+
+        let position = builder.push_floats(&mesh_data.positions, true);
+        let normal = builder.push_floats(&mesh_data.normals, false);
+        let uv = builder.push_floats(&mesh_data.uvs, false);
+
+        // Triangles take the material of their first vertex
+        let mut triangles_by_material = BTreeMap::<u32, Vec<u32>>::new();
+
+        for triangle in mesh_data.indices.chunks_exact(3) {
+            triangles_by_material
+                .entry(mesh_data.material_id(triangle[0]))
+                .or_default()
+                .extend_from_slice(triangle);
+        }
+
+        let primitives = triangles_by_material
+            .iter()
+            .map(|(material_id, indices)| {
+                let next_material = material_indices.len();
+                let material = *material_indices.entry(*material_id).or_insert(next_material);
+
+                format!(
+                    r#"{{"attributes":{{"POSITION":{},"NORMAL":{},"TEXCOORD_0":{}}},"indices":{},"material":{}}}"#,
+                    position,
+                    normal,
+                    uv,
+                    builder.push_indices(indices),
+                    material
+                )
+            })
+            .collect::<Vec<String>>();
+
+        meshes.push(format!(r#"{{"primitives":[{}]}}"#, primitives.join(",")));
+
+        let (scale, rotation, translation) = chunk.transform.to_scale_rotation_translation();
+
+        nodes.push(format!(
+            r#"{{"name":"chunk_{}_{}_{}","mesh":{},"translation":{},"rotation":{},"scale":{}}}"#,
+            chunk.position.x,
+            chunk.position.y,
+            chunk.position.z,
+            meshes.len() - 1,
+            json_floats(&translation.to_array()),
+            json_floats(&rotation.to_array()),
+            json_floats(&scale.to_array())
+        ));
+    }
+
+    let mut materials = vec![String::new(); material_indices.len()];
+
+    for (material_id, index) in material_indices.iter() {
+        let color = palette
+            .get(*material_id as usize)
+            .copied()
+            .unwrap_or([0.5, 0.5, 0.5, 1.0]);
+
+        materials[*index] = format!(
+            r#"{{"name":"material_{}","pbrMetallicRoughness":{{"baseColorFactor":{},"metallicFactor":0.0,"roughnessFactor":1.0}}}}"#,
+            material_id,
+            json_floats(&color)
+        );
+    }
+
+    let uri = bin_uri
+        .map(|uri| format!(r#","uri":"{}""#, encode_uri(uri)))
+        .unwrap_or_default();
+
+    let scene_nodes = (0..nodes.len())
+        .map(|i| i.to_string())
+        .collect::<Vec<String>>()
+        .join(",");
+
+    let json = format!(
+        r#"{{"asset":{{"version":"2.0","generator":"marching-cubes-gpu"}},"scene":0,"scenes":[{{"nodes":[{}]}}],"nodes":[{}],"meshes":[{}],"materials":[{}],"accessors":[{}],"bufferViews":[{}],"buffers":[{{"byteLength":{}{}}}]}}"#,
+        scene_nodes,
+        nodes.join(","),
+        meshes.join(","),
+        materials.join(","),
+        builder.accessors.join(","),
+        builder.buffer_views.join(","),
+        builder.buffer.len(),
+        uri
+    );
+
+    (json, builder.buffer)
+}
+
+// Percent-encodes everything but the unreserved characters, which also leaves nothing to escape
+// in json
+fn encode_uri(uri: &str) -> String {
+    uri.bytes()
+        .map(|byte| {
+            if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+                (byte as char).to_string()
+            } else {
+                format!("%{:02X}", byte)
+            }
+        })
+        .collect()
+}
+
+fn json_floats(values: &[f32]) -> String {
+    let values = values
+        .iter()
+        .map(|value| {
+            if value.is_finite() {
+                format!("{:?}", value)
+            } else {
+                "0.0".to_string()
+            }
+        })
+        .collect::<Vec<String>>();
+
+    format!("[{}]", values.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_chunks_are_rejected() {
+        let mesh_data = MeshData::default();
+        let chunk = GltfChunk {
+            position: IVec3::ZERO,
+            transform: GlobalTransform::IDENTITY,
+            mesh_data: &mesh_data,
+        };
+
+        let path = std::env::temp_dir().join("mcgpu_empty_test.glb");
+
+        assert!(export_gltf(&[], &[], &path).is_err());
+        assert!(export_gltf(&[chunk], &[], &path).is_err());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn buffer_uri_is_encoded() {
+        assert_eq!(encode_uri("world.bin"), "world.bin");
+        assert_eq!(
+            encode_uri(r#"my "world" #1.bin"#),
+            "my%20%22world%22%20%231.bin"
+        );
+        assert_eq!(encode_uri("wörld.bin"), "w%C3%B6rld.bin");
+    }
+}
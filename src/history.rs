@@ -0,0 +1,363 @@
+use std::collections::VecDeque;
+
+use bevy::{
+    app::{App, Plugin, Update},
+    log::debug,
+    prelude::{
+        Commands, Entity, Event, EventReader, IntoSystemConfigs, Query, Res, ResMut, Resource,
+    },
+    utils::HashMap,
+};
+
+use crate::{
+    dual_contouring::HermiteData,
+    marching_cubes_cpu::VoxelGrid,
+    meshing::DirtyChunk,
+    voxel_value::{VoxelValue, F16},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VoxelChange {
+    pub index: [usize; 3],
+    pub before: f32,
+    pub after: f32,
+}
+
+// The samples one operation changed in one grid
+#[derive(Clone, Debug)]
+pub struct VoxelDiff {
+    pub entity: Entity,
+    pub changes: Vec<VoxelChange>,
+}
+
+#[derive(Clone, Debug)]
+pub struct EditOperation {
+    pub label: String,
+    pub diffs: Vec<VoxelDiff>,
+}
+
+// First value before and last value after of every sample an operation changed so far
+type PendingChanges = HashMap<(Entity, [usize; 3]), (f32, f32)>;
+
+// Edits are recorded sample by sample into the current operation, which keeps the first value
+// before and the last value after every sample so a whole brush stroke undoes in one step.
+// Only `VoxelGrid` entities are covered, chunks are edited through `Chunk::voxels` directly.
+#[derive(Resource, Debug)]
+pub struct EditHistory {
+    pub max_operations: usize,
+    undo_stack: VecDeque<EditOperation>,
+    redo_stack: Vec<EditOperation>,
+    current: Option<(String, PendingChanges)>,
+    // Operation being undone or redone this frame, and whether it is an undo
+    applying: Option<(EditOperation, bool)>,
+}
+
+impl Default for EditHistory {
+    fn default() -> Self {
+        Self {
+            max_operations: 100,
+            undo_stack: VecDeque::new(),
+            redo_stack: Vec::new(),
+            current: None,
+            applying: None,
+        }
+    }
+}
+
+impl EditHistory {
+    pub fn begin(&mut self, label: &str) {
+        self.commit();
+        self.current = Some((label.to_string(), HashMap::default()));
+    }
+
+    // Starts an unnamed operation when none was begun
+    pub fn record(&mut self, entity: Entity, change: VoxelChange) {
+        let (_, changes) = self
+            .current
+            .get_or_insert_with(|| ("edit".to_string(), HashMap::default()));
+
+        changes
+            .entry((entity, change.index))
+            .and_modify(|(_, after)| *after = change.after)
+            .or_insert((change.before, change.after));
+    }
+
+    pub fn record_all(&mut self, entity: Entity, changes: impl IntoIterator<Item = VoxelChange>) {
+        for change in changes {
+            self.record(entity, change);
+        }
+    }
+
+    // Ends the current operation, new edits clear what could be redone
+    pub fn commit(&mut self) {
+        let Some((label, changes)) = self.current.take() else {
+            return;
+        };
+
+        let mut diffs = HashMap::<Entity, Vec<VoxelChange>>::default();
+
+        for ((entity, index), (before, after)) in changes {
+            if before != after {
+                diffs.entry(entity).or_default().push(VoxelChange {
+                    index,
+                    before,
+                    after,
+                });
+            }
+        }
+
+        if diffs.is_empty() {
+            return;
+        }
+
+        self.push_undo(EditOperation {
+            label,
+            diffs: diffs
+                .into_iter()
+                .map(|(entity, changes)| VoxelDiff { entity, changes })
+                .collect(),
+        });
+
+        self.redo_stack.clear();
+    }
+
+    // The oldest operations are dropped past `max_operations`
+    fn push_undo(&mut self, operation: EditOperation) {
+        self.undo_stack.push_back(operation);
+
+        while self.undo_stack.len() > self.max_operations {
+            self.undo_stack.pop_front();
+        }
+    }
+
+    // Sets the samples from `min` to `max` inclusive as one operation, the caller marks the
+    // entity as dirty and removes its `HermiteData` when this returns true
+    pub fn fill<V: VoxelValue>(
+        &mut self,
+        entity: Entity,
+        voxel_grid: &mut VoxelGrid<V>,
+        min: [usize; 3],
+        max: [usize; 3],
+        value: f32,
+    ) -> bool {
+        if voxel_grid.resolution.contains(&0) {
+            return false;
+        }
+
+        self.begin("fill");
+
+        let max = [0, 1, 2].map(|i| max[i].min(voxel_grid.resolution[i].saturating_sub(1)));
+        let mut changed = false;
+
+        for z in min[2]..=max[2] {
+            for y in min[1]..=max[1] {
+                for x in min[0]..=max[0] {
+                    let before = voxel_grid.get(x, y, z);
+                    voxel_grid.set(x, y, z, value);
+
+                    // Quantized grids store the value they can represent
+                    let after = voxel_grid.get(x, y, z);
+
+                    if before != after {
+                        self.record(
+                            entity,
+                            VoxelChange {
+                                index: [x, y, z],
+                                before,
+                                after,
+                            },
+                        );
+                        changed = true;
+                    }
+                }
+            }
+        }
+
+        self.commit();
+
+        changed
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo_stack.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo_stack.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.undo_stack.clear();
+        self.redo_stack.clear();
+        self.current = None;
+    }
+}
+
+#[derive(Event, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HistoryAction {
+    Undo,
+    Redo,
+}
+
+// Undoes or redoes the last operation on each `HistoryAction` event
+pub struct HistoryPlugin;
+
+impl Plugin for HistoryPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<EditHistory>()
+            .add_event::<HistoryAction>()
+            .add_systems(
+                Update,
+                (
+                    start_history_action_system,
+                    (
+                        apply_history_system::<f32>,
+                        apply_history_system::<u8>,
+                        apply_history_system::<i8>,
+                        apply_history_system::<F16>,
+                    ),
+                    finish_history_action_system,
+                )
+                    .chain(),
+            );
+    }
+}
+
+// Only one action is applied per frame, the grids of every value type apply it in between
+pub fn start_history_action_system(
+    mut history: ResMut<EditHistory>,
+    mut actions: EventReader<HistoryAction>,
+) {
+    let Some(action) = actions.read().last().copied() else {
+        return;
+    };
+
+    history.commit();
+
+    let operation = match action {
+        HistoryAction::Undo => history.undo_stack.pop_back(),
+        HistoryAction::Redo => history.redo_stack.pop(),
+    };
+
+    if let Some(operation) = operation {
+        debug!("{:?} {}", action, operation.label);
+        history.applying = Some((operation, action == HistoryAction::Undo));
+    }
+}
+
+pub fn apply_history_system<V: VoxelValue>(
+    mut commands: Commands,
+    history: Res<EditHistory>,
+    mut query: Query<&mut VoxelGrid<V>>,
+) {
+    let Some((operation, is_undo)) = &history.applying else {
+        return;
+    };
+
+    for diff in operation.diffs.iter() {
+        let Ok(mut voxel_grid) = query.get_mut(diff.entity) else {
+            continue;
+        };
+
+        for change in diff.changes.iter() {
+            let [x, y, z] = change.index;
+            let value = if *is_undo {
+                change.before
+            } else {
+                change.after
+            };
+
+            voxel_grid.set(x, y, z, value);
+        }
+
+        commands
+            .entity(diff.entity)
+            .insert(DirtyChunk)
+            .remove::<HermiteData>();
+    }
+}
+
+pub fn finish_history_action_system(mut history: ResMut<EditHistory>) {
+    let Some((operation, is_undo)) = history.applying.take() else {
+        return;
+    };
+
+    if is_undo {
+        history.redo_stack.push(operation);
+    } else {
+        history.push_undo(operation);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{
+        ecs::system::RunSystemOnce,
+        math::Vec3,
+        prelude::{Events, World},
+    };
+
+    use super::*;
+    use crate::{marching_cubes_cpu::Bounds, sparse::SparseGrid};
+
+    fn grid() -> VoxelGrid {
+        VoxelGrid {
+            resolution: [4; 3],
+            data: SparseGrid::new([4; 3], 0.0),
+            bounds: Bounds {
+                min: Vec3::ZERO,
+                max: Vec3::ONE,
+            },
+        }
+    }
+
+    #[test]
+    fn fill_is_undone_in_one_step() {
+        let mut world = World::new();
+        world.init_resource::<Events<HistoryAction>>();
+
+        let entity = world.spawn(grid()).id();
+
+        let mut history = EditHistory::default();
+        let mut voxel_grid = world.get_mut::<VoxelGrid>(entity).unwrap();
+        assert!(history.fill(entity, &mut voxel_grid, [1; 3], [2; 3], 1.0));
+        assert_eq!(voxel_grid.get(2, 2, 2), 1.0);
+
+        world.insert_resource(history);
+        world.send_event(HistoryAction::Undo);
+        world.run_system_once(start_history_action_system);
+        world.run_system_once(apply_history_system::<f32>);
+        world.run_system_once(finish_history_action_system);
+
+        let voxel_grid = world.get::<VoxelGrid>(entity).unwrap();
+        assert_eq!(voxel_grid.get(2, 2, 2), 0.0);
+        assert!(world.resource::<EditHistory>().can_redo());
+    }
+
+    #[test]
+    fn redo_keeps_max_operations() {
+        let entity = Entity::from_raw(0);
+        let mut voxel_grid = grid();
+
+        let mut history = EditHistory::default();
+
+        for i in 0..3 {
+            history.fill(entity, &mut voxel_grid, [i; 3], [i; 3], 1.0);
+        }
+
+        let operation = history.undo_stack.pop_back().unwrap();
+        history.redo_stack.push(operation);
+
+        // Lowered while an operation can be redone
+        history.max_operations = 1;
+
+        let operation = history.redo_stack.pop().unwrap();
+        history.applying = Some((operation, false));
+
+        let mut world = World::new();
+        world.insert_resource(history);
+        world.run_system_once(finish_history_action_system);
+
+        assert_eq!(world.resource::<EditHistory>().undo_stack.len(), 1);
+    }
+}
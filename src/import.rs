@@ -0,0 +1,213 @@
+use std::{
+    fmt, fs,
+    io::{self, BufRead, BufReader},
+    path::Path,
+};
+
+use bevy::math::{Mat4, Vec3};
+use gltf::{buffer::Source, mesh::Mode, Gltf};
+
+use crate::meshing::MeshData;
+
+#[derive(Debug)]
+pub enum MeshImportError {
+    Io(io::Error),
+    InvalidFile(String),
+    Unsupported(String),
+}
+
+impl fmt::Display for MeshImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MeshImportError::Io(err) => write!(f, "io error: {}", err),
+            MeshImportError::InvalidFile(reason) => write!(f, "invalid file: {}", reason),
+            MeshImportError::Unsupported(reason) => write!(f, "unsupported: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for MeshImportError {}
+
+impl From<io::Error> for MeshImportError {
+    fn from(err: io::Error) -> Self {
+        MeshImportError::Io(err)
+    }
+}
+
+// Picks the reader from the extension, only positions and triangles are read
+pub fn load_mesh(path: &Path) -> Result<MeshData, MeshImportError> {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+
+    match extension.as_str() {
+        "obj" => load_obj(path),
+        "gltf" | "glb" => load_gltf(path),
+        _ => Err(MeshImportError::Unsupported(format!(
+            "mesh format {:?}",
+            extension
+        ))),
+    }
+}
+
+pub fn load_obj(path: &Path) -> Result<MeshData, MeshImportError> {
+    read_obj(BufReader::new(fs::File::open(path)?))
+}
+
+// Polygons are triangulated as fans, texture coordinates, normals and groups are ignored
+pub fn read_obj(reader: impl BufRead) -> Result<MeshData, MeshImportError> {
+    let mut mesh_data = MeshData::default();
+
+    for (line_number, line) in reader.lines().enumerate() {
+        let line = line?;
+        let mut tokens = line.split_whitespace();
+
+        let invalid = |reason: &str| {
+            MeshImportError::InvalidFile(format!("line {}: {}", line_number + 1, reason))
+        };
+
+        match tokens.next() {
+            Some("v") => {
+                let mut position = [0.0; 3];
+
+                for value in position.iter_mut() {
+                    *value = tokens
+                        .next()
+                        .and_then(|token| token.parse().ok())
+                        .ok_or_else(|| invalid("expected three coordinates"))?;
+                }
+
+                mesh_data.positions.push(position);
+            }
+            Some("f") => {
+                let vertex_count = mesh_data.positions.len() as i64;
+
+                // Only the position index before the first slash is used, negative indices
+                // count back from the last vertex
+                let face = tokens
+                    .map(|token| {
+                        let index = token
+                            .split('/')
+                            .next()
+                            .and_then(|index| index.parse::<i64>().ok())
+                            .ok_or_else(|| invalid("invalid face index"))?;
+
+                        let index = if index < 0 {
+                            vertex_count + index
+                        } else {
+                            index - 1
+                        };
+
+                        if (0..vertex_count).contains(&index) {
+                            Ok(index as u32)
+                        } else {
+                            Err(invalid("face index out of range"))
+                        }
+                    })
+                    .collect::<Result<Vec<u32>, MeshImportError>>()?;
+
+                for i in 1..face.len().saturating_sub(1) {
+                    mesh_data.indices.extend([face[0], face[i], face[i + 1]]);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    mesh_data.normals = vec![[0.0; 3]; mesh_data.positions.len()];
+    mesh_data.uvs = vec![[0.0; 2]; mesh_data.positions.len()];
+
+    Ok(mesh_data)
+}
+
+// Every mesh of the default scene is placed with its node transforms, buffers have to be
+// embedded in a .glb or stored next to the file
+pub fn load_gltf(path: &Path) -> Result<MeshData, MeshImportError> {
+    let gltf = Gltf::from_slice(&fs::read(path)?)
+        .map_err(|err| MeshImportError::InvalidFile(err.to_string()))?;
+
+    let buffers =
+        gltf.buffers()
+            .map(|buffer| match buffer.source() {
+                Source::Bin => gltf.blob.clone().ok_or_else(|| {
+                    MeshImportError::InvalidFile("missing binary chunk".to_string())
+                }),
+                Source::Uri(uri) if uri.starts_with("data:") => Err(MeshImportError::Unsupported(
+                    "buffers embedded as data uris".to_string(),
+                )),
+                Source::Uri(uri) => Ok(fs::read(path.with_file_name(uri))?),
+            })
+            .collect::<Result<Vec<Vec<u8>>, MeshImportError>>()?;
+
+    let mut mesh_data = MeshData::default();
+
+    match gltf.default_scene().or_else(|| gltf.scenes().next()) {
+        Some(scene) => {
+            for node in scene.nodes() {
+                append_gltf_node(&node, Mat4::IDENTITY, &buffers, &mut mesh_data);
+            }
+        }
+        None => {
+            for mesh in gltf.meshes() {
+                append_gltf_mesh(&mesh, Mat4::IDENTITY, &buffers, &mut mesh_data);
+            }
+        }
+    }
+
+    Ok(mesh_data)
+}
+
+fn append_gltf_node(
+    node: &gltf::Node,
+    parent: Mat4,
+    buffers: &[Vec<u8>],
+    mesh_data: &mut MeshData,
+) {
+    let transform = parent * Mat4::from_cols_array_2d(&node.transform().matrix());
+
+    if let Some(mesh) = node.mesh() {
+        append_gltf_mesh(&mesh, transform, buffers, mesh_data);
+    }
+
+    for child in node.children() {
+        append_gltf_node(&child, transform, buffers, mesh_data);
+    }
+}
+
+fn append_gltf_mesh(
+    mesh: &gltf::Mesh,
+    transform: Mat4,
+    buffers: &[Vec<u8>],
+    mesh_data: &mut MeshData,
+) {
+    for primitive in mesh.primitives() {
+        if primitive.mode() != Mode::Triangles {
+            continue;
+        }
+
+        let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(Vec::as_slice));
+
+        let Some(positions) = reader.read_positions() else {
+            continue;
+        };
+
+        let positions = positions
+            .map(|position| transform.transform_point3(Vec3::from(position)).to_array())
+            .collect::<Vec<[f32; 3]>>();
+
+        let indices = match reader.read_indices() {
+            Some(indices) => indices.into_u32().collect(),
+            None => (0..positions.len() as u32).collect(),
+        };
+
+        mesh_data.extend(&MeshData {
+            normals: vec![[0.0; 3]; positions.len()],
+            uvs: vec![[0.0; 2]; positions.len()],
+            positions,
+            indices,
+            material_ids: Vec::new(),
+        });
+    }
+}
@@ -0,0 +1,109 @@
+//! Voxel meshing for Bevy with marching cubes on the GPU and several CPU meshers.
+//!
+//! Add [`VoxelPlugins`] to an app and spawn entities with a [`VoxelGrid`] or [`Chunk`], a mesh
+//! handle and optionally [`MeshingSettings`]. The meshers can also be called directly through
+//! [`mesh_voxel_grid`] without an app.
+//!
+//! [`VoxelPlugins`] don't read any input. Sculpting, stamping, undo and exporting are driven through
+//! their resources and events, [`VoxelEditorPlugins`] binds them to the keyboard and mouse.
+
+use bevy::app::{PluginGroup, PluginGroupBuilder};
+
+pub mod ambient_occlusion;
+pub mod camera;
+pub mod channels;
+pub mod chunk;
+#[cfg(feature = "physics")]
+pub mod collider;
+mod contour;
+pub mod dual_contouring;
+pub mod editor;
+pub mod export;
+pub mod gltf_export;
+pub mod history;
+pub mod import;
+pub mod lod;
+mod lut;
+pub mod marching_cubes_cpu;
+#[cfg(feature = "gpu")]
+pub mod marching_cubes_gpu;
+pub mod marching_tetrahedra;
+pub mod meshing;
+pub mod raycast;
+pub mod sculpt;
+pub mod sparse;
+pub mod stamp;
+pub mod surface_nets;
+pub mod triplanar;
+pub mod volume;
+pub mod vox;
+pub mod voxel_file;
+pub mod voxel_value;
+
+pub use camera::CameraMarker;
+pub use channels::{ChannelSchema, VoxelChannel, VoxelChannels};
+pub use chunk::{Chunk, Voxel, CHUNK_SZ};
+#[cfg(feature = "physics")]
+pub use collider::{ColliderSettings, ColliderStrategy};
+pub use editor::VoxelEditorPlugins;
+pub use lod::{ChunkLod, LodSettings};
+pub use marching_cubes_cpu::{Bounds, VoxelGrid};
+pub use meshing::{mesh_voxel_grid, DirtyChunk, MeshData, MeshingAlgorithm, MeshingSettings};
+pub use voxel_value::{VoxelValue, F16};
+
+pub mod prelude {
+    #[cfg(feature = "physics")]
+    pub use crate::collider::{ColliderPlugin, ColliderSettings, ColliderStrategy};
+    #[cfg(feature = "cpu")]
+    pub use crate::marching_cubes_cpu::MarchingCubesCpuPlugin;
+    #[cfg(feature = "gpu")]
+    pub use crate::marching_cubes_gpu::MarchingCubesGpuPlugin;
+    pub use crate::{
+        ambient_occlusion::AmbientOcclusionSettings,
+        editor::VoxelEditorPlugins,
+        export::{ExportMeshes, MeshExportPlugin},
+        gltf_export::{ExportGltf, GltfExportPlugin},
+        history::{HistoryAction, HistoryPlugin},
+        lod::LodPlugin,
+        raycast::VoxelRaycast,
+        sculpt::{BrushHit, BrushStroke, SculptPlugin, SculptSettings},
+        stamp::StampPlugin,
+        triplanar::{TriplanarExtension, TriplanarMaterial, TriplanarMaterialPlugin},
+        volume::VolumePlugin,
+        vox::{ExportVox, VoxPlugin},
+        voxel_file::{SaveVoxels, VoxelFilePlugin},
+        Bounds, CameraMarker, Chunk, ChunkLod, DirtyChunk, MeshingAlgorithm, MeshingSettings,
+        VoxelChannels, VoxelGrid, VoxelPlugins, VoxelValue,
+    };
+}
+
+// Everything but the GPU mesher, which replaces the CPU one, and the Rapier plugin, which the
+// app adds with its own settings. The CPU mesher and colliders are left out when their features
+// are disabled. None of them read input, see `VoxelEditorPlugins`
+pub struct VoxelPlugins;
+
+impl PluginGroup for VoxelPlugins {
+    fn build(self) -> PluginGroupBuilder {
+        let group = PluginGroupBuilder::start::<Self>();
+
+        #[cfg(feature = "cpu")]
+        let group = group.add(marching_cubes_cpu::MarchingCubesCpuPlugin);
+
+        let group = group
+            .add(triplanar::TriplanarMaterialPlugin)
+            .add(lod::LodPlugin)
+            .add(export::MeshExportPlugin)
+            .add(gltf_export::GltfExportPlugin)
+            .add(voxel_file::VoxelFilePlugin)
+            .add(vox::VoxPlugin)
+            .add(volume::VolumePlugin)
+            .add(sculpt::SculptPlugin)
+            .add(history::HistoryPlugin)
+            .add(stamp::StampPlugin);
+
+        #[cfg(feature = "physics")]
+        let group = group.add(collider::ColliderPlugin);
+
+        group
+    }
+}
@@ -0,0 +1,260 @@
+use bevy::{
+    app::{App, Plugin, Update},
+    math::{IVec3, Vec3},
+    prelude::{GlobalTransform, Query, Res, Resource, With},
+    utils::HashMap,
+};
+
+use crate::{
+    chunk::Chunk,
+    contour::{mesh_cell, CellPoint},
+    meshing::MeshData,
+    CameraMarker,
+};
+
+// Fraction of a coarse cell that the regular cells are pulled back from a transition face
+pub const TRANSITION_WIDTH: f32 = 0.5;
+
+pub const FACE_DIRECTIONS: [IVec3; 6] = [
+    IVec3::NEG_X,
+    IVec3::X,
+    IVec3::NEG_Y,
+    IVec3::Y,
+    IVec3::NEG_Z,
+    IVec3::Z,
+];
+
+pub struct LodPlugin;
+
+impl Plugin for LodPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<LodSettings>()
+            .add_systems(Update, update_chunk_lod_system);
+    }
+}
+
+#[derive(Resource, Clone, Debug)]
+pub struct LodSettings {
+    // Camera distance at which each level after the full resolution one starts
+    pub distances: Vec<f32>,
+}
+
+impl Default for LodSettings {
+    fn default() -> Self {
+        Self {
+            distances: vec![32.0, 64.0, 128.0],
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub struct ChunkLod {
+    // Every level doubles the distance between the samples that are meshed
+    pub level: u32,
+    // One bit per face in the order of FACE_DIRECTIONS, set when the neighbour on that side is finer
+    pub transition_faces: u8,
+}
+
+impl ChunkLod {
+    pub fn stride(&self) -> usize {
+        1 << self.level
+    }
+
+    // Index of the last sample along each axis. The boundary plane is always meshed, when the
+    // stride doesn't divide the resolution the last coarse cell is shorter than the others so
+    // that every level ends on the same samples as the full resolution mesh
+    pub fn last_samples(&self, resolution: [usize; 3]) -> [usize; 3] {
+        resolution.map(|r| r.saturating_sub(1))
+    }
+
+    // Number of coarse cells along each axis
+    pub fn cell_counts(&self, resolution: [usize; 3]) -> [usize; 3] {
+        let stride = self.stride();
+        self.last_samples(resolution).map(|l| l.div_ceil(stride))
+    }
+
+    // Sample at a corner of the coarse cell starting at `start`, corners past the boundary are
+    // clamped to it
+    pub fn corner(&self, start: [usize; 3], offset: [usize; 3], last: [usize; 3]) -> [usize; 3] {
+        let stride = self.stride();
+        [0, 1, 2].map(|axis| (start[axis] + offset[axis] * stride).min(last[axis]))
+    }
+
+    // Width in samples of the last coarse cell before `last`
+    fn last_cell_width(&self, last: usize) -> usize {
+        let stride = self.stride();
+        last - last.saturating_sub(1) / stride * stride
+    }
+
+    pub fn has_transition(&self, face: usize) -> bool {
+        self.level > 0 && self.transition_faces & (1 << face) != 0
+    }
+
+    // Samples on a transition face are moved inwards to make room for the transition cells
+    pub fn shrink(
+        &self,
+        position: Vec3,
+        index: [usize; 3],
+        last: [usize; 3],
+        cell_size: Vec3,
+    ) -> Vec3 {
+        let mut position = position;
+
+        // Scaled by the width of the cell next to the face so the shorter last cell doesn't
+        // collapse
+        for axis in 0..3 {
+            if index[axis] == 0 && self.has_transition(2 * axis) {
+                position[axis] += TRANSITION_WIDTH * self.stride() as f32 * cell_size[axis];
+            } else if index[axis] == last[axis] && self.has_transition(2 * axis + 1) {
+                let width = self.last_cell_width(last[axis]) as f32;
+                position[axis] -= TRANSITION_WIDTH * width * cell_size[axis];
+            }
+        }
+
+        position
+    }
+}
+
+pub fn update_chunk_lod_system(
+    settings: Res<LodSettings>,
+    camera: Query<&GlobalTransform, With<CameraMarker>>,
+    mut chunks: Query<(&GlobalTransform, &mut Chunk)>,
+) {
+    let Ok(camera) = camera.get_single() else {
+        return;
+    };
+
+    let camera = camera.translation();
+
+    let levels = chunks
+        .iter()
+        .map(|(transform, chunk)| {
+            let distance = transform.translation().distance(camera);
+            let level = settings
+                .distances
+                .iter()
+                .take_while(|threshold| distance >= **threshold)
+                .count() as u32;
+
+            (chunk.position, level)
+        })
+        .collect::<HashMap<IVec3, u32>>();
+
+    for (_, mut chunk) in chunks.iter_mut() {
+        let level = levels[&chunk.position];
+
+        let mut transition_faces = 0;
+
+        for (face, direction) in FACE_DIRECTIONS.iter().enumerate() {
+            if let Some(neighbour) = levels.get(&(chunk.position + *direction)) {
+                if *neighbour < level {
+                    transition_faces |= 1 << face;
+                }
+            }
+        }
+
+        let lod = ChunkLod {
+            level,
+            transition_faces,
+        };
+
+        if chunk.lod != lod {
+            chunk.lod = lod;
+        }
+    }
+}
+
+// Transvoxel style transition cells: each cell spans one coarse cell on the boundary plane of a
+// face bordering a finer chunk, its front face uses the 3x3 samples the next finer level meshes on
+// that plane and its back face the 4 corners of the shrunk regular cell behind it, so the surface
+// closes on both sides and meets the finer chunk the same way two full resolution chunks do.
+//
+// Instead of the transvoxel lookup tables the cell is meshed by tracing the contour of its faces.
+pub fn transition_cells(
+    lod: &ChunkLod,
+    resolution: [usize; 3],
+    cell_size: Vec3,
+    iso_level: f32,
+    density: impl Fn([usize; 3]) -> f32,
+    position: impl Fn([usize; 3]) -> Vec3,
+    mesh_data: &mut MeshData,
+) {
+    let stride = lod.stride();
+    let half = stride / 2;
+    let last = lod.last_samples(resolution);
+
+    for face in 0..6 {
+        if !lod.has_transition(face) {
+            continue;
+        }
+
+        let axis = face / 2;
+        let plane = if face % 2 == 0 { 0 } else { last[axis] };
+        let u_axis = (axis + 1) % 3;
+        let v_axis = (axis + 2) % 3;
+
+        for cu in (0..last[u_axis]).step_by(stride) {
+            for cv in (0..last[v_axis]).step_by(stride) {
+                // The middle samples are where the next finer level puts its corners, which
+                // coincide with the end of a shortened last cell
+                let steps =
+                    |c: usize, last: usize| [c, (c + half).min(last), (c + stride).min(last)];
+                let (us, vs) = (steps(cu, last[u_axis]), steps(cv, last[v_axis]));
+
+                let sample = |i: usize, j: usize, back: bool| {
+                    let mut index = [0; 3];
+                    index[axis] = plane;
+                    index[u_axis] = us[i];
+                    index[v_axis] = vs[j];
+
+                    let unshrunk = position(index);
+                    let mut point = lod.shrink(unshrunk, index, last, cell_size);
+
+                    if !back {
+                        point[axis] = unshrunk[axis];
+                    }
+
+                    CellPoint {
+                        position: point,
+                        value: density(index),
+                    }
+                };
+
+                // Keys 0..9 are the front samples row by row, 9..13 the back corners
+                let mut points = [CellPoint {
+                    position: Vec3::ZERO,
+                    value: 0.0,
+                }; 13];
+
+                for j in 0..3 {
+                    for i in 0..3 {
+                        points[i + 3 * j] = sample(i, j, false);
+                    }
+                }
+
+                for j in 0..2 {
+                    for i in 0..2 {
+                        points[9 + i + 2 * j] = sample(i * 2, j * 2, true);
+                    }
+                }
+
+                mesh_cell(&points, &TRANSITION_CELL_FACES, iso_level, mesh_data);
+            }
+        }
+    }
+}
+
+const TRANSITION_CELL_FACES: [&[u8]; 9] = [
+    // Front face split into the four cells of the finer chunk
+    &[0, 1, 4, 3],
+    &[1, 2, 5, 4],
+    &[3, 4, 7, 6],
+    &[4, 5, 8, 7],
+    // Back face
+    &[9, 10, 12, 11],
+    // Sides
+    &[0, 3, 6, 11, 9],
+    &[2, 5, 8, 12, 10],
+    &[0, 1, 2, 10, 9],
+    &[6, 7, 8, 12, 11],
+];
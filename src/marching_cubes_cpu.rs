@@ -1,30 +1,53 @@
+#[cfg(any(feature = "cpu", feature = "physics"))]
+use bevy::prelude::Mesh;
+#[cfg(feature = "physics")]
+use bevy::render::mesh::VertexAttributeValues;
+#[cfg(feature = "cpu")]
 use bevy::{
     app::{App, Plugin, PreUpdate},
     asset::{Assets, Handle},
-    color::palettes::css::WHITE,
-    input::ButtonInput,
-    log::{debug, info},
-    math::{IVec3, Quat, Vec3, Vec4, Vec4Swizzles},
-    pbr::{PbrBundle, StandardMaterial},
-    prelude::{
-        default, Commands, Component, Condition, Entity, GlobalTransform, IntoSystemConfigs,
-        KeyCode, Mesh, Query, Res, ResMut, Sphere, Transform,
-    },
-    render::mesh::{Indices, VertexAttributeValues},
-    time::Time,
+    log::debug,
+    prelude::{Commands, DetectChanges, Entity, Has, Query, Ref, ResMut},
 };
+use bevy::{math::Vec3, prelude::Component};
+#[cfg(feature = "physics")]
 use bevy_rapier3d::prelude::{Collider, ComputedColliderShape};
 
+#[cfg(all(feature = "cpu", feature = "physics"))]
+use crate::collider::{queue_collider, ColliderSettings, ColliderSource};
+#[cfg(feature = "cpu")]
 use crate::{
+    ambient_occlusion::{bake_ambient_occlusion, AmbientOcclusionSettings},
+    channels::VoxelChannels,
+    chunk::Chunk,
+    dual_contouring::{dual_contouring, HermiteData},
+    meshing::{mesh_voxel_grid, DirtyChunk, MeshGeneration, MeshingAlgorithm},
+    voxel_value::F16,
+};
+use crate::{
+    contour::{mesh_cube, CellPoint},
+    lod::{transition_cells, ChunkLod},
     lut::{EDGE_TABLE, TRI_TABLE},
-    marching_cubes_gpu::Chunk,
+    meshing::{interp_vertex, MeshData, MeshingSettings},
+    sparse::{for_each_active_cell, SparseGrid},
+    voxel_value::VoxelValue,
 };
 
+#[cfg(feature = "cpu")]
 pub struct MarchingCubesCpuPlugin;
 
+#[cfg(feature = "cpu")]
 impl Plugin for MarchingCubesCpuPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(PreUpdate, marching_cubes_system);
+        app.add_systems(
+            PreUpdate,
+            (
+                marching_cubes_system::<f32>,
+                marching_cubes_system::<u8>,
+                marching_cubes_system::<i8>,
+                marching_cubes_system::<F16>,
+            ),
+        );
     }
 }
 
@@ -54,309 +77,704 @@ const VERTICES_COMB: [[usize; 2]; 12] = [
     [3, 7],
 ];
 
+// Corners of each cube face in ring order
+const FACES: [&[u8]; 6] = [
+    &[0, 1, 2, 3],
+    &[4, 5, 6, 7],
+    &[0, 1, 5, 4],
+    &[3, 2, 6, 7],
+    &[0, 3, 7, 4],
+    &[1, 2, 6, 5],
+];
+
+// Fraction of the extent of a mesh added around it when voxelizing
+#[cfg(feature = "physics")]
+const MESH_PADDING: f32 = 0.05;
+
+#[cfg(feature = "physics")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VoxelizeError {
+    NoPositions,
+    // Rapier couldn't build a triangle mesh from it
+    InvalidMesh,
+    // Every axis needs at least two samples
+    ResolutionTooSmall,
+}
+
+#[cfg(feature = "physics")]
+impl std::fmt::Display for VoxelizeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VoxelizeError::NoPositions => write!(f, "the mesh has no vertex positions"),
+            VoxelizeError::InvalidMesh => write!(f, "the mesh has no usable triangles"),
+            VoxelizeError::ResolutionTooSmall => {
+                write!(f, "the resolution needs at least two samples per axis")
+            }
+        }
+    }
+}
+
+#[cfg(feature = "physics")]
+impl std::error::Error for VoxelizeError {}
+
+// Distances along the ray at which it crosses the surface of the mesh
+#[cfg(feature = "physics")]
+fn ray_crossings(collider: &Collider, origin: Vec3, direction: Vec3, length: f32) -> Vec<f32> {
+    // Steps past a hit so the same triangle isn't hit again
+    let epsilon = length * 1e-5;
+
+    let mut crossings = Vec::new();
+    let mut start = 0.0;
+
+    while let Some(t) =
+        collider.cast_local_ray(origin + direction * start, direction, length - start, false)
+    {
+        crossings.push(start + t);
+        start += t + epsilon;
+    }
+
+    crossings
+}
+
+#[derive(Clone, Copy, Debug)]
 pub struct Bounds {
     pub min: Vec3,
     pub max: Vec3,
 }
 
-#[derive(Component)]
-pub struct VoxelGrid {
+// Samples are stored as V and read as f32, quantized grids are meshed like f32 ones
+#[derive(Component, Clone)]
+pub struct VoxelGrid<V: VoxelValue = f32> {
     pub resolution: [usize; 3],
-    pub data: Vec<f32>,
+    pub data: SparseGrid<V>,
     pub bounds: Bounds,
 }
 
-impl VoxelGrid {
-    pub fn from_mesh(mesh: &Mesh, resolution: [usize; 3]) -> Self {
-        let mut x_min = f32::MAX;
-        let mut x_max = f32::MIN;
-
-        let mut y_min = f32::MAX;
-        let mut y_max = f32::MIN;
-
-        let mut z_min = f32::MAX;
-        let mut z_max = f32::MIN;
+impl<V: VoxelValue> VoxelGrid<V> {
+    // Samples are inside when rays from outside of the mesh cross its surface an odd number of
+    // times before reaching them. Rays along each axis vote, so a ray grazing an edge of the mesh
+    // is outvoted by the other two. The mesh should be closed.
+    #[cfg(feature = "physics")]
+    pub fn from_mesh(mesh: &Mesh, resolution: [usize; 3]) -> Result<Self, VoxelizeError> {
+        if resolution.iter().any(|r| *r < 2) {
+            return Err(VoxelizeError::ResolutionTooSmall);
+        }
 
-        if let Some(VertexAttributeValues::Float32x3(vertices)) =
+        let Some(VertexAttributeValues::Float32x3(vertices)) =
             mesh.attribute(Mesh::ATTRIBUTE_POSITION)
-        {
-            for vertex in vertices.iter() {
-                x_min = x_min.min(vertex[0]);
-                x_max = x_max.max(vertex[0]);
+        else {
+            return Err(VoxelizeError::NoPositions);
+        };
 
-                y_min = y_min.min(vertex[1]);
-                y_max = y_max.max(vertex[1]);
+        let (min, max) = vertices
+            .iter()
+            .map(|vertex| Vec3::from(*vertex))
+            .fold((Vec3::MAX, Vec3::MIN), |(min, max), vertex| {
+                (min.min(vertex), max.max(vertex))
+            });
 
-                z_min = z_min.min(vertex[2]);
-                z_max = z_max.max(vertex[2]);
-            }
+        if vertices.is_empty() || !min.is_finite() || !max.is_finite() {
+            return Err(VoxelizeError::NoPositions);
         }
 
-        x_min *= 1.1;
-        x_max *= 1.1;
-
-        y_min *= 1.1;
-        y_max *= 1.1;
-
-        z_min *= 1.1;
-        z_max *= 1.1;
+        let collider = Collider::from_bevy_mesh(mesh, &ComputedColliderShape::TriMesh)
+            .ok_or(VoxelizeError::InvalidMesh)?;
+
+        // Padded on every side so the outer samples are outside, flat meshes are padded by the
+        // largest extent
+        let padding = ((max - min) * MESH_PADDING).max(Vec3::splat(
+            (max - min).max_element().max(f32::EPSILON) * MESH_PADDING,
+        ));
+        let min = min - padding;
+        let max = max + padding;
+
+        // The first and last samples lie on the padded bounds
+        let cell_size = (max - min)
+            / Vec3::new(
+                (resolution[0] - 1) as f32,
+                (resolution[1] - 1) as f32,
+                (resolution[2] - 1) as f32,
+            );
+
+        let mut voxel_grid = VoxelGrid {
+            resolution,
+            data: SparseGrid::new(resolution, V::from_f32(0.0)),
+            bounds: Bounds {
+                min,
+                max: min
+                    + cell_size
+                        * Vec3::new(
+                            resolution[0] as f32,
+                            resolution[1] as f32,
+                            resolution[2] as f32,
+                        ),
+            },
+        };
 
-        let x_step = (x_max - x_min) / resolution[0] as f32;
-        let y_step = (y_max - y_min) / resolution[1] as f32;
-        let z_step = (z_max - z_min) / resolution[2] as f32;
+        let mut votes = vec![0u8; resolution.iter().product()];
+        let flat_index = |index: [usize; 3]| {
+            index[0] + index[1] * resolution[0] + index[2] * resolution[0] * resolution[1]
+        };
 
-        let x_steps = resolution[0];
-        let y_steps = resolution[1];
-        let z_steps = resolution[2];
+        for axis in 0..3 {
+            let u_axis = (axis + 1) % 3;
+            let v_axis = (axis + 2) % 3;
 
-        let collider = Collider::from_bevy_mesh(mesh, &ComputedColliderShape::TriMesh).unwrap();
+            let mut direction = Vec3::ZERO;
+            direction[axis] = 1.0;
 
-        let mut data = vec![0.0; resolution[0] * resolution[1] * resolution[2]];
+            // Rays start a cell in front of the first sample and end a cell behind the last
+            let length = (max - min)[axis] + 2.0 * cell_size[axis];
 
-        for zi in 0..z_steps - 1 {
-            for yi in 0..y_steps - 1 {
-                for xi in 0..x_steps - 1 {
-                    let x = x_min + xi as f32 * x_step;
-                    let y = y_min + yi as f32 * y_step;
-                    let z = z_min + zi as f32 * z_step;
+            for v in 0..resolution[v_axis] {
+                for u in 0..resolution[u_axis] {
+                    let mut index = [0; 3];
+                    index[u_axis] = u;
+                    index[v_axis] = v;
 
-                    let point = Vec3::new(x, y, z);
+                    let origin = voxel_grid.position(index) - direction * cell_size[axis];
+                    let crossings = ray_crossings(&collider, origin, direction, length);
 
-                    let check_directions =
-                        [Vec3::X, Vec3::Y, Vec3::Z, -Vec3::X, -Vec3::Y, -Vec3::Z];
+                    for i in 0..resolution[axis] {
+                        index[axis] = i;
 
-                    let mut is_check_num = 0;
+                        let distance = (i + 1) as f32 * cell_size[axis];
+                        let crossed = crossings.iter().filter(|t| **t < distance).count();
 
-                    for direction in check_directions.iter() {
-                        if collider.intersects_local_ray(point, *direction, 100.0) {
-                            is_check_num += 1;
+                        if crossed % 2 == 1 {
+                            votes[flat_index(index)] += 1;
                         }
                     }
+                }
+            }
+        }
 
-                    let criterion = is_check_num == 6;
-                    if criterion {
-                        data[zi * y_steps * x_steps + yi * x_steps + xi] = 1.0;
+        for z in 0..resolution[2] {
+            for y in 0..resolution[1] {
+                for x in 0..resolution[0] {
+                    if votes[flat_index([x, y, z])] >= 2 {
+                        voxel_grid.set(x, y, z, 1.0);
                     }
                 }
             }
         }
 
-        let bounds = Bounds {
-            min: Vec3::new(x_min, y_min, z_min),
-            max: Vec3::new(x_max, y_max, z_max),
-        };
+        Ok(voxel_grid)
+    }
+
+    pub fn get(&self, x: usize, y: usize, z: usize) -> f32 {
+        self.data.get([x, y, z]).to_f32()
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, z: usize, value: f32) {
+        self.data.set([x, y, z], V::from_f32(value));
+    }
 
+    // The same grid with its samples stored as another type
+    pub fn convert<W: VoxelValue>(&self) -> VoxelGrid<W> {
         VoxelGrid {
-            resolution,
-            data,
-            bounds,
+            resolution: self.resolution,
+            data: SparseGrid::from_dense(
+                self.resolution,
+                &self
+                    .data
+                    .iter()
+                    .map(|value| W::from_f32(value.to_f32()))
+                    .collect::<Vec<W>>(),
+            ),
+            bounds: self.bounds,
         }
     }
 
-    pub fn get(&self, x: usize, y: usize, z: usize) -> f32 {
-        self.data[z * self.resolution[1] * self.resolution[0] + y * self.resolution[0] + x]
+    pub fn position(&self, index: [usize; 3]) -> Vec3 {
+        self.bounds.min
+            + Vec3::new(index[0] as f32, index[1] as f32, index[2] as f32) * self.cell_size()
     }
-}
 
-pub fn marching_cubes_system(
-    mut commands: Commands,
-    mut query: Query<(
-        Entity,
-        &Handle<StandardMaterial>,
-        &Handle<Mesh>,
-        &GlobalTransform,
-        &mut Transform,
-        &VoxelGrid,
-        &Collider,
-        &mut Chunk,
-    )>,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
-    keyboard_input: Res<ButtonInput<KeyCode>>,
-    time: Res<Time>,
-) {
-    if !keyboard_input.just_pressed(KeyCode::Enter) {
-        return;
+    pub fn cell_size(&self) -> Vec3 {
+        let Bounds { min, max } = self.bounds;
+
+        (max - min)
+            / Vec3::new(
+                self.resolution[0] as f32,
+                self.resolution[1] as f32,
+                self.resolution[2] as f32,
+            )
+    }
+
+    // Trilinear interpolation of the density at a point in the local space of the grid, points
+    // outside of the grid are treated as empty
+    pub fn sample(&self, point: Vec3) -> f32 {
+        let local = (point - self.bounds.min) / self.cell_size();
+
+        let last = Vec3::new(
+            (self.resolution[0] - 1) as f32,
+            (self.resolution[1] - 1) as f32,
+            (self.resolution[2] - 1) as f32,
+        );
+
+        if local.cmplt(Vec3::ZERO).any() || local.cmpgt(last).any() {
+            return 0.0;
+        }
+
+        let x0 = (local.x as usize).min(self.resolution[0].saturating_sub(2));
+        let y0 = (local.y as usize).min(self.resolution[1].saturating_sub(2));
+        let z0 = (local.z as usize).min(self.resolution[2].saturating_sub(2));
+
+        let x1 = (x0 + 1).min(self.resolution[0] - 1);
+        let y1 = (y0 + 1).min(self.resolution[1] - 1);
+        let z1 = (z0 + 1).min(self.resolution[2] - 1);
+
+        let t = local - Vec3::new(x0 as f32, y0 as f32, z0 as f32);
+
+        let c00 = lerp(self.get(x0, y0, z0), self.get(x1, y0, z0), t.x);
+        let c10 = lerp(self.get(x0, y1, z0), self.get(x1, y1, z0), t.x);
+        let c01 = lerp(self.get(x0, y0, z1), self.get(x1, y0, z1), t.x);
+        let c11 = lerp(self.get(x0, y1, z1), self.get(x1, y1, z1), t.x);
+
+        let c0 = lerp(c00, c10, t.y);
+        let c1 = lerp(c01, c11, t.y);
+
+        lerp(c0, c1, t.z)
     }
 
-    let points_sphere = meshes.add(Mesh::from(Sphere::new(0.05)));
+    // Central difference of the density, points towards increasing density
+    pub fn gradient(&self, point: Vec3) -> Vec3 {
+        let h = self.cell_size();
 
-    debug!("Running marching cubes");
+        Vec3::new(
+            self.sample(point + Vec3::X * h.x) - self.sample(point - Vec3::X * h.x),
+            self.sample(point + Vec3::Y * h.y) - self.sample(point - Vec3::Y * h.y),
+            self.sample(point + Vec3::Z * h.z) - self.sample(point - Vec3::Z * h.z),
+        ) / (2.0 * h)
+    }
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
 
+#[cfg(feature = "cpu")]
+type GridMeshQuery<'w, 's, V> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static Handle<Mesh>,
+        &'static VoxelGrid<V>,
+        Ref<'static, Chunk>,
+        Option<&'static MeshingSettings>,
+        Option<&'static AmbientOcclusionSettings>,
+        Option<&'static HermiteData>,
+        Option<&'static VoxelChannels>,
+        Option<&'static MeshGeneration>,
+        Has<DirtyChunk>,
+    ),
+>;
+
+#[cfg(feature = "cpu")]
+pub fn marching_cubes_system<V: VoxelValue>(
+    mut commands: Commands,
+    query: GridMeshQuery<V>,
+    #[cfg(feature = "physics")] collider_settings: Query<&ColliderSettings>,
+    mut meshes: ResMut<Assets<Mesh>>,
+) {
     for (
         entity,
-        material_handle,
         mesh_handle,
-        global_transform,
-        transform,
         voxel_grid,
-        collider,
-        _,
-    ) in query.iter_mut()
+        chunk,
+        settings,
+        ambient_occlusion,
+        hermite_data,
+        channels,
+        generation,
+        is_dirty,
+    ) in query.iter()
     {
+        // Chunks are remeshed when their level of detail changed and when marked dirty, after edits
+        // or on request
+        let is_lod_changed = chunk.is_changed() && !chunk.is_added();
+
+        if !is_lod_changed && !is_dirty {
+            continue;
+        }
+
         debug!("Running marching cubes for entity {:?}", entity);
 
+        let settings = settings.copied().unwrap_or_default();
+
+        let mesh_data = match settings.algorithm {
+            MeshingAlgorithm::DualContouring => {
+                dual_contouring(voxel_grid, &settings, &chunk.lod, hermite_data)
+            }
+            _ => mesh_voxel_grid(voxel_grid, &settings, &chunk.lod),
+        };
+
+        debug!("Calculated vertices: {}", mesh_data.vertex_count());
+
         let mesh = meshes.get_mut(mesh_handle).unwrap();
 
-        let Bounds { min, max } = voxel_grid.bounds;
+        // Remeshing on request or after a no-op edit often gives the same triangles back
+        let is_geometry_changed = MeshData::from_mesh(mesh)
+            .map_or(true, |previous| !previous.has_same_geometry(&mesh_data));
 
-        let x_steps = voxel_grid.resolution[0];
-        let y_steps = voxel_grid.resolution[1];
-        let z_steps = voxel_grid.resolution[2];
+        mesh_data.apply_to(mesh);
 
-        let x_step = (max[0] - min[0]) / x_steps as f32;
-        let y_step = (max[1] - min[1]) / y_steps as f32;
-        let z_step = (max[2] - min[2]) / z_steps as f32;
+        if let Some(channels) = channels {
+            channels.insert_attributes(mesh);
+        }
 
-        for xi in 0..voxel_grid.resolution[0] {
-            for yi in 0..voxel_grid.resolution[1] {
-                for zi in 0..voxel_grid.resolution[2] {
-                    let x = min[0] + xi as f32 * x_step;
-                    let y = min[1] + yi as f32 * y_step;
-                    let z = min[2] + zi as f32 * z_step;
+        if let Some(ambient_occlusion) = ambient_occlusion {
+            let colors = bake_ambient_occlusion(
+                voxel_grid,
+                &mesh_data.positions,
+                &mesh_data.normals,
+                ambient_occlusion,
+            );
+            mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors);
+        } else {
+            mesh.remove_attribute(Mesh::ATTRIBUTE_COLOR);
+        }
 
-                    let value = voxel_grid.get(xi, yi, zi);
+        if is_geometry_changed {
+            commands
+                .entity(entity)
+                .insert(generation.copied().unwrap_or_default().next());
+
+            #[cfg(feature = "physics")]
+            {
+                let strategy = collider_settings
+                    .get(entity)
+                    .cloned()
+                    .unwrap_or_default()
+                    .strategy;
+                let source =
+                    ColliderSource::for_grid(&strategy, voxel_grid, settings.iso_level, &mesh_data);
+                queue_collider(&mut commands, entity, source);
+            }
+        }
 
-                    if value > 0.0 {}
-                }
+        commands.entity(entity).remove::<DirtyChunk>();
+
+        debug!("Marching cubes done");
+    }
+}
+
+pub fn marching_cubes<V: VoxelValue>(
+    voxel_grid: &VoxelGrid<V>,
+    settings: &MeshingSettings,
+    lod: &ChunkLod,
+) -> MeshData {
+    let iso_level = settings.iso_level;
+
+    let stride = lod.stride();
+    let last = lod.last_samples(voxel_grid.resolution);
+    let cell_size = voxel_grid.cell_size();
+
+    let mut mesh_data = MeshData::default();
+
+    for_each_active_cell(voxel_grid, stride, last, |start| {
+        let points = OFFSETS.map(|offset| {
+            let index = lod.corner(start, offset, last);
+
+            CellPoint {
+                position: lod.shrink(voxel_grid.position(index), index, last, cell_size),
+                value: voxel_grid.get(index[0], index[1], index[2]),
             }
+        });
+
+        let cube_index = cube_index(&points, iso_level);
+
+        if cube_index == 0x00 || cube_index == 0xff {
+            return;
         }
 
-        let mut vert_counter = 0;
+        if is_ambiguous(cube_index) {
+            mesh_cube(&points, &OFFSETS, &FACES, iso_level, &mut mesh_data);
+            return;
+        }
 
-        let mut new_vertices = Vec::new();
-        let mut new_indices = Vec::new();
-        let mut new_normals = Vec::new();
-        let mut new_uvs = Vec::new();
+        let triangulation = TRI_TABLE[cube_index as usize];
 
-        let mut points_inside = 0;
+        let vertices = (0..12)
+            .map(|index| {
+                let edge = ((EDGE_TABLE[cube_index as usize] & (1 << index)) != 0) as i32 as f32;
 
-        for zi in 0..z_steps - 1 {
-            for yi in 0..y_steps - 1 {
-                for xi in 0..x_steps - 1 {
-                    let mut cube_index = 0b0000_0000;
+                let p1 = points[VERTICES_COMB[index][0]];
+                let p2 = points[VERTICES_COMB[index][1]];
 
-                    let position_values = OFFSETS
-                        .iter()
-                        .map(|offset| {
-                            let x = min[0] + (xi + offset[0]) as f32 * x_step;
-                            let y = min[1] + (yi + offset[1]) as f32 * y_step;
-                            let z = min[2] + (zi + offset[2]) as f32 * z_step;
-                            let value =
-                                voxel_grid.get(xi + offset[0], yi + offset[1], zi + offset[2]);
+                edge * interp_vertex(p1.position, p2.position, p1.value, p2.value, iso_level)
+            })
+            .collect::<Vec<Vec3>>();
 
-                            Vec4::new(x, y, z, value)
-                        })
-                        .collect::<Vec<Vec4>>();
+        for tri_idx in (0..triangulation.len()).step_by(3) {
+            if triangulation[tri_idx] == -1 {
+                break;
+            }
 
-                    for (index, position) in position_values.iter().enumerate() {
-                        // let criterion = position.x * position.x
-                        //     + position.y * position.y
-                        //     + position.z * position.z
-                        //     - 1.0
-                        //     > 0.0;
+            let v0 = vertices[triangulation[tri_idx] as usize];
+            let v1 = vertices[triangulation[tri_idx + 1] as usize];
+            let v2 = vertices[triangulation[tri_idx + 2] as usize];
 
-                        let criterion = position.w > 0.0;
+            mesh_data.push_triangle(v0, v1, v2);
+        }
+    });
+
+    transition_cells(
+        lod,
+        voxel_grid.resolution,
+        cell_size,
+        iso_level,
+        |index| voxel_grid.get(index[0], index[1], index[2]),
+        |index| voxel_grid.position(index),
+        &mut mesh_data,
+    );
+
+    mesh_data
+}
 
-                        if criterion {
-                            points_inside += 1;
-                        }
+// Same convention as the shader, corners outside of the surface set their bit so the triangles of
+// TRI_TABLE face outwards
+fn cube_index(points: &[CellPoint; 8], iso_level: f32) -> u32 {
+    points
+        .iter()
+        .enumerate()
+        .filter(|(_, point)| point.value <= iso_level)
+        .fold(0, |cube_index, (corner, _)| cube_index | (1 << corner))
+}
 
-                        cube_index = cube_index | (criterion as u32) * (1 << index);
-                    }
+// TRI_TABLE picks a fixed triangulation for faces with alternating corners, which doesn't have to
+// match the neighbouring cube and leaves cracks, and always caps two opposite corners separately
+// although the interior can connect them. Those cubes are traced with the asymptotic decider and
+// the interior test of `mesh_cube` instead so the output stays manifold.
+fn is_ambiguous(cube_index: u32) -> bool {
+    if FACES.iter().any(|face| is_ambiguous_face(face, cube_index)) {
+        return true;
+    }
 
-                    if cube_index == 0x00 || cube_index == 0xff {
-                        continue;
-                    }
+    let odd = match cube_index.count_ones() {
+        2 => cube_index,
+        6 => !cube_index & 0xff,
+        _ => return false,
+    };
 
-                    let triangulation = TRI_TABLE[cube_index as usize];
-
-                    let vertices = (0..12)
-                        .map(|index| {
-                            let edge = ((EDGE_TABLE[cube_index as usize] & (1 << index)) != 0)
-                                as i32 as f32;
-
-                            edge * interp_vertex(
-                                position_values[VERTICES_COMB[index][0]].xyz(),
-                                position_values[VERTICES_COMB[index][1]].xyz(),
-                                0.5,
-                                -0.5,
-                            )
-                        })
-                        .collect::<Vec<Vec3>>();
-
-                    for tri_idx in (0..triangulation.len()).step_by(3) {
-                        if triangulation[tri_idx] == -1 {
-                            break;
-                        }
+    let [a, b] = [odd.trailing_zeros(), 31 - odd.leading_zeros()].map(|i| OFFSETS[i as usize]);
 
-                        let v0 = vertices[triangulation[tri_idx] as usize];
-                        let v1 = vertices[triangulation[tri_idx + 1] as usize];
-                        let v2 = vertices[triangulation[tri_idx + 2] as usize];
+    (0..3).all(|axis| a[axis] != b[axis])
+}
 
-                        let normal = (v1 - v0).cross(v2 - v0).normalize();
+fn is_ambiguous_face(face: &[u8], cube_index: u32) -> bool {
+    let [a, b, c, d] = [0, 1, 2, 3].map(|i| cube_index & (1 << face[i]) != 0);
 
-                        new_vertices.push([v0.x, v0.y, v0.z]);
-                        new_vertices.push([v1.x, v1.y, v1.z]);
-                        new_vertices.push([v2.x, v2.y, v2.z]);
+    a == c && b == d && a != b
+}
 
-                        new_indices.extend([vert_counter, vert_counter + 1, vert_counter + 2]);
+// The shader only meshes cubes that TRI_TABLE gets right, the ambiguous ones are meshed here
+pub fn ambiguous_cells<T: Copy + PartialEq>(
+    lod: &ChunkLod,
+    voxels: &SparseGrid<T>,
+    cell_size: Vec3,
+    iso_level: f32,
+    density: impl Fn(T) -> f32,
+    position: impl Fn([usize; 3]) -> Vec3,
+    mesh_data: &mut MeshData,
+) {
+    let last = lod.last_samples(voxels.dimensions());
 
-                        new_normals.push([normal.x, normal.y, normal.z]);
-                        new_normals.push([normal.x, normal.y, normal.z]);
-                        new_normals.push([normal.x, normal.y, normal.z]);
+    voxels.for_each_active_cell(lod.stride(), last, |start| {
+        let points = OFFSETS.map(|offset| {
+            let index = lod.corner(start, offset, last);
 
-                        new_uvs.push([0.0, 0.0]);
-                        new_uvs.push([1.0, 0.0]);
-                        new_uvs.push([0.0, 1.0]);
+            CellPoint {
+                position: lod.shrink(position(index), index, last, cell_size),
+                value: density(voxels.get(index)),
+            }
+        });
 
-                        vert_counter += 3;
-                    }
+        if is_ambiguous(cube_index(&points, iso_level)) {
+            mesh_cube(&points, &OFFSETS, &FACES, iso_level, mesh_data);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::utils::HashMap;
+
+    use super::*;
+
+    // Samples outside of `values` are air so the surface is closed
+    fn padded_grid(size: usize, mut value: impl FnMut([usize; 3]) -> f32) -> VoxelGrid {
+        let resolution = [size + 2; 3];
+
+        let mut values = Vec::new();
+
+        for z in 0..size + 2 {
+            for y in 0..size + 2 {
+                for x in 0..size + 2 {
+                    let index = [x, y, z];
+
+                    values.push(if index.iter().all(|i| (1..=size).contains(i)) {
+                        value(index.map(|i| i - 1))
+                    } else {
+                        0.0
+                    });
                 }
             }
         }
 
-        let vertex_count = new_vertices.len();
+        VoxelGrid {
+            resolution,
+            data: SparseGrid::from_dense(resolution, &values),
+            bounds: Bounds {
+                min: Vec3::ZERO,
+                max: Vec3::splat((size + 2) as f32),
+            },
+        }
+    }
 
-        debug!("Calculated vertices: {}", vertex_count);
-        debug!("Points inside: {}", points_inside);
+    fn mesh(voxel_grid: &VoxelGrid) -> MeshData {
+        marching_cubes(
+            voxel_grid,
+            &MeshingSettings::default(),
+            &ChunkLod::default(),
+        )
+    }
 
-        if let Some(VertexAttributeValues::Float32x3(vertices)) =
-            mesh.attribute_mut(Mesh::ATTRIBUTE_POSITION)
-        {
-            vertices.clear();
-            vertices.extend(new_vertices);
+    // Number of connected pieces of the surface, triangles sharing a position are connected
+    fn surfaces(mesh_data: &MeshData) -> usize {
+        fn root(parents: &mut [usize], mut i: usize) -> usize {
+            while parents[i] != i {
+                i = parents[i];
+            }
+            i
         }
 
-        if let Some(Indices::U32(indices)) = mesh.indices_mut() {
-            indices.clear();
-            indices.extend(new_indices);
+        let mut parents = (0..mesh_data.positions.len()).collect::<Vec<usize>>();
+        let mut welded = HashMap::<[u32; 3], usize>::default();
+
+        for (i, position) in mesh_data.positions.iter().enumerate() {
+            let first = *welded.entry(position.map(f32::to_bits)).or_insert(i);
+            let (a, b) = (root(&mut parents, first), root(&mut parents, i));
+            parents[a] = b;
         }
 
-        if let Some(VertexAttributeValues::Float32x3(normals)) =
-            mesh.attribute_mut(Mesh::ATTRIBUTE_NORMAL)
-        {
-            normals.clear();
-            normals.reserve(vertex_count);
-            normals.extend(new_normals);
+        for triangle in mesh_data.indices.chunks_exact(3) {
+            for k in 1..3 {
+                let a = root(&mut parents, triangle[0] as usize);
+                let b = root(&mut parents, triangle[k] as usize);
+                parents[a] = b;
+            }
         }
-        if let Some(VertexAttributeValues::Float32x2(uvs)) =
-            mesh.attribute_mut(Mesh::ATTRIBUTE_UV_0)
-        {
-            uvs.clear();
-            uvs.reserve(vertex_count);
-            uvs.extend(new_uvs);
+
+        (0..parents.len())
+            .filter(|i| root(&mut parents, *i) == *i)
+            .count()
+    }
+
+    #[test]
+    fn ambiguous_face_is_watertight() {
+        // Opposite corners of every face of the inner cube are inside
+        let voxel_grid = padded_grid(2, |[x, y, z]| if (x + y + z) % 2 == 0 { 1.0 } else { 0.3 });
+
+        assert!(mesh(&voxel_grid).is_watertight());
+    }
+
+    #[test]
+    fn interior_tunnel_is_watertight() {
+        // The inside corners only touch through the middle of the cube, where the interpolant is
+        // above the iso level, so they are joined by a tube
+        let voxel_grid = padded_grid(2, |index| match index {
+            [0, 0, 0] | [1, 1, 1] => 1.0,
+            _ => 0.45,
+        });
+
+        let tunnel = mesh(&voxel_grid);
+        assert!(tunnel.is_watertight());
+
+        let voxel_grid = padded_grid(2, |index| match index {
+            [0, 0, 0] | [1, 1, 1] => 1.0,
+            _ => 0.0,
+        });
+
+        let separate = mesh(&voxel_grid);
+        assert!(separate.is_watertight());
+
+        assert_eq!(surfaces(&tunnel), 1);
+        assert_eq!(surfaces(&separate), 2);
+    }
+
+    #[test]
+    fn noise_is_watertight() {
+        let mut state = 0x2545_f491_u32;
+
+        for _ in 0..20 {
+            let voxel_grid = padded_grid(8, |_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+
+                state as f32 / u32::MAX as f32
+            });
+
+            assert!(mesh(&voxel_grid).is_watertight());
         }
+    }
 
-        commands
-            .entity(entity)
-            .insert(Collider::from_bevy_mesh(mesh, &ComputedColliderShape::TriMesh).unwrap());
+    #[cfg(feature = "physics")]
+    #[test]
+    fn voxelizes_meshes_away_from_the_origin() {
+        use bevy::math::primitives::Cuboid;
 
-        debug!("Marching cubes done");
+        let min = Vec3::new(100.0, -3.0, 20.0);
+        let max = Vec3::new(110.0, 5.0, 260.0);
+        let mesh = Mesh::from(Cuboid::from_size(max - min)).translated_by((min + max) * 0.5);
+
+        let voxel_grid = VoxelGrid::<f32>::from_mesh(&mesh, [16; 3]).unwrap();
+        assert!(voxel_grid.bounds.min.cmplt(min).all());
+        assert!(voxel_grid.bounds.max.cmpgt(max).all());
+
+        for z in 0..16 {
+            for y in 0..16 {
+                for x in 0..16 {
+                    let position = voxel_grid.position([x, y, z]);
+                    let inside = position.cmpgt(min).all() && position.cmplt(max).all();
+
+                    assert_eq!(voxel_grid.get(x, y, z), inside as u8 as f32);
+                }
+            }
+        }
     }
-}
 
-fn interp_vertex(p1: Vec3, p2: Vec3, val1: f32, val2: f32) -> Vec3 {
-    let t = (0.0 - val1) / (val2 - val1);
-    p1 + (p2 - p1) * t
+    #[cfg(feature = "physics")]
+    #[test]
+    fn voxelizing_keeps_cavities_empty() {
+        use bevy::math::primitives::Sphere;
+
+        // A ball of radius 4 with a hollow of radius 2
+        let mut mesh = Mesh::from(Sphere::new(4.0));
+        mesh.merge(&Mesh::from(Sphere::new(2.0)));
+
+        let voxel_grid = VoxelGrid::<f32>::from_mesh(&mesh, [21; 3]).unwrap();
+
+        assert_eq!(voxel_grid.get(10, 10, 10), 0.0);
+        assert_eq!(voxel_grid.sample(Vec3::new(3.0, 0.0, 0.0)), 1.0);
+        assert_eq!(voxel_grid.sample(Vec3::new(0.0, -3.0, 0.0)), 1.0);
+        assert_eq!(voxel_grid.sample(Vec3::new(0.0, 0.0, 5.0)), 0.0);
+    }
+
+    #[cfg(feature = "physics")]
+    #[test]
+    fn voxelizing_without_positions_fails() {
+        let mesh = Mesh::new(
+            bevy::render::mesh::PrimitiveTopology::TriangleList,
+            bevy::render::render_asset::RenderAssetUsages::default(),
+        );
+
+        assert_eq!(
+            VoxelGrid::<f32>::from_mesh(&mesh, [8; 3]).err(),
+            Some(VoxelizeError::NoPositions)
+        );
+    }
 }
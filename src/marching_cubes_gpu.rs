@@ -9,41 +9,30 @@ use bevy::{
         render_resource::*,
         renderer::{RenderDevice, RenderQueue},
     },
-    utils::HashMap,
 };
 
-use bevy_rapier3d::prelude::{Collider, ComputedColliderShape};
-use bytemuck::{cast_slice, Pod, Zeroable};
+use bytemuck::{bytes_of, cast_slice, Pod, Zeroable};
+use channels::VoxelChannels;
+use chunk::{Chunk, Voxel, CHUNK_SZ, CHUNK_SZ_2, CHUNK_SZ_3};
+#[cfg(feature = "physics")]
+use collider::{queue_collider, ColliderSettings, ColliderSource};
+use lod::{transition_cells, TRANSITION_WIDTH};
 use lut::{EDGE_TABLE, TRI_TABLE};
+use marching_cubes_cpu::ambiguous_cells;
+use meshing::{MeshData, MeshGeneration};
 use wgpu::MaintainBase::Wait;
 
 use crate::*;
 
 // use flagset::{flags, FlagSet};
 
-const CHUNK_SZ: usize = 32;
-const CHUNK_SZ_2: usize = CHUNK_SZ * CHUNK_SZ;
-const CHUNK_SZ_3: usize = CHUNK_SZ * CHUNK_SZ * CHUNK_SZ;
-
-#[derive(Component)]
-pub struct Chunk {
-    pub position: IVec3,
-    pub voxels: Vec<Voxel>,
-}
-
-impl Chunk {
-    pub fn new(position: IVec3) -> Self {
-        let mut voxels = Vec::with_capacity(CHUNK_SZ_3);
-        voxels.resize(CHUNK_SZ_3, Voxel::default());
-        Self { position, voxels }
-    }
-}
-
 #[derive(Copy, Clone, Default, Pod, Zeroable)]
 #[repr(C)]
-pub struct Voxel {
-    flags: u32,
-    density: f32,
+struct ChunkParams {
+    lod: u32,
+    transition_faces: u32,
+    transition_width: f32,
+    _padding: u32,
 }
 
 #[derive(Resource)]
@@ -67,6 +56,7 @@ pub struct VoxelBuffers {
     indices: BufVec<u32>,
     atomics: BufVec<u32>,
     atomics_staging: Buffer,
+    params: Buffer,
 }
 
 struct BindingGroups {
@@ -125,6 +115,12 @@ fn init_pipeline_system(mut commands: Commands, render_device: Res<RenderDevice>
         contents: cast_slice(&[0u32, 0u32]),
         usage: BufferUsages::COPY_SRC,
     });
+    let params = render_device.create_buffer(&BufferDescriptor {
+        label: Some("chunk params buffer"),
+        size: size_of::<ChunkParams>() as BufferAddress,
+        usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
 
     // let voxel_shader = asset_server.load("shaders/voxels.wgsl");
     let shader_source = include_str!("../assets/shaders/voxels.wgsl");
@@ -153,13 +149,27 @@ fn init_pipeline_system(mut commands: Commands, render_device: Res<RenderDevice>
         indices,
         atomics,
         atomics_staging,
+        params,
     });
     commands.insert_resource(VoxelsPipeline { voxels_pipeline });
 }
 
+type ChunkMeshQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static Handle<Mesh>,
+        &'static mut Chunk,
+        Option<&'static VoxelChannels>,
+        Option<&'static MeshGeneration>,
+    ),
+>;
+
 pub fn marching_cubes_system(
     mut commands: Commands,
-    mut query: Query<(Entity, &Handle<Mesh>, &mut Chunk)>,
+    mut query: ChunkMeshQuery,
+    #[cfg(feature = "physics")] collider_settings: Query<&ColliderSettings>,
     mut meshes: ResMut<Assets<Mesh>>,
     mut buffers: ResMut<VoxelBuffers>,
     time: Res<Time>,
@@ -169,7 +179,19 @@ pub fn marching_cubes_system(
 ) {
     // let now = std::time::Instant::now();
 
-    for (entity, mesh, mut chunk) in query.iter_mut() {
+    for (entity, mesh, chunk, channels, generation) in query.iter_mut() {
+        // A chunk filled with air has no surface
+        if chunk
+            .voxels
+            .uniform()
+            .is_some_and(|voxel| voxel.flags == 0 && voxel.density < 0.5)
+        {
+            if chunk.is_changed() {
+                clear_chunk(&mut commands, entity, &mut meshes, mesh, generation);
+            }
+            continue;
+        }
+
         buffers.atomics.clear();
         buffers.atomics.push(0);
         buffers.atomics.push(0);
@@ -197,6 +219,7 @@ pub fn marching_cubes_system(
                     buffers.normals.buffer().as_entire_binding(),
                     buffers.indices.buffer().as_entire_binding(),
                     buffers.uvs.buffer().as_entire_binding(),
+                    buffers.params.as_entire_binding(),
                 )),
             ),
         };
@@ -204,7 +227,21 @@ pub fn marching_cubes_system(
         let mut command_encoder = render_device.create_command_encoder(&CommandEncoderDescriptor {
             label: Some("voxel 1 command encoder"),
         });
-        render_queue.write_buffer(&buffers.voxels_staging, 0, &cast_slice(&chunk.voxels)[..]);
+        render_queue.write_buffer(
+            &buffers.voxels_staging,
+            0,
+            cast_slice(&chunk.voxels.to_dense()),
+        );
+        render_queue.write_buffer(
+            &buffers.params,
+            0,
+            bytes_of(&ChunkParams {
+                lod: chunk.lod.level,
+                transition_faces: chunk.lod.transition_faces as u32,
+                transition_width: TRANSITION_WIDTH,
+                _padding: 0,
+            }),
+        );
         command_encoder.copy_buffer_to_buffer(
             &buffers.voxels_staging,
             0,
@@ -234,34 +271,70 @@ pub fn marching_cubes_system(
         let vertex_count = buffers.atomics.as_slice()[0] as usize;
         let index_count = buffers.atomics.as_slice()[1] as usize;
 
-        if vertex_count == 0 {
+        // Transition cells and cubes with an ambiguous topology are few compared to the regular
+        // cells, so they are built on the CPU. Like `get_smooth_density` in the shader they treat
+        // blocks as empty, the blocks are meshed as cubes.
+        let smooth_density = |voxel: Voxel| if voxel.flags == 0 { voxel.density } else { 0.0 };
+
+        let mut cpu_cells = MeshData::default();
+        transition_cells(
+            &chunk.lod,
+            [CHUNK_SZ; 3],
+            Vec3::ONE,
+            0.5,
+            |index| smooth_density(chunk.voxels.get(index)),
+            |[x, y, z]| Vec3::new(x as f32, y as f32, z as f32),
+            &mut cpu_cells,
+        );
+        ambiguous_cells(
+            &chunk.lod,
+            &chunk.voxels,
+            Vec3::ONE,
+            0.5,
+            smooth_density,
+            |[x, y, z]| Vec3::new(x as f32, y as f32, z as f32),
+            &mut cpu_cells,
+        );
+
+        if vertex_count == 0 && cpu_cells.indices.is_empty() {
+            if chunk.is_changed() {
+                clear_chunk(&mut commands, entity, &mut meshes, mesh, generation);
+            }
             continue;
         }
 
-        let mut command_encoder = render_device.create_command_encoder(&CommandEncoderDescriptor {
-            label: Some("voxel 2 command encoder"),
-        });
-        buffers
-            .vertices
-            .encode_read(vertex_count, &mut command_encoder);
-        buffers
-            .normals
-            .encode_read(vertex_count, &mut command_encoder);
-        buffers.uvs.encode_read(vertex_count, &mut command_encoder);
-        buffers
-            .indices
-            .encode_read(index_count, &mut command_encoder);
-        render_queue.submit(once(command_encoder.finish()));
-        buffers.vertices.map_buffer(vertex_count);
-        buffers.normals.map_buffer(vertex_count);
-        buffers.uvs.map_buffer(vertex_count);
-        buffers.indices.map_buffer(index_count);
-        render_device.poll(Wait);
-
-        buffers.vertices.read_and_unmap_buffer(vertex_count);
-        buffers.normals.read_and_unmap_buffer(vertex_count);
-        buffers.uvs.read_and_unmap_buffer(vertex_count);
-        buffers.indices.read_and_unmap_buffer(index_count);
+        if vertex_count > 0 {
+            let mut command_encoder =
+                render_device.create_command_encoder(&CommandEncoderDescriptor {
+                    label: Some("voxel 2 command encoder"),
+                });
+            buffers
+                .vertices
+                .encode_read(vertex_count, &mut command_encoder);
+            buffers
+                .normals
+                .encode_read(vertex_count, &mut command_encoder);
+            buffers.uvs.encode_read(vertex_count, &mut command_encoder);
+            buffers
+                .indices
+                .encode_read(index_count, &mut command_encoder);
+            render_queue.submit(once(command_encoder.finish()));
+            buffers.vertices.map_buffer(vertex_count);
+            buffers.normals.map_buffer(vertex_count);
+            buffers.uvs.map_buffer(vertex_count);
+            buffers.indices.map_buffer(index_count);
+            render_device.poll(Wait);
+
+            buffers.vertices.read_and_unmap_buffer(vertex_count);
+            buffers.normals.read_and_unmap_buffer(vertex_count);
+            buffers.uvs.read_and_unmap_buffer(vertex_count);
+            buffers.indices.read_and_unmap_buffer(index_count);
+        } else {
+            buffers.vertices.clear();
+            buffers.normals.clear();
+            buffers.uvs.clear();
+            buffers.indices.clear();
+        }
 
         let mesh = meshes.get_mut(mesh).unwrap();
 
@@ -297,15 +370,56 @@ pub fn marching_cubes_system(
             }
         }
 
-        // TODO:perf inefficient
-        commands
-            .entity(entity)
-            .insert(Collider::from_bevy_mesh(mesh, &ComputedColliderShape::TriMesh).unwrap());
+        cpu_cells.append_to(mesh);
+
+        if let Some(channels) = channels {
+            channels.insert_attributes(mesh);
+        }
+
+        // The chunk is remeshed every frame but its geometry only changes with its voxels or
+        // level of detail
+        if chunk.is_changed() {
+            commands
+                .entity(entity)
+                .insert(generation.copied().unwrap_or_default().next());
+
+            #[cfg(feature = "physics")]
+            {
+                let strategy = collider_settings
+                    .get(entity)
+                    .cloned()
+                    .unwrap_or_default()
+                    .strategy;
+                let mesh_data = MeshData::from_mesh(mesh).unwrap_or_default();
+                let source = ColliderSource::for_chunk(&strategy, &chunk, &mesh_data);
+                queue_collider(&mut commands, entity, source);
+            }
+        }
     }
 
     // println!("Elapsed: {:.2?}", now.elapsed());
 }
 
+// The chunk has no surface anymore, the geometry it had is removed along with its collider
+fn clear_chunk(
+    commands: &mut Commands,
+    entity: Entity,
+    meshes: &mut Assets<Mesh>,
+    mesh: &Handle<Mesh>,
+    generation: Option<&MeshGeneration>,
+) {
+    if let Some(mesh) = meshes.get_mut(mesh) {
+        MeshData::default().apply_to(mesh);
+    }
+
+    commands
+        .entity(entity)
+        .insert(generation.copied().unwrap_or_default().next());
+
+    #[cfg(feature = "physics")]
+    queue_collider(commands, entity, ColliderSource::empty());
+}
+
 pub struct BufVec<T: Pod> {
     read_only: bool,
     buffer_capacity: usize,
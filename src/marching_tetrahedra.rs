@@ -0,0 +1,213 @@
+use bevy::{math::Vec3, utils::HashMap};
+
+use crate::{
+    lod::ChunkLod,
+    marching_cubes_cpu::VoxelGrid,
+    meshing::{interp_vertex, MeshData, MeshingSettings},
+    sparse::for_each_active_cell,
+    voxel_value::VoxelValue,
+};
+
+// Corner bits are x = 1, y = 2, z = 4
+const TETRAHEDRA: [[usize; 4]; 6] = [
+    [0, 1, 3, 7],
+    [0, 1, 5, 7],
+    [0, 2, 3, 7],
+    [0, 2, 6, 7],
+    [0, 4, 5, 7],
+    [0, 4, 6, 7],
+];
+
+// Every cell is split into six tetrahedra around its main diagonal. All cells use the same
+// diagonal so the faces of neighbouring tetrahedra line up, and a tetrahedron has no ambiguous
+// configurations, so the surface is closed wherever it doesn't leave the grid. Vertices are shared
+// between cells, which makes that checkable with MeshData::is_watertight.
+pub fn marching_tetrahedra<V: VoxelValue>(
+    voxel_grid: &VoxelGrid<V>,
+    settings: &MeshingSettings,
+    lod: &ChunkLod,
+) -> MeshData {
+    let iso_level = settings.iso_level;
+
+    let stride = lod.stride();
+    let last = lod.last_samples(voxel_grid.resolution);
+
+    let flat_index = |index: [usize; 3]| {
+        index[0]
+            + index[1] * voxel_grid.resolution[0]
+            + index[2] * voxel_grid.resolution[0] * voxel_grid.resolution[1]
+    };
+
+    let mut mesh_data = MeshData::default();
+    let mut edge_vertices = HashMap::<(usize, usize), u32>::default();
+
+    let mut edge_vertex = |mesh_data: &mut MeshData, a: [usize; 3], b: [usize; 3]| {
+        // Ordered so that both tetrahedra sharing the edge interpolate the same way
+        let (a, b) = if flat_index(a) < flat_index(b) {
+            (a, b)
+        } else {
+            (b, a)
+        };
+
+        *edge_vertices
+            .entry((flat_index(a), flat_index(b)))
+            .or_insert_with(|| {
+                let va = voxel_grid.get(a[0], a[1], a[2]);
+                let vb = voxel_grid.get(b[0], b[1], b[2]);
+
+                let position = interp_vertex(
+                    voxel_grid.position(a),
+                    voxel_grid.position(b),
+                    va,
+                    vb,
+                    iso_level,
+                );
+                let normal = -voxel_grid.gradient(position).normalize_or_zero();
+
+                mesh_data.push_vertex(position, normal)
+            })
+    };
+
+    for_each_active_cell(voxel_grid, stride, last, |[xi, yi, zi]| {
+        let corners = [0, 1, 2, 3, 4, 5, 6, 7].map(|corner: usize| {
+            lod.corner(
+                [xi, yi, zi],
+                [corner & 1, (corner >> 1) & 1, (corner >> 2) & 1],
+                last,
+            )
+        });
+
+        for tetrahedron in TETRAHEDRA.iter() {
+            let samples = tetrahedron.map(|corner| corners[corner]);
+
+            let (inside, outside): (Vec<[usize; 3]>, Vec<[usize; 3]>) = samples
+                .iter()
+                .partition(|s| voxel_grid.get(s[0], s[1], s[2]) > iso_level);
+
+            if inside.is_empty() || outside.is_empty() {
+                continue;
+            }
+
+            let polygon = match (inside.len(), outside.len()) {
+                (1, 3) => outside
+                    .iter()
+                    .map(|o| edge_vertex(&mut mesh_data, inside[0], *o))
+                    .collect::<Vec<u32>>(),
+                (3, 1) => inside
+                    .iter()
+                    .map(|i| edge_vertex(&mut mesh_data, *i, outside[0]))
+                    .collect::<Vec<u32>>(),
+                _ => vec![
+                    edge_vertex(&mut mesh_data, inside[0], outside[0]),
+                    edge_vertex(&mut mesh_data, inside[0], outside[1]),
+                    edge_vertex(&mut mesh_data, inside[1], outside[1]),
+                    edge_vertex(&mut mesh_data, inside[1], outside[0]),
+                ],
+            };
+
+            let centroid = |samples: &[[usize; 3]]| {
+                samples
+                    .iter()
+                    .map(|s| voxel_grid.position(*s))
+                    .sum::<Vec3>()
+                    / samples.len() as f32
+            };
+            let outward = centroid(&outside) - centroid(&inside);
+
+            let position = |index: u32| Vec3::from(mesh_data.positions[index as usize]);
+            let normal = (position(polygon[1]) - position(polygon[0]))
+                .cross(position(polygon[2]) - position(polygon[0]));
+
+            let mut polygon = polygon;
+
+            if normal.dot(outward) < 0.0 {
+                polygon.reverse();
+            }
+
+            for i in 1..polygon.len() - 1 {
+                mesh_data
+                    .indices
+                    .extend([polygon[0], polygon[i], polygon[i + 1]]);
+            }
+        }
+    });
+
+    mesh_data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{marching_cubes_cpu::Bounds, sparse::SparseGrid};
+
+    const SIZE: usize = 8;
+
+    // Random densities inside of a border of air, so the surface is closed
+    fn padded_noise(state: &mut u32) -> VoxelGrid {
+        let resolution = [SIZE + 2; 3];
+        let mut values = Vec::new();
+
+        for z in 0..SIZE + 2 {
+            for y in 0..SIZE + 2 {
+                for x in 0..SIZE + 2 {
+                    *state ^= *state << 13;
+                    *state ^= *state >> 17;
+                    *state ^= *state << 5;
+
+                    let is_border = [x, y, z].iter().any(|i| *i == 0 || *i == SIZE + 1);
+
+                    values.push(if is_border {
+                        0.0
+                    } else {
+                        *state as f32 / u32::MAX as f32
+                    });
+                }
+            }
+        }
+
+        VoxelGrid {
+            resolution,
+            data: SparseGrid::from_dense(resolution, &values),
+            bounds: Bounds {
+                min: Vec3::ZERO,
+                max: Vec3::splat((SIZE + 2) as f32),
+            },
+        }
+    }
+
+    #[test]
+    fn noise_is_watertight() {
+        let settings = MeshingSettings::default();
+        let mut state = 0x9e37_79b9_u32;
+        let mut configurations = [false; 256];
+
+        for _ in 0..20 {
+            let voxel_grid = padded_noise(&mut state);
+
+            for z in 0..SIZE + 1 {
+                for y in 0..SIZE + 1 {
+                    for x in 0..SIZE + 1 {
+                        let configuration = (0..8).fold(0, |configuration, corner| {
+                            let value = voxel_grid.get(
+                                x + (corner & 1),
+                                y + ((corner >> 1) & 1),
+                                z + (corner >> 2),
+                            );
+
+                            configuration | ((value > settings.iso_level) as usize) << corner
+                        });
+
+                        configurations[configuration] = true;
+                    }
+                }
+            }
+
+            let mesh_data = marching_tetrahedra(&voxel_grid, &settings, &ChunkLod::default());
+            assert!(!mesh_data.indices.is_empty());
+            assert!(mesh_data.is_watertight());
+        }
+
+        // The field goes through every way a cell can be cut by the surface
+        assert!(configurations.iter().all(|seen| *seen));
+    }
+}
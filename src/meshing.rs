@@ -0,0 +1,445 @@
+use bevy::{
+    math::Vec3,
+    prelude::{Component, GlobalTransform, Mesh},
+    render::{
+        mesh::{Indices, PrimitiveTopology, VertexAttributeValues},
+        render_asset::RenderAssetUsages,
+    },
+    utils::HashMap,
+};
+
+use crate::{
+    dual_contouring::dual_contouring,
+    lod::ChunkLod,
+    marching_cubes_cpu::{marching_cubes, VoxelGrid},
+    marching_tetrahedra::marching_tetrahedra,
+    surface_nets::surface_nets,
+    triplanar::ATTRIBUTE_MATERIAL_ID,
+    voxel_value::VoxelValue,
+};
+
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub enum MeshingAlgorithm {
+    #[default]
+    MarchingCubes,
+    SurfaceNets,
+    DualContouring,
+    MarchingTetrahedra,
+}
+
+// Marks an entity whose voxels were edited, the CPU mesher remeshes it and removes the marker
+#[derive(Component, Clone, Copy, Debug, Default)]
+pub struct DirtyChunk;
+
+// Counts the geometry changes of a mesh, colliders are only rebuilt when it moves on
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MeshGeneration(pub u64);
+
+impl MeshGeneration {
+    pub fn next(&self) -> Self {
+        Self(self.0.wrapping_add(1))
+    }
+}
+
+// Per chunk meshing options, the GPU pipeline always uses marching cubes
+#[derive(Component, Clone, Copy, Debug)]
+pub struct MeshingSettings {
+    pub algorithm: MeshingAlgorithm,
+    // Samples with a density above the iso level are inside of the surface
+    pub iso_level: f32,
+    // Dual contouring only, cells with normals further apart than this angle in radians keep
+    // their sharp corner
+    pub sharp_feature_angle: f32,
+}
+
+impl Default for MeshingSettings {
+    fn default() -> Self {
+        Self {
+            algorithm: MeshingAlgorithm::default(),
+            iso_level: 0.5,
+            sharp_feature_angle: std::f32::consts::FRAC_PI_6,
+        }
+    }
+}
+
+pub fn mesh_voxel_grid<V: VoxelValue>(
+    voxel_grid: &VoxelGrid<V>,
+    settings: &MeshingSettings,
+    lod: &ChunkLod,
+) -> MeshData {
+    match settings.algorithm {
+        MeshingAlgorithm::MarchingCubes => marching_cubes(voxel_grid, settings, lod),
+        MeshingAlgorithm::SurfaceNets => surface_nets(voxel_grid, settings, lod),
+        MeshingAlgorithm::DualContouring => dual_contouring(voxel_grid, settings, lod, None),
+        MeshingAlgorithm::MarchingTetrahedra => marching_tetrahedra(voxel_grid, settings, lod),
+    }
+}
+
+#[derive(Default, Clone, Debug)]
+pub struct MeshData {
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    pub uvs: Vec<[f32; 2]>,
+    pub indices: Vec<u32>,
+    // One per vertex, empty when the mesher doesn't assign materials
+    pub material_ids: Vec<u32>,
+}
+
+impl MeshData {
+    // Reads back a mesh created by the meshers, missing normals and uvs are zeroed
+    pub fn from_mesh(mesh: &Mesh) -> Option<Self> {
+        let Some(VertexAttributeValues::Float32x3(positions)) =
+            mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+        else {
+            return None;
+        };
+
+        let normals = match mesh.attribute(Mesh::ATTRIBUTE_NORMAL) {
+            Some(VertexAttributeValues::Float32x3(normals)) => normals.clone(),
+            _ => vec![[0.0; 3]; positions.len()],
+        };
+
+        let uvs = match mesh.attribute(Mesh::ATTRIBUTE_UV_0) {
+            Some(VertexAttributeValues::Float32x2(uvs)) => uvs.clone(),
+            _ => vec![[0.0; 2]; positions.len()],
+        };
+
+        let indices = match mesh.indices() {
+            Some(indices) => indices.iter().map(|index| index as u32).collect(),
+            None => (0..positions.len() as u32).collect(),
+        };
+
+        let material_ids = match mesh.attribute(ATTRIBUTE_MATERIAL_ID) {
+            Some(VertexAttributeValues::Float32x2(ids)) => {
+                ids.iter().map(|id| id[0].round() as u32).collect()
+            }
+            _ => Vec::new(),
+        };
+
+        Some(Self {
+            positions: positions.clone(),
+            normals,
+            uvs,
+            indices,
+            material_ids,
+        })
+    }
+
+    // A new mesh with the buffers that apply_to and append_to expect
+    pub fn to_mesh(&self) -> Mesh {
+        let mut mesh = Mesh::new(
+            PrimitiveTopology::TriangleList,
+            RenderAssetUsages::default(),
+        );
+
+        mesh.insert_indices(Indices::U32(Vec::new()));
+        mesh.insert_attribute(
+            Mesh::ATTRIBUTE_POSITION,
+            VertexAttributeValues::Float32x3(Vec::new()),
+        );
+        mesh.insert_attribute(
+            Mesh::ATTRIBUTE_NORMAL,
+            VertexAttributeValues::Float32x3(Vec::new()),
+        );
+        mesh.insert_attribute(
+            Mesh::ATTRIBUTE_UV_0,
+            VertexAttributeValues::Float32x2(Vec::new()),
+        );
+
+        self.apply_to(&mut mesh);
+
+        mesh
+    }
+
+    pub fn transformed(&self, transform: &GlobalTransform) -> Self {
+        let affine = transform.affine();
+
+        // Normals go through the inverse transpose to stay perpendicular to the surface when the
+        // scale isn't uniform
+        let normal_matrix = affine.matrix3.inverse().transpose();
+
+        Self {
+            positions: self
+                .positions
+                .iter()
+                .map(|p| affine.transform_point3(Vec3::from(*p)).to_array())
+                .collect(),
+            normals: self
+                .normals
+                .iter()
+                .map(|n| {
+                    normal_matrix
+                        .mul_vec3(Vec3::from(*n))
+                        .normalize_or_zero()
+                        .to_array()
+                })
+                .collect(),
+            uvs: self.uvs.clone(),
+            indices: self.indices.clone(),
+            material_ids: self.material_ids.clone(),
+        }
+    }
+
+    // Normals, uvs and materials don't matter for collisions
+    pub fn has_same_geometry(&self, other: &MeshData) -> bool {
+        self.positions == other.positions && self.indices == other.indices
+    }
+
+    pub fn vertex_count(&self) -> usize {
+        self.positions.len()
+    }
+
+    // Every edge has to be shared by exactly two triangles that traverse it in opposite directions.
+    // Vertices are welded by position first, so meshers that duplicate vertices can be checked too
+    pub fn is_watertight(&self) -> bool {
+        let mut welded = HashMap::<[u32; 3], u32>::default();
+
+        let ids = self
+            .positions
+            .iter()
+            .map(|p| {
+                let next_id = welded.len() as u32;
+                *welded.entry(p.map(f32::to_bits)).or_insert(next_id)
+            })
+            .collect::<Vec<u32>>();
+
+        // Number of times each edge is traversed from its lower and from its higher vertex
+        let mut edges = HashMap::<(u32, u32), (u32, u32)>::default();
+
+        for triangle in self.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| ids[triangle[i] as usize]);
+
+            for (from, to) in [(a, b), (b, c), (c, a)] {
+                if from == to {
+                    continue;
+                }
+
+                let count = edges.entry((from.min(to), from.max(to))).or_default();
+
+                if from < to {
+                    count.0 += 1;
+                } else {
+                    count.1 += 1;
+                }
+            }
+        }
+
+        !self.indices.is_empty() && edges.values().all(|count| *count == (1, 1))
+    }
+
+    pub fn push_triangle(&mut self, v0: Vec3, v1: Vec3, v2: Vec3) {
+        let vert_counter = self.positions.len() as u32;
+
+        let normal = (v1 - v0).cross(v2 - v0).normalize_or_zero();
+
+        self.positions.push([v0.x, v0.y, v0.z]);
+        self.positions.push([v1.x, v1.y, v1.z]);
+        self.positions.push([v2.x, v2.y, v2.z]);
+
+        self.indices
+            .extend([vert_counter, vert_counter + 1, vert_counter + 2]);
+
+        self.normals.push([normal.x, normal.y, normal.z]);
+        self.normals.push([normal.x, normal.y, normal.z]);
+        self.normals.push([normal.x, normal.y, normal.z]);
+
+        self.uvs.push([0.0, 0.0]);
+        self.uvs.push([1.0, 0.0]);
+        self.uvs.push([0.0, 1.0]);
+    }
+
+    pub fn push_vertex(&mut self, position: Vec3, normal: Vec3) -> u32 {
+        let index = self.positions.len() as u32;
+
+        self.positions.push([position.x, position.y, position.z]);
+        self.normals.push([normal.x, normal.y, normal.z]);
+        self.uvs.push([0.0, 0.0]);
+
+        index
+    }
+
+    pub fn push_quad(&mut self, quad: [u32; 4]) {
+        self.indices
+            .extend([quad[0], quad[1], quad[2], quad[0], quad[2], quad[3]]);
+    }
+
+    pub fn material_id(&self, vertex: u32) -> u32 {
+        self.material_ids.get(vertex as usize).copied().unwrap_or(0)
+    }
+
+    pub fn extend(&mut self, other: &MeshData) {
+        let offset = self.positions.len() as u32;
+
+        if !self.material_ids.is_empty() || !other.material_ids.is_empty() {
+            self.material_ids.resize(self.positions.len(), 0);
+            self.material_ids
+                .extend((0..other.positions.len() as u32).map(|i| other.material_id(i)));
+        }
+
+        self.positions.extend_from_slice(&other.positions);
+        self.normals.extend_from_slice(&other.normals);
+        self.uvs.extend_from_slice(&other.uvs);
+        self.indices
+            .extend(other.indices.iter().map(|index| index + offset));
+    }
+
+    // Replaces the geometry of a mesh created with position, normal, uv and u32 index buffers
+    pub fn apply_to(&self, mesh: &mut Mesh) {
+        if let Some(VertexAttributeValues::Float32x3(vertices)) =
+            mesh.attribute_mut(Mesh::ATTRIBUTE_POSITION)
+        {
+            vertices.clear();
+        }
+
+        if let Some(Indices::U32(indices)) = mesh.indices_mut() {
+            indices.clear();
+        }
+
+        if let Some(VertexAttributeValues::Float32x3(normals)) =
+            mesh.attribute_mut(Mesh::ATTRIBUTE_NORMAL)
+        {
+            normals.clear();
+        }
+
+        if let Some(VertexAttributeValues::Float32x2(uvs)) =
+            mesh.attribute_mut(Mesh::ATTRIBUTE_UV_0)
+        {
+            uvs.clear();
+        }
+
+        mesh.remove_attribute(ATTRIBUTE_MATERIAL_ID);
+
+        self.append_to(mesh);
+
+        if !self.material_ids.is_empty() {
+            mesh.insert_attribute(
+                ATTRIBUTE_MATERIAL_ID,
+                material_id_values(&self.material_ids, self.positions.len()),
+            );
+        }
+    }
+
+    pub fn append_to(&self, mesh: &mut Mesh) {
+        let mut offset = 0;
+
+        if let Some(VertexAttributeValues::Float32x3(vertices)) =
+            mesh.attribute_mut(Mesh::ATTRIBUTE_POSITION)
+        {
+            offset = vertices.len() as u32;
+            vertices.extend_from_slice(&self.positions);
+        }
+
+        if let Some(Indices::U32(indices)) = mesh.indices_mut() {
+            indices.extend(self.indices.iter().map(|index| index + offset));
+        }
+
+        if let Some(VertexAttributeValues::Float32x3(normals)) =
+            mesh.attribute_mut(Mesh::ATTRIBUTE_NORMAL)
+        {
+            normals.extend_from_slice(&self.normals);
+        }
+
+        if let Some(VertexAttributeValues::Float32x2(uvs)) =
+            mesh.attribute_mut(Mesh::ATTRIBUTE_UV_0)
+        {
+            uvs.extend_from_slice(&self.uvs);
+        }
+
+        if let Some(VertexAttributeValues::Float32x2(ids)) =
+            mesh.attribute_mut(ATTRIBUTE_MATERIAL_ID)
+        {
+            ids.extend(material_id_values(&self.material_ids, self.positions.len()));
+        }
+    }
+}
+
+fn material_id_values(material_ids: &[u32], vertex_count: usize) -> Vec<[f32; 2]> {
+    (0..vertex_count)
+        .map(|i| [material_ids.get(i).copied().unwrap_or(0) as f32, 0.0])
+        .collect()
+}
+
+// Edges are always interpolated from their lower end, so cells that share an edge get bitwise
+// equal vertices no matter in which order they pass the ends
+pub fn interp_vertex(p1: Vec3, p2: Vec3, val1: f32, val2: f32, iso_level: f32) -> Vec3 {
+    if p2.to_array() < p1.to_array() {
+        return interp_vertex(p2, p1, val2, val1, iso_level);
+    }
+
+    if (val2 - val1).abs() < f32::EPSILON {
+        return (p1 + p2) * 0.5;
+    }
+
+    let t = (iso_level - val1) / (val2 - val1);
+    p1 + (p2 - p1) * t
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tetrahedron() -> MeshData {
+        let corners = [Vec3::ZERO, Vec3::X, Vec3::Y, Vec3::Z];
+
+        let mut mesh_data = MeshData::default();
+
+        for [a, b, c] in [[0, 2, 1], [0, 1, 3], [0, 3, 2], [1, 2, 3]] {
+            mesh_data.push_triangle(corners[a], corners[b], corners[c]);
+        }
+
+        mesh_data
+    }
+
+    #[test]
+    fn closed_mesh_with_duplicated_vertices_is_watertight() {
+        assert!(tetrahedron().is_watertight());
+    }
+
+    #[test]
+    fn empty_mesh_is_not_watertight() {
+        assert!(!MeshData::default().is_watertight());
+    }
+
+    #[test]
+    fn missing_triangle_is_not_watertight() {
+        let mut mesh_data = tetrahedron();
+        mesh_data.indices.truncate(9);
+
+        assert!(!mesh_data.is_watertight());
+    }
+
+    #[test]
+    fn flipped_triangle_is_not_watertight() {
+        let mut mesh_data = tetrahedron();
+        mesh_data.indices.swap(0, 1);
+
+        assert!(!mesh_data.is_watertight());
+    }
+
+    #[test]
+    fn edge_shared_by_more_than_two_triangles_is_not_watertight() {
+        // Two traversals of the first triangle's edges in one direction and three in the other
+        let mut mesh_data = tetrahedron();
+        let [a, b, c] = [0, 1, 2].map(|i| mesh_data.indices[i]);
+        mesh_data.indices.extend([a, b, c, c, b, a, c, b, a]);
+
+        assert!(!mesh_data.is_watertight());
+    }
+
+    #[test]
+    fn transformed_normals_stay_perpendicular() {
+        // A slope whose normal is tilted towards the stretched axis
+        let mut mesh_data = MeshData::default();
+        mesh_data.push_triangle(Vec3::ZERO, Vec3::new(1.0, -1.0, 0.0), Vec3::Z);
+
+        let transform = GlobalTransform::from_scale(Vec3::new(4.0, 1.0, 1.0));
+        let transformed = mesh_data.transformed(&transform);
+
+        let [a, b, c] = [0, 1, 2].map(|i| Vec3::from(transformed.positions[i]));
+        let normal = Vec3::from(transformed.normals[0]);
+
+        assert!((normal.length() - 1.0).abs() < 1e-5);
+        assert!(normal.dot(b - a).abs() < 1e-5);
+        assert!(normal.dot(c - a).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,396 @@
+use bevy::{
+    ecs::system::SystemParam,
+    math::Vec3,
+    prelude::{Entity, GlobalTransform, Query},
+};
+
+use crate::{
+    chunk::{Chunk, CHUNK_SZ},
+    marching_cubes_cpu::VoxelGrid,
+    meshing::MeshingSettings,
+    voxel_value::VoxelValue,
+};
+
+#[derive(Clone, Copy, Debug)]
+pub struct VoxelHit {
+    pub entity: Entity,
+    // Sample index of the first voxel above the iso level
+    pub voxel: [usize; 3],
+    // In world space
+    pub point: Vec3,
+    pub normal: Vec3,
+    pub distance: f32,
+}
+
+struct LocalRay {
+    origin: Vec3,
+    direction: Vec3,
+    max_distance: f32,
+}
+
+// A hit in the local space of a grid
+struct LocalHit {
+    voxel: [usize; 3],
+    point: Vec3,
+    normal: Vec3,
+}
+
+// Amanatides-Woo traversal of the voxels around the samples of a grid, every voxel is the cube of
+// one cell size centered on its sample. The first voxel above the iso level is hit, on the face
+// the ray entered through; the point is moved to where the density crosses the iso level between
+// that sample and the one in front of the face.
+fn traverse(
+    resolution: [usize; 3],
+    min: Vec3,
+    cell_size: Vec3,
+    get: impl Fn([usize; 3]) -> f32,
+    ray: LocalRay,
+    iso_level: f32,
+) -> Option<LocalHit> {
+    let LocalRay {
+        origin,
+        direction,
+        max_distance,
+    } = ray;
+    let direction = direction.normalize_or_zero();
+
+    if direction == Vec3::ZERO || resolution.contains(&0) {
+        return None;
+    }
+
+    // Ray in voxel units, voxel i spans i to i + 1
+    let to_voxels = |p: Vec3| (p - min) / cell_size + 0.5;
+    let start = to_voxels(origin);
+    let step_direction = direction / cell_size;
+
+    let size = Vec3::new(
+        resolution[0] as f32,
+        resolution[1] as f32,
+        resolution[2] as f32,
+    );
+
+    // Clip the ray to the grid
+    let mut t_enter = 0.0f32;
+    let mut t_exit = max_distance;
+    let mut entry_axis = None;
+
+    for axis in 0..3 {
+        if step_direction[axis].abs() < f32::EPSILON {
+            if start[axis] < 0.0 || start[axis] > size[axis] {
+                return None;
+            }
+            continue;
+        }
+
+        let t0 = (0.0 - start[axis]) / step_direction[axis];
+        let t1 = (size[axis] - start[axis]) / step_direction[axis];
+        let (near, far) = (t0.min(t1), t0.max(t1));
+
+        if near > t_enter {
+            t_enter = near;
+            entry_axis = Some(axis);
+        }
+        t_exit = t_exit.min(far);
+    }
+
+    if t_enter > t_exit {
+        return None;
+    }
+
+    let entry = start + step_direction * t_enter;
+    let mut voxel =
+        [0, 1, 2].map(|i| (entry[i].floor() as isize).clamp(0, resolution[i] as isize - 1));
+
+    let step = [0, 1, 2].map(|i| step_direction[i].signum() as isize);
+    let t_delta = [0, 1, 2].map(|i| (1.0 / step_direction[i]).abs());
+    let mut t_max = [0, 1, 2].map(|i| {
+        if step_direction[i].abs() < f32::EPSILON {
+            f32::INFINITY
+        } else {
+            let boundary = voxel[i] as f32 + if step[i] > 0 { 1.0 } else { 0.0 };
+            t_enter + (boundary - entry[i]) / step_direction[i]
+        }
+    });
+
+    let mut t = t_enter;
+    let mut axis = entry_axis;
+    let mut previous: Option<[usize; 3]> = None;
+
+    loop {
+        let index = voxel.map(|v| v as usize);
+        let value = get(index);
+
+        if value > iso_level {
+            // The ray started inside, there is no face to report
+            let axis = axis?;
+
+            let mut normal = Vec3::ZERO;
+            normal[axis] = -step[axis] as f32;
+
+            let mut point = origin + direction * t;
+
+            // Interpolate between the sample in front of the face and the hit one, a ray entering
+            // the grid has nothing in front and the face is used as is
+            if let Some(previous) = previous {
+                let outside = get(previous);
+                let s = ((iso_level - outside) / (value - outside)).clamp(0.0, 1.0);
+
+                let from = min[axis] + previous[axis] as f32 * cell_size[axis];
+                let to = min[axis] + index[axis] as f32 * cell_size[axis];
+                point[axis] = from + (to - from) * s;
+            }
+
+            return Some(LocalHit {
+                voxel: index,
+                point,
+                normal,
+            });
+        }
+
+        let next_axis = (0..3)
+            .min_by(|a, b| t_max[*a].total_cmp(&t_max[*b]))
+            .unwrap();
+
+        t = t_max[next_axis];
+
+        if t > t_exit {
+            return None;
+        }
+
+        previous = Some(index);
+        voxel[next_axis] += step[next_axis];
+        t_max[next_axis] += t_delta[next_axis];
+        axis = Some(next_axis);
+
+        if voxel[next_axis] < 0 || voxel[next_axis] >= resolution[next_axis] as isize {
+            return None;
+        }
+    }
+}
+
+// The ray is in world space, distances are world distances along the normalized direction
+fn to_world(entity: Entity, transform: &GlobalTransform, origin: Vec3, hit: LocalHit) -> VoxelHit {
+    let point = transform.transform_point(hit.point);
+
+    VoxelHit {
+        entity,
+        voxel: hit.voxel,
+        point,
+        normal: transform
+            .affine()
+            .transform_vector3(hit.normal)
+            .normalize_or_zero(),
+        distance: point.distance(origin),
+    }
+}
+
+fn local_ray(
+    transform: &GlobalTransform,
+    origin: Vec3,
+    direction: Vec3,
+    max_distance: f32,
+) -> LocalRay {
+    let to_local = transform.affine().inverse();
+    let local_direction = to_local.transform_vector3(direction.normalize_or_zero());
+
+    // Distances scale with the transform
+    LocalRay {
+        origin: to_local.transform_point3(origin),
+        direction: local_direction,
+        max_distance: max_distance * local_direction.length(),
+    }
+}
+
+pub fn raycast_grid<V: VoxelValue>(
+    entity: Entity,
+    voxel_grid: &VoxelGrid<V>,
+    transform: &GlobalTransform,
+    origin: Vec3,
+    direction: Vec3,
+    max_distance: f32,
+    iso_level: f32,
+) -> Option<VoxelHit> {
+    let ray = local_ray(transform, origin, direction, max_distance);
+
+    let hit = traverse(
+        voxel_grid.resolution,
+        voxel_grid.bounds.min,
+        voxel_grid.cell_size(),
+        |[x, y, z]| voxel_grid.get(x, y, z),
+        ray,
+        iso_level,
+    )?;
+
+    Some(to_world(entity, transform, origin, hit))
+}
+
+// Chunk meshes are in voxel units, the GPU mesher uses an iso level of 0.5
+pub fn raycast_chunk(
+    entity: Entity,
+    chunk: &Chunk,
+    transform: &GlobalTransform,
+    origin: Vec3,
+    direction: Vec3,
+    max_distance: f32,
+) -> Option<VoxelHit> {
+    let ray = local_ray(transform, origin, direction, max_distance);
+
+    let hit = traverse(
+        [CHUNK_SZ; 3],
+        Vec3::ZERO,
+        Vec3::ONE,
+        |index| {
+            let voxel = chunk.voxels.get(index);
+
+            // Blocks are solid whatever their density
+            if voxel.flags != 0 {
+                1.0
+            } else {
+                voxel.density
+            }
+        },
+        ray,
+        0.5,
+    )?;
+
+    Some(to_world(entity, transform, origin, hit))
+}
+
+// Nearest hit over several grids, for worlds split into chunks
+pub fn raycast_grids<'a, V: VoxelValue>(
+    grids: impl IntoIterator<Item = (Entity, &'a VoxelGrid<V>, &'a GlobalTransform)>,
+    origin: Vec3,
+    direction: Vec3,
+    max_distance: f32,
+    iso_level: f32,
+) -> Option<VoxelHit> {
+    grids
+        .into_iter()
+        .filter_map(|(entity, voxel_grid, transform)| {
+            raycast_grid(
+                entity,
+                voxel_grid,
+                transform,
+                origin,
+                direction,
+                max_distance,
+                iso_level,
+            )
+        })
+        .min_by(|a, b| a.distance.total_cmp(&b.distance))
+}
+
+// Nearest hit over several chunks
+pub fn raycast_chunks<'a>(
+    chunks: impl IntoIterator<Item = (Entity, &'a Chunk, &'a GlobalTransform)>,
+    origin: Vec3,
+    direction: Vec3,
+    max_distance: f32,
+) -> Option<VoxelHit> {
+    chunks
+        .into_iter()
+        .filter_map(|(entity, chunk, transform)| {
+            raycast_chunk(entity, chunk, transform, origin, direction, max_distance)
+        })
+        .min_by(|a, b| a.distance.total_cmp(&b.distance))
+}
+
+// Raycasts every grid and chunk of the world from a system, without going through colliders.
+// Grids use the iso level of their `MeshingSettings`.
+#[derive(SystemParam)]
+pub struct VoxelRaycast<'w, 's, V: VoxelValue = f32> {
+    grids: Query<
+        'w,
+        's,
+        (
+            Entity,
+            &'static VoxelGrid<V>,
+            &'static GlobalTransform,
+            Option<&'static MeshingSettings>,
+        ),
+    >,
+    chunks: Query<'w, 's, (Entity, &'static Chunk, &'static GlobalTransform)>,
+}
+
+impl<V: VoxelValue> VoxelRaycast<'_, '_, V> {
+    pub fn cast(&self, origin: Vec3, direction: Vec3, max_distance: f32) -> Option<VoxelHit> {
+        let grid_hits =
+            self.grids
+                .iter()
+                .filter_map(|(entity, voxel_grid, transform, settings)| {
+                    raycast_grid(
+                        entity,
+                        voxel_grid,
+                        transform,
+                        origin,
+                        direction,
+                        max_distance,
+                        settings.copied().unwrap_or_default().iso_level,
+                    )
+                });
+
+        let chunk_hit = raycast_chunks(self.chunks.iter(), origin, direction, max_distance);
+
+        grid_hits
+            .chain(chunk_hit)
+            .min_by(|a, b| a.distance.total_cmp(&b.distance))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{
+        ecs::system::RunSystemOnce,
+        math::IVec3,
+        prelude::{Transform, World},
+    };
+
+    use super::*;
+    use crate::{chunk::Voxel, marching_cubes_cpu::Bounds, sparse::SparseGrid};
+
+    #[test]
+    fn cast_returns_the_nearest_grid_or_chunk() {
+        let mut world = World::new();
+
+        // A solid chunk from x = 10 and a solid grid from x = 5
+        let mut chunk = Chunk::new(IVec3::ZERO);
+        chunk.voxels.fill(Voxel {
+            flags: 0,
+            density: 1.0,
+        });
+
+        let chunk = world
+            .spawn((
+                chunk,
+                GlobalTransform::from(Transform::from_xyz(10.0, -16.0, -16.0)),
+            ))
+            .id();
+
+        let grid = world
+            .spawn((
+                VoxelGrid {
+                    resolution: [4; 3],
+                    data: SparseGrid::new([4; 3], 1.0),
+                    bounds: Bounds {
+                        min: Vec3::ZERO,
+                        max: Vec3::splat(4.0),
+                    },
+                },
+                GlobalTransform::from(Transform::from_xyz(5.0, -2.0, -2.0)),
+            ))
+            .id();
+
+        let hit =
+            world.run_system_once(|raycast: VoxelRaycast| raycast.cast(Vec3::ZERO, Vec3::X, 100.0));
+        assert_eq!(hit.map(|hit| hit.entity), Some(grid));
+
+        world.entity_mut(grid).despawn();
+
+        let hit = world
+            .run_system_once(|raycast: VoxelRaycast| raycast.cast(Vec3::ZERO, Vec3::X, 100.0))
+            .unwrap();
+        assert_eq!(hit.entity, chunk);
+        assert_eq!(hit.normal, Vec3::NEG_X);
+        assert!((hit.point.x - 9.5).abs() < 1e-4);
+    }
+}
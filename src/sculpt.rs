@@ -0,0 +1,363 @@
+use bevy::{
+    app::{App, Plugin, Update},
+    math::{Ray3d, Vec3},
+    prelude::{
+        Commands, Entity, GlobalTransform, IntoSystemConfigs, Local, Query, Res, ResMut, Resource,
+    },
+    time::Time,
+};
+
+use crate::{
+    dual_contouring::HermiteData,
+    history::{EditHistory, VoxelChange},
+    marching_cubes_cpu::VoxelGrid,
+    meshing::{DirtyChunk, MeshingSettings},
+    raycast::{raycast_grid, VoxelHit},
+    voxel_value::{VoxelValue, F16},
+};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BrushMode {
+    #[default]
+    Add,
+    Remove,
+    // Moves every sample towards the average of its neighbours
+    Smooth,
+    // Moves the surface towards the plane through the hit point
+    Flatten,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BrushShape {
+    #[default]
+    Sphere,
+    Cube,
+}
+
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct SculptSettings {
+    pub mode: BrushMode,
+    pub shape: BrushShape,
+    // In world units
+    pub radius: f32,
+    // Change of density per second at the center of the brush
+    pub strength: f32,
+}
+
+impl Default for SculptSettings {
+    fn default() -> Self {
+        Self {
+            mode: BrushMode::default(),
+            shape: BrushShape::default(),
+            radius: 0.5,
+            strength: 2.0,
+        }
+    }
+}
+
+// The ray the brush is aimed along and the nearest voxel it hit this frame, in world space. The
+// app sets the ray, the editor plugins cast it through the cursor.
+#[derive(Resource, Clone, Copy, Debug, Default)]
+pub struct BrushHit {
+    pub ray: Option<Ray3d>,
+    pub hit: Option<VoxelHit>,
+}
+
+// The brush sculpts every frame the stroke is active, a stroke is undone as a whole
+#[derive(Resource, Clone, Copy, Debug, Default)]
+pub struct BrushStroke {
+    pub active: bool,
+}
+
+// Sculpts `VoxelGrid` entities. Chunks meshed on the GPU aren't sculpted, their edits go through
+// `Chunk::voxels` directly. Input is left to the app or `VoxelEditorPlugins`.
+pub struct SculptPlugin;
+
+impl Plugin for SculptPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SculptSettings>()
+            .init_resource::<BrushHit>()
+            .init_resource::<BrushStroke>()
+            .init_resource::<EditHistory>()
+            .add_systems(
+                Update,
+                (
+                    clear_brush_hit_system,
+                    (
+                        pick_voxels_system::<f32>,
+                        pick_voxels_system::<u8>,
+                        pick_voxels_system::<i8>,
+                        pick_voxels_system::<F16>,
+                    ),
+                    brush_stroke_system,
+                    (
+                        sculpt_system::<f32>,
+                        sculpt_system::<u8>,
+                        sculpt_system::<i8>,
+                        sculpt_system::<F16>,
+                    ),
+                )
+                    .chain(),
+            );
+    }
+}
+
+pub fn clear_brush_hit_system(mut brush_hit: ResMut<BrushHit>) {
+    brush_hit.hit = None;
+}
+
+// Traverses the voxels directly, so grids can be picked before they have a mesh or collider
+pub fn pick_voxels_system<V: VoxelValue>(
+    mut brush_hit: ResMut<BrushHit>,
+    query: Query<(
+        Entity,
+        &VoxelGrid<V>,
+        &GlobalTransform,
+        Option<&MeshingSettings>,
+    )>,
+) {
+    let Some(ray) = brush_hit.ray else {
+        return;
+    };
+
+    for (entity, voxel_grid, transform, settings) in query.iter() {
+        let iso_level = settings.copied().unwrap_or_default().iso_level;
+
+        let Some(hit) = raycast_grid(
+            entity,
+            voxel_grid,
+            transform,
+            ray.origin,
+            *ray.direction,
+            f32::MAX,
+            iso_level,
+        ) else {
+            continue;
+        };
+
+        if brush_hit
+            .hit
+            .map_or(true, |nearest| hit.distance < nearest.distance)
+        {
+            brush_hit.hit = Some(hit);
+        }
+    }
+}
+
+pub fn brush_stroke_system(
+    mut history: ResMut<EditHistory>,
+    stroke: Res<BrushStroke>,
+    mut was_active: Local<bool>,
+) {
+    if stroke.active && !*was_active {
+        history.begin("brush stroke");
+    }
+
+    if !stroke.active && *was_active {
+        history.commit();
+    }
+
+    *was_active = stroke.active;
+}
+
+pub fn sculpt_system<V: VoxelValue>(
+    mut commands: Commands,
+    mut history: ResMut<EditHistory>,
+    mut query: Query<(
+        Entity,
+        &mut VoxelGrid<V>,
+        &GlobalTransform,
+        Option<&MeshingSettings>,
+    )>,
+    settings: Res<SculptSettings>,
+    brush_hit: Res<BrushHit>,
+    stroke: Res<BrushStroke>,
+    time: Res<Time>,
+) {
+    if !stroke.active {
+        return;
+    }
+
+    let Some(VoxelHit { point, normal, .. }) = brush_hit.hit else {
+        return;
+    };
+
+    for (entity, mut voxel_grid, transform, meshing_settings) in query.iter_mut() {
+        let to_local = transform.affine().inverse();
+
+        let brush = Brush {
+            center: to_local.transform_point3(point),
+            normal: to_local.transform_vector3(normal).normalize_or_zero(),
+            radius: settings.radius * to_local.transform_vector3(Vec3::X).length(),
+            amount: settings.strength * time.delta_seconds(),
+            mode: settings.mode,
+            shape: settings.shape,
+            iso_level: meshing_settings.copied().unwrap_or_default().iso_level,
+        };
+
+        // Only borrow the grid mutably when the brush touches it, so untouched grids aren't
+        // flagged as changed
+        if brush.sample_range(&voxel_grid).is_none() {
+            continue;
+        }
+
+        let changes = apply_brush(&mut voxel_grid, &brush);
+
+        if !changes.is_empty() {
+            history.record_all(entity, changes);
+            // Edge intersections of the old surface would keep dual contouring from meshing the
+            // new one
+            commands
+                .entity(entity)
+                .insert(DirtyChunk)
+                .remove::<HermiteData>();
+        }
+    }
+}
+
+// A brush in the local space of a grid
+#[derive(Clone, Copy, Debug)]
+pub struct Brush {
+    pub center: Vec3,
+    // Out of the surface
+    pub normal: Vec3,
+    pub radius: f32,
+    pub amount: f32,
+    pub mode: BrushMode,
+    pub shape: BrushShape,
+    // Flattening puts the surface of this iso level on the plane
+    pub iso_level: f32,
+}
+
+impl Brush {
+    // Falloff from 1 at the center to 0 at the radius
+    fn weight(&self, position: Vec3) -> f32 {
+        let offset = (position - self.center) / self.radius.max(f32::EPSILON);
+
+        let distance = match self.shape {
+            BrushShape::Sphere => offset.length(),
+            BrushShape::Cube => offset.abs().max_element(),
+        };
+
+        (1.0 - distance * distance).max(0.0)
+    }
+
+    // Samples covered by the brush, None when it misses the grid
+    fn sample_range<V: VoxelValue>(
+        &self,
+        voxel_grid: &VoxelGrid<V>,
+    ) -> Option<([usize; 3], [usize; 3])> {
+        let cell_size = voxel_grid.cell_size();
+
+        let min = (self.center - self.radius - voxel_grid.bounds.min) / cell_size;
+        let max = (self.center + self.radius - voxel_grid.bounds.min) / cell_size;
+
+        let mut range = ([0; 3], [0; 3]);
+
+        for axis in 0..3 {
+            let last = voxel_grid.resolution[axis].checked_sub(1)? as f32;
+
+            if max[axis] < 0.0 || min[axis] > last {
+                return None;
+            }
+
+            range.0[axis] = min[axis].ceil().clamp(0.0, last) as usize;
+            range.1[axis] = max[axis].floor().clamp(0.0, last) as usize;
+        }
+
+        Some(range)
+    }
+}
+
+// Returns the samples that changed
+pub fn apply_brush<V: VoxelValue>(
+    voxel_grid: &mut VoxelGrid<V>,
+    brush: &Brush,
+) -> Vec<VoxelChange> {
+    let Some((min, max)) = brush.sample_range(voxel_grid) else {
+        return Vec::new();
+    };
+
+    let cell_size = voxel_grid.cell_size();
+    let resolution = voxel_grid.resolution;
+
+    let (min_density, max_density) = (V::MIN_DENSITY, V::MAX_DENSITY);
+
+    let get =
+        |voxel_grid: &VoxelGrid<V>, index: [usize; 3]| voxel_grid.get(index[0], index[1], index[2]);
+
+    // New values are computed from the unmodified grid first, smoothing reads the neighbours
+    let mut changes = Vec::new();
+
+    for z in min[2]..=max[2] {
+        for y in min[1]..=max[1] {
+            for x in min[0]..=max[0] {
+                let index = [x, y, z];
+                let position = voxel_grid.position(index);
+
+                let weight = brush.weight(position);
+
+                if weight <= 0.0 {
+                    continue;
+                }
+
+                let value = get(voxel_grid, index);
+                let amount = (brush.amount * weight).min(1.0);
+
+                let new_value = match brush.mode {
+                    BrushMode::Add => (value + amount).min(max_density),
+                    BrushMode::Remove => (value - amount).max(min_density),
+                    BrushMode::Smooth => {
+                        let mut sum = 0.0;
+                        let mut count = 0;
+
+                        for axis in 0..3 {
+                            for direction in [-1isize, 1] {
+                                let neighbour = index[axis] as isize + direction;
+
+                                if neighbour < 0 || neighbour >= resolution[axis] as isize {
+                                    continue;
+                                }
+
+                                let mut neighbour_index = index;
+                                neighbour_index[axis] = neighbour as usize;
+
+                                sum += get(voxel_grid, neighbour_index);
+                                count += 1;
+                            }
+                        }
+
+                        let average = if count > 0 { sum / count as f32 } else { value };
+
+                        value + (average - value) * amount
+                    }
+                    BrushMode::Flatten => {
+                        // Density that puts the iso surface on the plane, going through the
+                        // whole range over one cell
+                        let distance =
+                            (position - brush.center).dot(brush.normal) / cell_size.max_element();
+                        let target = (brush.iso_level - distance * (max_density - min_density))
+                            .clamp(min_density, max_density);
+
+                        value + (target - value) * amount
+                    }
+                };
+
+                if new_value != value {
+                    changes.push(VoxelChange {
+                        index,
+                        before: value,
+                        after: new_value,
+                    });
+                }
+            }
+        }
+    }
+
+    for change in changes.iter() {
+        let [x, y, z] = change.index;
+        voxel_grid.set(x, y, z, change.after);
+    }
+
+    changes
+}
@@ -0,0 +1,527 @@
+use std::mem::size_of;
+
+use crate::{marching_cubes_cpu::VoxelGrid, voxel_value::VoxelValue};
+
+// Edge length of a brick, mixed regions are stored at this granularity
+pub const BRICK_SZ: usize = 8;
+pub const BRICK_SZ_3: usize = BRICK_SZ * BRICK_SZ * BRICK_SZ;
+
+// Indices are at most a byte, bricks with more distinct values are stored densely
+const MAX_PALETTE_LEN: usize = 256;
+
+// Indices packed into words, the bit widths are powers of two so an index never straddles words
+#[derive(Clone, Debug)]
+struct PackedIndices {
+    bits: usize,
+    words: Box<[u64]>,
+}
+
+impl PackedIndices {
+    fn new(bits: usize) -> Self {
+        Self {
+            bits,
+            words: vec![0; BRICK_SZ_3 * bits / 64].into_boxed_slice(),
+        }
+    }
+
+    fn bits_for(palette_len: usize) -> usize {
+        match palette_len {
+            0..=2 => 1,
+            3..=4 => 2,
+            5..=16 => 4,
+            _ => 8,
+        }
+    }
+
+    fn get(&self, i: usize) -> usize {
+        let per_word = 64 / self.bits;
+        let shift = (i % per_word) * self.bits;
+
+        ((self.words[i / per_word] >> shift) & ((1 << self.bits) - 1)) as usize
+    }
+
+    fn set(&mut self, i: usize, index: usize) {
+        let per_word = 64 / self.bits;
+        let shift = (i % per_word) * self.bits;
+        let mask = ((1u64 << self.bits) - 1) << shift;
+
+        let word = &mut self.words[i / per_word];
+        *word = (*word & !mask) | ((index as u64) << shift);
+    }
+
+    fn repacked(&self, bits: usize) -> Self {
+        let mut repacked = Self::new(bits);
+
+        for i in 0..BRICK_SZ_3 {
+            repacked.set(i, self.get(i));
+        }
+
+        repacked
+    }
+}
+
+#[derive(Clone, Debug)]
+struct PaletteBrick<T> {
+    palette: Vec<T>,
+    indices: PackedIndices,
+}
+
+impl<T: Copy + PartialEq> PaletteBrick<T> {
+    fn new(fill: T) -> Self {
+        Self {
+            palette: vec![fill],
+            indices: PackedIndices::new(1),
+        }
+    }
+
+    fn get(&self, local: usize) -> T {
+        self.palette[self.indices.get(local)]
+    }
+
+    // Values that are overwritten stay in the palette until the grid is optimized. A value that
+    // isn't in a full palette doesn't fit, the brick has to be made dense first.
+    fn set(&mut self, local: usize, value: T) {
+        let index = match self.palette.iter().position(|v| *v == value) {
+            Some(index) => index,
+            None => {
+                self.palette.push(value);
+
+                let bits = PackedIndices::bits_for(self.palette.len());
+                if bits != self.indices.bits {
+                    self.indices = self.indices.repacked(bits);
+                }
+
+                self.palette.len() - 1
+            }
+        };
+
+        self.indices.set(local, index);
+    }
+
+    fn is_full_for(&self, value: T) -> bool {
+        self.palette.len() == MAX_PALETTE_LEN && !self.palette.contains(&value)
+    }
+
+    fn to_dense(&self) -> Box<[T]> {
+        (0..BRICK_SZ_3).map(|i| self.get(i)).collect()
+    }
+
+    fn memory_size(&self) -> usize {
+        self.palette.capacity() * size_of::<T>() + self.indices.words.len() * size_of::<u64>()
+    }
+
+    // Storing the brick densely takes less memory
+    fn is_too_large(&self) -> bool {
+        self.memory_size() >= BRICK_SZ_3 * size_of::<T>()
+    }
+}
+
+// Mixed bricks are palette compressed, or dense when they have too many distinct values
+#[derive(Clone, Debug)]
+enum Brick<T> {
+    Uniform(T),
+    Palette(PaletteBrick<T>),
+    Dense(Box<[T]>),
+}
+
+impl<T: Copy + PartialEq> Brick<T> {
+    fn get(&self, local: usize) -> T {
+        match self {
+            Brick::Uniform(value) => *value,
+            Brick::Palette(palette) => palette.get(local),
+            Brick::Dense(values) => values[local],
+        }
+    }
+
+    fn memory_size(&self) -> usize {
+        size_of::<Self>()
+            + match self {
+                Brick::Uniform(_) => 0,
+                Brick::Palette(palette) => palette.memory_size(),
+                Brick::Dense(values) => values.len() * size_of::<T>(),
+            }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Storage<T> {
+    Uniform(T),
+    Bricks(Vec<Brick<T>>),
+}
+
+// A 3D array that stores uniform regions as a single value. The whole grid collapses to one value
+// when it is uniform, otherwise it is split into bricks that are uniform, palette compressed or
+// dense. Reads and writes go through `get` and `set` whatever the representation is, meshing on
+// the GPU expands it with `to_dense`.
+#[derive(Clone, Debug)]
+pub struct SparseGrid<T> {
+    dimensions: [usize; 3],
+    bricks: [usize; 3],
+    storage: Storage<T>,
+}
+
+impl<T: Copy + PartialEq> SparseGrid<T> {
+    pub fn new(dimensions: [usize; 3], value: T) -> Self {
+        Self {
+            dimensions,
+            bricks: dimensions.map(|d| d.div_ceil(BRICK_SZ)),
+            storage: Storage::Uniform(value),
+        }
+    }
+
+    // `data` is indexed with x varying fastest
+    pub fn from_dense(dimensions: [usize; 3], data: &[T]) -> Self {
+        assert_eq!(data.len(), dimensions.iter().product::<usize>());
+
+        let Some(first) = data.first() else {
+            return Self {
+                dimensions,
+                bricks: [0; 3],
+                storage: Storage::Bricks(Vec::new()),
+            };
+        };
+
+        let mut grid = Self::new(dimensions, *first);
+
+        let bricks = (0..grid.brick_count())
+            .map(|brick| {
+                let origin = grid.brick_origin(brick);
+
+                // Padding outside of the grid repeats the first value of the brick
+                let fill = data[grid.flat_index(origin)];
+                let mut values = vec![fill; BRICK_SZ_3].into_boxed_slice();
+
+                grid.for_each_in_brick(brick, |index, local| {
+                    values[local] = data[grid.flat_index(index)];
+                });
+
+                Brick::Dense(values)
+            })
+            .collect();
+
+        grid.storage = Storage::Bricks(bricks);
+        grid.optimize();
+        grid
+    }
+
+    pub fn dimensions(&self) -> [usize; 3] {
+        self.dimensions
+    }
+
+    pub fn len(&self) -> usize {
+        self.dimensions.iter().product()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn flat_index(&self, index: [usize; 3]) -> usize {
+        index[0]
+            + index[1] * self.dimensions[0]
+            + index[2] * self.dimensions[0] * self.dimensions[1]
+    }
+
+    fn brick_count(&self) -> usize {
+        self.bricks.iter().product()
+    }
+
+    fn brick_origin(&self, brick: usize) -> [usize; 3] {
+        [
+            brick % self.bricks[0] * BRICK_SZ,
+            brick / self.bricks[0] % self.bricks[1] * BRICK_SZ,
+            brick / (self.bricks[0] * self.bricks[1]) * BRICK_SZ,
+        ]
+    }
+
+    fn locate(&self, index: [usize; 3]) -> (usize, usize) {
+        let brick = index.map(|i| i / BRICK_SZ);
+        let local = index.map(|i| i % BRICK_SZ);
+
+        (
+            brick[0] + brick[1] * self.bricks[0] + brick[2] * self.bricks[0] * self.bricks[1],
+            local[0] + local[1] * BRICK_SZ + local[2] * BRICK_SZ * BRICK_SZ,
+        )
+    }
+
+    // Visits the samples of a brick that lie inside of the grid
+    fn for_each_in_brick(&self, brick: usize, mut f: impl FnMut([usize; 3], usize)) {
+        let origin = self.brick_origin(brick);
+        let extent = [0, 1, 2].map(|i| BRICK_SZ.min(self.dimensions[i] - origin[i]));
+
+        for z in 0..extent[2] {
+            for y in 0..extent[1] {
+                for x in 0..extent[0] {
+                    f(
+                        [origin[0] + x, origin[1] + y, origin[2] + z],
+                        x + y * BRICK_SZ + z * BRICK_SZ * BRICK_SZ,
+                    );
+                }
+            }
+        }
+    }
+
+    pub fn get(&self, index: [usize; 3]) -> T {
+        debug_assert!((0..3).all(|i| index[i] < self.dimensions[i]));
+
+        match &self.storage {
+            Storage::Uniform(value) => *value,
+            Storage::Bricks(bricks) => {
+                let (brick, local) = self.locate(index);
+
+                bricks[brick].get(local)
+            }
+        }
+    }
+
+    pub fn set(&mut self, index: [usize; 3], value: T) {
+        debug_assert!((0..3).all(|i| index[i] < self.dimensions[i]));
+
+        let (brick, local) = self.locate(index);
+        let brick_count = self.brick_count();
+
+        if let Storage::Uniform(uniform) = self.storage {
+            if uniform == value {
+                return;
+            }
+
+            self.storage = Storage::Bricks(vec![Brick::Uniform(uniform); brick_count]);
+        }
+
+        let Storage::Bricks(bricks) = &mut self.storage else {
+            unreachable!();
+        };
+
+        match &mut bricks[brick] {
+            Brick::Uniform(uniform) => {
+                if *uniform != value {
+                    let mut palette = PaletteBrick::new(*uniform);
+                    palette.set(local, value);
+                    bricks[brick] = Brick::Palette(palette);
+                }
+            }
+            Brick::Palette(palette) => {
+                if palette.is_full_for(value) {
+                    let mut values = palette.to_dense();
+                    values[local] = value;
+                    bricks[brick] = Brick::Dense(values);
+                    return;
+                }
+
+                palette.set(local, value);
+
+                if palette.is_too_large() {
+                    bricks[brick] = Brick::Dense(palette.to_dense());
+                }
+            }
+            Brick::Dense(values) => values[local] = value,
+        }
+    }
+
+    pub fn fill(&mut self, value: T) {
+        self.storage = Storage::Uniform(value);
+    }
+
+    // The value of the grid if every sample has it
+    pub fn uniform(&self) -> Option<T> {
+        match &self.storage {
+            Storage::Uniform(value) => Some(*value),
+            Storage::Bricks(_) => None,
+        }
+    }
+
+    // The value of the samples from `min` to `max` inclusive if they all have the same one, only
+    // looks at whole bricks so a mixed brick is never reported as uniform
+    pub fn region_uniform(&self, min: [usize; 3], max: [usize; 3]) -> Option<T> {
+        let bricks = match &self.storage {
+            Storage::Uniform(value) => return Some(*value),
+            Storage::Bricks(bricks) => bricks,
+        };
+
+        let max = [0, 1, 2].map(|i| max[i].min(self.dimensions[i].saturating_sub(1)));
+        let mut result = None;
+
+        for z in min[2] / BRICK_SZ..=max[2] / BRICK_SZ {
+            for y in min[1] / BRICK_SZ..=max[1] / BRICK_SZ {
+                for x in min[0] / BRICK_SZ..=max[0] / BRICK_SZ {
+                    let brick = x + y * self.bricks[0] + z * self.bricks[0] * self.bricks[1];
+
+                    let Brick::Uniform(value) = bricks[brick] else {
+                        return None;
+                    };
+
+                    match result {
+                        Some(previous) if previous != value => return None,
+                        _ => result = Some(value),
+                    }
+                }
+            }
+        }
+
+        result
+    }
+
+    // Collapses bricks whose samples are all equal, rebuilds the palettes of the others without
+    // values that were overwritten, and collapses the whole grid if every brick ends up with the
+    // same value. Edits only ever split bricks, so this should run after bulk edits.
+    pub fn optimize(&mut self) {
+        let Storage::Bricks(bricks) = &self.storage else {
+            return;
+        };
+
+        let mut collapsed = bricks.clone();
+
+        for (brick, entry) in collapsed.iter_mut().enumerate() {
+            if let Brick::Uniform(_) = entry {
+                continue;
+            }
+
+            let mut palette = Vec::<T>::new();
+            let mut fits_palette = true;
+
+            self.for_each_in_brick(brick, |_, local| {
+                let value = entry.get(local);
+
+                if fits_palette && !palette.contains(&value) {
+                    palette.push(value);
+                    fits_palette = palette.len() <= MAX_PALETTE_LEN;
+                }
+            });
+
+            if palette.len() == 1 {
+                *entry = Brick::Uniform(palette[0]);
+                continue;
+            }
+
+            if !fits_palette {
+                continue;
+            }
+
+            let mut compressed = PaletteBrick {
+                indices: PackedIndices::new(PackedIndices::bits_for(palette.len())),
+                palette,
+            };
+
+            self.for_each_in_brick(brick, |_, local| {
+                let value = entry.get(local);
+                let index = compressed.palette.iter().position(|v| *v == value);
+                compressed.indices.set(local, index.unwrap_or(0));
+            });
+
+            *entry = if compressed.is_too_large() {
+                Brick::Dense((0..BRICK_SZ_3).map(|i| entry.get(i)).collect())
+            } else {
+                Brick::Palette(compressed)
+            };
+        }
+
+        let first = match collapsed.first() {
+            Some(Brick::Uniform(value)) => Some(*value),
+            _ => None,
+        };
+
+        self.storage = match first {
+            Some(first)
+                if collapsed
+                    .iter()
+                    .all(|brick| matches!(brick, Brick::Uniform(value) if *value == first)) =>
+            {
+                Storage::Uniform(first)
+            }
+            _ => Storage::Bricks(collapsed),
+        };
+    }
+
+    pub fn to_dense(&self) -> Vec<T> {
+        self.iter().collect()
+    }
+
+    // Samples in the same order as `from_dense`
+    pub fn iter(&self) -> impl Iterator<Item = T> + '_ {
+        let [dx, dy, dz] = self.dimensions;
+
+        (0..dz)
+            .flat_map(move |z| (0..dy).flat_map(move |y| (0..dx).map(move |x| self.get([x, y, z]))))
+    }
+
+    // Approximate heap and inline size in bytes
+    pub fn memory_size(&self) -> usize {
+        size_of::<Self>()
+            + match &self.storage {
+                Storage::Uniform(_) => 0,
+                Storage::Bricks(bricks) => bricks.iter().map(Brick::memory_size).sum(),
+            }
+    }
+
+    // Calls `f` with the first sample of every cell from 0 to `last` that isn't inside of a
+    // uniform region. Cells are visited in blocks the size of a brick, a block whose samples are
+    // all in uniform bricks of the same value can't contain the surface and is skipped as a whole.
+    pub fn for_each_active_cell(
+        &self,
+        stride: usize,
+        last: [usize; 3],
+        mut f: impl FnMut([usize; 3]),
+    ) {
+        let block = (BRICK_SZ / stride).max(1) * stride;
+
+        for bz in (0..last[2]).step_by(block) {
+            for by in (0..last[1]).step_by(block) {
+                for bx in (0..last[0]).step_by(block) {
+                    let min = [bx, by, bz];
+                    let max = [0, 1, 2].map(|i| (min[i] + block).min(last[i]));
+
+                    if self.region_uniform(min, max).is_some() {
+                        continue;
+                    }
+
+                    for zi in (bz..(bz + block).min(last[2])).step_by(stride) {
+                        for yi in (by..(by + block).min(last[1])).step_by(stride) {
+                            for xi in (bx..(bx + block).min(last[0])).step_by(stride) {
+                                f([xi, yi, zi]);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Calls `f` with the first sample of every cell from 0 to `last` that isn't inside of a uniform
+// region of the grid, see `SparseGrid::for_each_active_cell`
+pub fn for_each_active_cell<V: VoxelValue>(
+    voxel_grid: &VoxelGrid<V>,
+    stride: usize,
+    last: [usize; 3],
+    f: impl FnMut([usize; 3]),
+) {
+    voxel_grid.data.for_each_active_cell(stride, last, f);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn brick_with_more_values_than_a_palette_holds() {
+        let mut grid = SparseGrid::new([BRICK_SZ; 3], 0.0f32);
+
+        let index = |i: usize| {
+            [
+                i % BRICK_SZ,
+                i / BRICK_SZ % BRICK_SZ,
+                i / BRICK_SZ / BRICK_SZ,
+            ]
+        };
+
+        for i in 0..300 {
+            grid.set(index(i), i as f32 + 1.0);
+        }
+
+        for i in 0..300 {
+            assert_eq!(grid.get(index(i)), i as f32 + 1.0);
+        }
+
+        assert_eq!(grid.get(index(300)), 0.0);
+    }
+}
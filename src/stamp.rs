@@ -0,0 +1,562 @@
+use bevy::{
+    app::{App, Plugin, Update},
+    log::info,
+    math::{Affine3A, Quat, Vec3},
+    prelude::{
+        Commands, Entity, GlobalTransform, IntoSystemConfigs, Query, Res, ResMut, Resource,
+        Transform,
+    },
+};
+
+use crate::{
+    chunk::Chunk,
+    dual_contouring::HermiteData,
+    history::{EditHistory, VoxelChange},
+    marching_cubes_cpu::{Bounds, VoxelGrid},
+    meshing::DirtyChunk,
+    sculpt::BrushHit,
+    sparse::SparseGrid,
+    voxel_value::{VoxelValue, F16},
+};
+
+// Positions closer than this to a sample, in samples, read it directly instead of interpolating
+const SNAP_EPSILON: f32 = 1e-3;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BlendMode {
+    #[default]
+    Replace,
+    // Keeps the denser of the two
+    Union,
+    // Carves the prefab out of the target
+    Subtract,
+}
+
+impl BlendMode {
+    // Subtracting mirrors the prefab within the value range of the target, so solid carves air
+    pub fn blend<V: VoxelValue>(&self, target: f32, prefab: f32) -> f32 {
+        match self {
+            BlendMode::Replace => prefab,
+            BlendMode::Union => target.max(prefab),
+            BlendMode::Subtract => target.min(V::MIN_DENSITY + V::MAX_DENSITY - prefab),
+        }
+    }
+}
+
+// A copied region, `coverage` marks the samples that were copied from a grid. The rest of the
+// prefab lay outside of every grid and is left alone when stamping.
+#[derive(Clone)]
+pub struct Prefab {
+    pub grid: VoxelGrid,
+    pub coverage: SparseGrid<bool>,
+}
+
+impl Prefab {
+    // Whether every sample read at `local` was copied
+    fn covers(&self, local: Vec3) -> bool {
+        let Some(coordinates) = sample_coordinates(&self.grid, local) else {
+            return false;
+        };
+
+        let last = self.grid.resolution.map(|r| r - 1);
+        let rounded = coordinates.round();
+
+        let (min, max) = if (coordinates - rounded).abs().max_element() < SNAP_EPSILON {
+            (rounded, rounded)
+        } else {
+            (coordinates.floor(), coordinates.ceil())
+        };
+
+        let clamp = |value: f32, axis: usize| (value.max(0.0) as usize).min(last[axis]);
+
+        (clamp(min.z, 2)..=clamp(max.z, 2)).all(|z| {
+            (clamp(min.y, 1)..=clamp(max.y, 1))
+                .all(|y| (clamp(min.x, 0)..=clamp(max.x, 0)).all(|x| self.coverage.get([x, y, z])))
+        })
+    }
+}
+
+// An empty prefab covering a world space region, centered on the origin of its local space so it
+// rotates around its center
+pub fn prefab_for_region(min: Vec3, max: Vec3, cell_size: f32) -> Prefab {
+    let extent = (max - min).abs();
+    let resolution = [0, 1, 2].map(|i| (extent[i] / cell_size).round() as usize + 1);
+
+    let half = Vec3::new(
+        (resolution[0] - 1) as f32,
+        (resolution[1] - 1) as f32,
+        (resolution[2] - 1) as f32,
+    ) * cell_size
+        * 0.5;
+
+    let min = -half;
+    let max = min
+        + Vec3::new(
+            resolution[0] as f32,
+            resolution[1] as f32,
+            resolution[2] as f32,
+        ) * cell_size;
+
+    Prefab {
+        grid: VoxelGrid {
+            resolution,
+            data: SparseGrid::new(resolution, 0.0),
+            bounds: Bounds { min, max },
+        },
+        coverage: SparseGrid::new(resolution, false),
+    }
+}
+
+// Position of a point in samples, None when it lies outside of the samples of the grid
+fn sample_coordinates<V: VoxelValue>(voxel_grid: &VoxelGrid<V>, local: Vec3) -> Option<Vec3> {
+    let coordinates = (local - voxel_grid.bounds.min) / voxel_grid.cell_size();
+
+    let inside = (0..3).all(|i| {
+        coordinates[i] >= -SNAP_EPSILON
+            && coordinates[i] <= (voxel_grid.resolution[i] - 1) as f32 + SNAP_EPSILON
+    });
+
+    inside.then_some(coordinates)
+}
+
+// Quarter turns line samples up with samples, those read them directly. Anything else is
+// resampled with trilinear interpolation.
+fn read<V: VoxelValue>(voxel_grid: &VoxelGrid<V>, local: Vec3) -> Option<f32> {
+    let coordinates = sample_coordinates(voxel_grid, local)?;
+    let rounded = coordinates.round();
+
+    if (coordinates - rounded).abs().max_element() < SNAP_EPSILON {
+        let last = voxel_grid.resolution.map(|r| r - 1);
+
+        return Some(voxel_grid.get(
+            (rounded.x.max(0.0) as usize).min(last[0]),
+            (rounded.y.max(0.0) as usize).min(last[1]),
+            (rounded.z.max(0.0) as usize).min(last[2]),
+        ));
+    }
+
+    Some(voxel_grid.sample(local))
+}
+
+// Copies the part of `source` that overlaps the prefab, the prefab is centered on `center` in
+// world space. Returns the number of samples copied.
+pub fn copy_into_prefab<V: VoxelValue>(
+    prefab: &mut Prefab,
+    center: Vec3,
+    source: &VoxelGrid<V>,
+    source_transform: &GlobalTransform,
+) -> usize {
+    let to_source = source_transform.affine().inverse();
+    let mut copied = 0;
+
+    for z in 0..prefab.grid.resolution[2] {
+        for y in 0..prefab.grid.resolution[1] {
+            for x in 0..prefab.grid.resolution[0] {
+                let world = center + prefab.grid.position([x, y, z]);
+
+                if let Some(value) = read(source, to_source.transform_point3(world)) {
+                    prefab.grid.set(x, y, z, value);
+                    prefab.coverage.set([x, y, z], true);
+                    copied += 1;
+                }
+            }
+        }
+    }
+
+    copied
+}
+
+// Blends the copied part of the prefab into the target, `placement` puts the center of the prefab
+// in world space
+pub fn stamp<V: VoxelValue>(
+    prefab: &Prefab,
+    placement: &Transform,
+    blend_mode: BlendMode,
+    target: &mut VoxelGrid<V>,
+    target_transform: &GlobalTransform,
+) -> Vec<VoxelChange> {
+    let to_target = target_transform.affine().inverse() * placement.compute_affine();
+    let to_prefab = to_target.inverse();
+
+    let Some((min, max)) = target_range(&prefab.grid, &to_target, target) else {
+        return Vec::new();
+    };
+
+    let mut changes = Vec::new();
+
+    for z in min[2]..=max[2] {
+        for y in min[1]..=max[1] {
+            for x in min[0]..=max[0] {
+                let position = target.position([x, y, z]);
+
+                let local = to_prefab.transform_point3(position);
+
+                if !prefab.covers(local) {
+                    continue;
+                }
+
+                let Some(value) = read(&prefab.grid, local) else {
+                    continue;
+                };
+
+                let before = target.get(x, y, z);
+                let after = blend_mode.blend::<V>(before, value);
+
+                if after != before {
+                    changes.push(VoxelChange {
+                        index: [x, y, z],
+                        before,
+                        after,
+                    });
+                }
+            }
+        }
+    }
+
+    for change in changes.iter() {
+        let [x, y, z] = change.index;
+        target.set(x, y, z, change.after);
+    }
+
+    changes
+}
+
+// Samples of the target inside of the bounding box of the placed prefab
+fn target_range<V: VoxelValue>(
+    prefab: &VoxelGrid,
+    to_target: &Affine3A,
+    target: &VoxelGrid<V>,
+) -> Option<([usize; 3], [usize; 3])> {
+    let first = prefab.position([0; 3]);
+    let last = prefab.position(prefab.resolution.map(|r| r - 1));
+
+    let mut min = Vec3::MAX;
+    let mut max = Vec3::MIN;
+
+    for corner in 0..8 {
+        let point = Vec3::new(
+            if corner & 1 == 0 { first.x } else { last.x },
+            if corner & 2 == 0 { first.y } else { last.y },
+            if corner & 4 == 0 { first.z } else { last.z },
+        );
+
+        let point = to_target.transform_point3(point);
+        min = min.min(point);
+        max = max.max(point);
+    }
+
+    let cell_size = target.cell_size();
+    let min = (min - target.bounds.min) / cell_size;
+    let max = (max - target.bounds.min) / cell_size;
+
+    let mut range = ([0; 3], [0; 3]);
+
+    for axis in 0..3 {
+        let last = target.resolution[axis].checked_sub(1)? as f32;
+
+        if max[axis] < 0.0 || min[axis] > last {
+            return None;
+        }
+
+        range.0[axis] = (min[axis] - SNAP_EPSILON).ceil().clamp(0.0, last) as usize;
+        range.1[axis] = (max[axis] + SNAP_EPSILON).floor().clamp(0.0, last) as usize;
+    }
+
+    Some(range)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StampAction {
+    Copy,
+    Stamp(Vec3),
+}
+
+// The selection is given by two opposite corners in world space. Copying and stamping are
+// requested here and carried out by the systems of `StampPlugin` in the same frame.
+#[derive(Resource, Clone)]
+pub struct StampSettings {
+    pub selection: [Option<Vec3>; 2],
+    pub cell_size: f32,
+    pub prefab: Option<Prefab>,
+    pub rotation: Quat,
+    pub blend_mode: BlendMode,
+    pending: Option<StampAction>,
+    // Center of the region being copied
+    copy_center: Vec3,
+}
+
+impl Default for StampSettings {
+    fn default() -> Self {
+        Self {
+            selection: [None; 2],
+            cell_size: 0.1,
+            prefab: None,
+            rotation: Quat::IDENTITY,
+            blend_mode: BlendMode::default(),
+            pending: None,
+            copy_center: Vec3::ZERO,
+        }
+    }
+}
+
+impl StampSettings {
+    // Copies the selection into a new prefab, false when it has no second corner yet
+    pub fn copy_selection(&mut self) -> bool {
+        let [Some(first), Some(second)] = self.selection else {
+            return false;
+        };
+
+        let min = first.min(second);
+        let max = first.max(second);
+
+        self.prefab = Some(prefab_for_region(min, max, self.cell_size));
+        self.copy_center = (min + max) * 0.5;
+        self.rotation = Quat::IDENTITY;
+        self.pending = Some(StampAction::Copy);
+
+        true
+    }
+
+    // Stamps the prefab centered on `point`, false when nothing was copied yet
+    pub fn stamp_at(&mut self, point: Vec3) -> bool {
+        if self.prefab.is_none() {
+            return false;
+        }
+
+        self.pending = Some(StampAction::Stamp(point));
+
+        true
+    }
+}
+
+pub struct StampPlugin;
+
+impl Plugin for StampPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<StampSettings>()
+            .init_resource::<EditHistory>()
+            .init_resource::<BrushHit>()
+            .add_systems(
+                Update,
+                (
+                    start_stamp_system,
+                    (
+                        stamp_system::<f32>,
+                        stamp_system::<u8>,
+                        stamp_system::<i8>,
+                        stamp_system::<F16>,
+                        stamp_chunks_system,
+                    ),
+                    finish_stamp_system,
+                )
+                    .chain(),
+            );
+    }
+}
+
+pub fn start_stamp_system(settings: Res<StampSettings>, mut history: ResMut<EditHistory>) {
+    if let Some(StampAction::Stamp(_)) = settings.pending {
+        history.begin("stamp");
+    }
+}
+
+pub fn stamp_system<V: VoxelValue>(
+    mut commands: Commands,
+    mut settings: ResMut<StampSettings>,
+    mut history: ResMut<EditHistory>,
+    mut query: Query<(Entity, &mut VoxelGrid<V>, &GlobalTransform)>,
+) {
+    let Some(action) = settings.pending else {
+        return;
+    };
+
+    let settings = &mut *settings;
+
+    let Some(prefab) = settings.prefab.as_mut() else {
+        return;
+    };
+
+    for (entity, mut voxel_grid, transform) in query.iter_mut() {
+        match action {
+            StampAction::Copy => {
+                copy_into_prefab(prefab, settings.copy_center, &voxel_grid, transform);
+            }
+            StampAction::Stamp(point) => {
+                let placement = Transform::from_translation(point).with_rotation(settings.rotation);
+
+                let changes = stamp(
+                    prefab,
+                    &placement,
+                    settings.blend_mode,
+                    &mut voxel_grid,
+                    transform,
+                );
+
+                if !changes.is_empty() {
+                    history.record_all(entity, changes);
+                    commands
+                        .entity(entity)
+                        .insert(DirtyChunk)
+                        .remove::<HermiteData>();
+                }
+            }
+        }
+    }
+}
+
+// Chunks are copied from and stamped into like grids, a region can span any number of them.
+// Stamping writes `Voxel::density` and leaves the flags of blocks alone, and like sculpting it
+// isn't recorded in the edit history.
+pub fn stamp_chunks_system(
+    mut settings: ResMut<StampSettings>,
+    mut query: Query<(&mut Chunk, &GlobalTransform)>,
+) {
+    let Some(action) = settings.pending else {
+        return;
+    };
+
+    let settings = &mut *settings;
+
+    let Some(prefab) = settings.prefab.as_mut() else {
+        return;
+    };
+
+    for (mut chunk, transform) in query.iter_mut() {
+        let mut voxel_grid = chunk.density_grid();
+
+        match action {
+            StampAction::Copy => {
+                copy_into_prefab(prefab, settings.copy_center, &voxel_grid, transform);
+            }
+            StampAction::Stamp(point) => {
+                let placement = Transform::from_translation(point).with_rotation(settings.rotation);
+
+                let changes = stamp(
+                    prefab,
+                    &placement,
+                    settings.blend_mode,
+                    &mut voxel_grid,
+                    transform,
+                );
+
+                // Only touching chunks that changed keeps the others from being remeshed
+                for change in changes {
+                    let mut voxel = chunk.voxels.get(change.index);
+                    voxel.density = change.after;
+                    chunk.voxels.set(change.index, voxel);
+                }
+            }
+        }
+    }
+}
+
+pub fn finish_stamp_system(mut settings: ResMut<StampSettings>, mut history: ResMut<EditHistory>) {
+    match settings.pending.take() {
+        Some(StampAction::Copy) => {
+            if let Some(prefab) = settings.prefab.as_mut() {
+                prefab.grid.data.optimize();
+                prefab.coverage.optimize();
+                info!("Copied a {:?} prefab", prefab.grid.resolution);
+            }
+        }
+        Some(StampAction::Stamp(_)) => history.commit(),
+        None => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grid<V: VoxelValue>(resolution: usize, size: f32, value: f32) -> VoxelGrid<V> {
+        VoxelGrid {
+            resolution: [resolution; 3],
+            data: SparseGrid::new([resolution; 3], V::from_f32(value)),
+            bounds: Bounds {
+                min: Vec3::ZERO,
+                max: Vec3::splat(size),
+            },
+        }
+    }
+
+    #[test]
+    fn replace_skips_samples_that_were_not_copied() {
+        // The region reaches past the source, only its first two samples per axis are copied
+        let source = grid::<f32>(4, 1.0, 0.0);
+        let mut prefab = prefab_for_region(Vec3::splat(0.5), Vec3::splat(1.5), 0.25);
+        let copied = copy_into_prefab(&mut prefab, Vec3::ONE, &source, &GlobalTransform::IDENTITY);
+        assert_eq!(copied, 8);
+
+        let mut target = grid::<f32>(8, 2.0, 1.0);
+        let changes = stamp(
+            &prefab,
+            &Transform::from_translation(Vec3::ONE),
+            BlendMode::Replace,
+            &mut target,
+            &GlobalTransform::IDENTITY,
+        );
+
+        assert_eq!(changes.len(), 8);
+        assert_eq!(target.get(2, 2, 2), 0.0);
+        assert_eq!(target.get(3, 3, 3), 0.0);
+        assert_eq!(target.get(4, 3, 3), 1.0);
+        assert_eq!(target.get(6, 6, 6), 1.0);
+    }
+
+    #[test]
+    fn stamps_span_chunks() {
+        use bevy::{
+            ecs::system::RunSystemOnce,
+            math::IVec3,
+            prelude::{Entity, World},
+        };
+
+        use crate::chunk::CHUNK_SZ;
+
+        let mut world = World::new();
+
+        let chunks = [0, 1].map(|x| {
+            let offset = (x * CHUNK_SZ) as f32;
+
+            world
+                .spawn((
+                    Chunk::new(IVec3::new(x as i32, 0, 0)),
+                    GlobalTransform::from(Transform::from_xyz(offset, 0.0, 0.0)),
+                ))
+                .id()
+        });
+
+        // A solid prefab of 3 samples per axis, stamped on the border between the chunks
+        let mut prefab = prefab_for_region(Vec3::ZERO, Vec3::splat(2.0), 1.0);
+        prefab.grid.data.fill(1.0);
+        prefab.coverage.fill(true);
+
+        let point = Vec3::new(CHUNK_SZ as f32 - 0.5, 4.0, 4.0);
+
+        world.insert_resource(StampSettings {
+            prefab: Some(prefab),
+            cell_size: 1.0,
+            pending: Some(StampAction::Stamp(point)),
+            ..Default::default()
+        });
+
+        world.run_system_once(stamp_chunks_system);
+
+        let density = |world: &World, chunk: Entity, index: [usize; 3]| {
+            world.get::<Chunk>(chunk).unwrap().voxels.get(index).density
+        };
+
+        assert_eq!(density(&world, chunks[0], [CHUNK_SZ - 1, 4, 4]), 1.0);
+        assert_eq!(density(&world, chunks[0], [CHUNK_SZ - 2, 4, 4]), 0.0);
+        assert_eq!(density(&world, chunks[1], [0, 4, 4]), 1.0);
+        assert_eq!(density(&world, chunks[1], [0, 3, 3]), 1.0);
+        assert_eq!(density(&world, chunks[1], [1, 4, 4]), 0.0);
+    }
+
+    #[test]
+    fn subtract_uses_the_range_of_the_target() {
+        assert_eq!(BlendMode::Subtract.blend::<f32>(1.0, 1.0), 0.0);
+        assert_eq!(BlendMode::Subtract.blend::<i8>(1.0, 1.0), -1.0);
+        assert_eq!(BlendMode::Subtract.blend::<i8>(1.0, -1.0), 1.0);
+    }
+}
@@ -0,0 +1,158 @@
+use bevy::math::Vec3;
+
+use crate::{
+    lod::ChunkLod,
+    marching_cubes_cpu::VoxelGrid,
+    meshing::{interp_vertex, MeshData, MeshingSettings},
+    sparse::for_each_active_cell,
+    voxel_value::VoxelValue,
+};
+
+const CORNERS: [[usize; 3]; 8] = [
+    [0, 0, 0],
+    [1, 0, 0],
+    [0, 1, 0],
+    [1, 1, 0],
+    [0, 0, 1],
+    [1, 0, 1],
+    [0, 1, 1],
+    [1, 1, 1],
+];
+
+const EDGES: [[usize; 2]; 12] = [
+    [0, 1],
+    [2, 3],
+    [4, 5],
+    [6, 7],
+    [0, 2],
+    [1, 3],
+    [4, 6],
+    [5, 7],
+    [0, 4],
+    [1, 5],
+    [2, 6],
+    [3, 7],
+];
+
+// Naive surface nets: one vertex per cell with a sign change, placed at the average of the edge
+// crossings, and one quad for every sample edge crossing the surface. Coarser levels of detail
+// are meshed with a larger stride, transition cells are not supported.
+pub fn surface_nets<V: VoxelValue>(
+    voxel_grid: &VoxelGrid<V>,
+    settings: &MeshingSettings,
+    lod: &ChunkLod,
+) -> MeshData {
+    let iso_level = settings.iso_level;
+
+    let stride = lod.stride();
+    let last = lod.last_samples(voxel_grid.resolution);
+    let cells = lod.cell_counts(voxel_grid.resolution);
+
+    let mut mesh_data = MeshData::default();
+
+    if cells.contains(&0) {
+        return mesh_data;
+    }
+
+    let cell_index = |c: [usize; 3]| c[0] + c[1] * cells[0] + c[2] * cells[0] * cells[1];
+
+    let mut cell_vertices = vec![u32::MAX; cells[0] * cells[1] * cells[2]];
+
+    for_each_active_cell(voxel_grid, stride, last, |base| {
+        let [cx, cy, cz] = base.map(|b| b / stride);
+
+        if cx >= cells[0] || cy >= cells[1] || cz >= cells[2] {
+            return;
+        }
+
+        let corners = CORNERS.map(|offset| {
+            let index = lod.corner(base, offset, last);
+
+            (
+                voxel_grid.position(index),
+                voxel_grid.get(index[0], index[1], index[2]),
+            )
+        });
+
+        let mut sum = Vec3::ZERO;
+        let mut count = 0;
+
+        for [a, b] in EDGES.iter() {
+            let (pa, va) = corners[*a];
+            let (pb, vb) = corners[*b];
+
+            if (va > iso_level) != (vb > iso_level) {
+                sum += interp_vertex(pa, pb, va, vb, iso_level);
+                count += 1;
+            }
+        }
+
+        if count == 0 {
+            return;
+        }
+
+        let position = sum / count as f32;
+        let normal = -voxel_grid.gradient(position).normalize_or_zero();
+
+        cell_vertices[cell_index([cx, cy, cz])] = mesh_data.push_vertex(position, normal);
+    });
+
+    // Every sample edge that crosses the surface is shared by four cells, their vertices form a quad.
+    // Edges starting on the far faces are skipped below, so the rest start at the first sample of a
+    // cell and lie inside of it.
+    for_each_active_cell(voxel_grid, stride, last, |base| {
+        let sample = base.map(|b| b / stride);
+        let [x, y, z] = sample;
+
+        if x >= cells[0] || y >= cells[1] || z >= cells[2] {
+            return;
+        }
+
+        let value = voxel_grid.get(base[0], base[1], base[2]);
+        let inside = value > iso_level;
+
+        for axis in 0..3 {
+            let u_axis = (axis + 1) % 3;
+            let v_axis = (axis + 2) % 3;
+
+            if sample[axis] == cells[axis]
+                || sample[u_axis] == 0
+                || sample[u_axis] == cells[u_axis]
+                || sample[v_axis] == 0
+                || sample[v_axis] == cells[v_axis]
+            {
+                continue;
+            }
+
+            let mut offset = [0; 3];
+            offset[axis] = 1;
+
+            let next = lod.corner(base, offset, last);
+            let next_value = voxel_grid.get(next[0], next[1], next[2]);
+
+            if inside == (next_value > iso_level) {
+                continue;
+            }
+
+            let quad = [[1, 1], [0, 1], [0, 0], [1, 0]].map(|[du, dv]| {
+                let mut cell = sample;
+                cell[u_axis] -= du;
+                cell[v_axis] -= dv;
+                cell_vertices[cell_index(cell)]
+            });
+
+            if quad.contains(&u32::MAX) {
+                continue;
+            }
+
+            // The quad winds around the edge axis, flip it when the surface faces backwards
+            if inside {
+                mesh_data.push_quad(quad);
+            } else {
+                mesh_data.push_quad([quad[3], quad[2], quad[1], quad[0]]);
+            }
+        }
+    });
+
+    mesh_data
+}
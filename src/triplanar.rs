@@ -0,0 +1,59 @@
+use bevy::{
+    pbr::{ExtendedMaterial, MaterialExtension, MaterialPlugin, StandardMaterial},
+    prelude::*,
+    render::{
+        mesh::MeshVertexAttribute,
+        render_resource::{AsBindGroup, ShaderRef, ShaderType},
+    },
+};
+
+// Marching cubes output has no meaningful UVs, so the texture is projected along the three world
+// axes and blended by the surface normal instead
+pub type TriplanarMaterial = ExtendedMaterial<StandardMaterial, TriplanarExtension>;
+
+// The material ID is carried in the x component of the second UV channel so that the standard
+// vertex shader forwards it to the fragment shader without a custom vertex layout
+pub const ATTRIBUTE_MATERIAL_ID: MeshVertexAttribute = Mesh::ATTRIBUTE_UV_1;
+
+pub struct TriplanarMaterialPlugin;
+
+impl Plugin for TriplanarMaterialPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(MaterialPlugin::<TriplanarMaterial>::default());
+    }
+}
+
+#[derive(Asset, AsBindGroup, Reflect, Debug, Clone, Default)]
+pub struct TriplanarExtension {
+    #[uniform(100)]
+    pub settings: TriplanarSettings,
+    // Array texture with one layer per material ID, the sampler should use a repeating address mode
+    #[texture(101, dimension = "2d_array")]
+    #[sampler(102)]
+    pub textures: Option<Handle<Image>>,
+}
+
+impl MaterialExtension for TriplanarExtension {
+    fn fragment_shader() -> ShaderRef {
+        "shaders/triplanar.wgsl".into()
+    }
+}
+
+#[derive(ShaderType, Reflect, Debug, Clone, Copy)]
+pub struct TriplanarSettings {
+    // World units to texture coordinates
+    pub scale: f32,
+    // Exponent applied to the normal weights, higher values give harder transitions between axes
+    pub blend_sharpness: f32,
+    pub layer_count: u32,
+}
+
+impl Default for TriplanarSettings {
+    fn default() -> Self {
+        Self {
+            scale: 0.25,
+            blend_sharpness: 4.0,
+            layer_count: 1,
+        }
+    }
+}
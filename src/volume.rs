@@ -0,0 +1,514 @@
+use std::{fmt, io, path::Path};
+
+use bevy::{
+    app::{App, Plugin},
+    asset::{io::Reader, AssetApp, AssetLoader, AsyncReadExt, LoadContext},
+    math::Vec3,
+};
+
+use crate::{
+    marching_cubes_cpu::{Bounds, VoxelGrid},
+    sparse::SparseGrid,
+    voxel_file::VoxelFile,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScalarType {
+    U8,
+    I8,
+    U16,
+    I16,
+    U32,
+    I32,
+    F32,
+    F64,
+}
+
+impl ScalarType {
+    pub fn size(&self) -> usize {
+        match self {
+            ScalarType::U8 | ScalarType::I8 => 1,
+            ScalarType::U16 | ScalarType::I16 => 2,
+            ScalarType::U32 | ScalarType::I32 | ScalarType::F32 => 4,
+            ScalarType::F64 => 8,
+        }
+    }
+
+    fn from_nrrd(name: &str) -> Option<Self> {
+        match name {
+            "uchar" | "unsigned char" | "uint8" | "uint8_t" => Some(ScalarType::U8),
+            "signed char" | "int8" | "int8_t" => Some(ScalarType::I8),
+            "ushort" | "unsigned short" | "unsigned short int" | "uint16" | "uint16_t" => {
+                Some(ScalarType::U16)
+            }
+            "short" | "short int" | "signed short" | "signed short int" | "int16" | "int16_t" => {
+                Some(ScalarType::I16)
+            }
+            "uint" | "unsigned int" | "uint32" | "uint32_t" => Some(ScalarType::U32),
+            "int" | "signed int" | "int32" | "int32_t" => Some(ScalarType::I32),
+            "float" => Some(ScalarType::F32),
+            "double" => Some(ScalarType::F64),
+            _ => None,
+        }
+    }
+
+    fn decode(&self, bytes: &[u8], endian: Endian) -> f32 {
+        macro_rules! read {
+            ($ty:ty) => {{
+                let bytes = bytes.try_into().unwrap();
+                match endian {
+                    Endian::Little => <$ty>::from_le_bytes(bytes) as f32,
+                    Endian::Big => <$ty>::from_be_bytes(bytes) as f32,
+                }
+            }};
+        }
+
+        match self {
+            ScalarType::U8 => read!(u8),
+            ScalarType::I8 => read!(i8),
+            ScalarType::U16 => read!(u16),
+            ScalarType::I16 => read!(i16),
+            ScalarType::U32 => read!(u32),
+            ScalarType::I32 => read!(i32),
+            ScalarType::F32 => read!(f32),
+            ScalarType::F64 => read!(f64),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Endian {
+    Little,
+    Big,
+}
+
+// Describes a headerless binary volume, x varies fastest
+#[derive(Clone, Debug)]
+pub struct RawVolume {
+    pub dimensions: [usize; 3],
+    pub spacing: [f32; 3],
+    pub origin: Vec3,
+    pub scalar_type: ScalarType,
+    pub endian: Endian,
+    // Bytes to skip before the samples start
+    pub header_size: usize,
+}
+
+#[derive(Debug)]
+pub enum VolumeError {
+    Io(io::Error),
+    InvalidMagic,
+    InvalidHeader(String),
+    Unsupported(String),
+    Truncated,
+}
+
+impl fmt::Display for VolumeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VolumeError::Io(err) => write!(f, "io error: {}", err),
+            VolumeError::InvalidMagic => write!(f, "not a NRRD file"),
+            VolumeError::InvalidHeader(reason) => write!(f, "invalid header: {}", reason),
+            VolumeError::Unsupported(reason) => write!(f, "unsupported: {}", reason),
+            VolumeError::Truncated => write!(f, "volume data is truncated"),
+        }
+    }
+}
+
+impl std::error::Error for VolumeError {}
+
+impl From<io::Error> for VolumeError {
+    fn from(err: io::Error) -> Self {
+        VolumeError::Io(err)
+    }
+}
+
+impl RawVolume {
+    pub fn new(dimensions: [usize; 3], scalar_type: ScalarType) -> Self {
+        Self {
+            dimensions,
+            spacing: [1.0; 3],
+            origin: Vec3::ZERO,
+            scalar_type,
+            endian: Endian::Little,
+            header_size: 0,
+        }
+    }
+
+    // Sizes come from file headers, a product that doesn't fit can't be a real volume
+    fn sample_count(&self) -> Result<usize, VolumeError> {
+        self.dimensions
+            .iter()
+            .try_fold(1usize, |count, size| count.checked_mul(*size))
+            .ok_or_else(|| VolumeError::Unsupported("volume too large".to_string()))
+    }
+
+    fn byte_count(&self) -> Result<usize, VolumeError> {
+        self.sample_count()?
+            .checked_mul(self.scalar_type.size())
+            .ok_or_else(|| VolumeError::Unsupported("volume too large".to_string()))
+    }
+
+    // Sample i sits at origin + i * spacing, so the bounds extend one spacing past the last sample
+    // like the grids built from meshes
+    fn bounds(&self) -> Bounds {
+        let spacing = Vec3::from(self.spacing);
+        let dimensions = Vec3::new(
+            self.dimensions[0] as f32,
+            self.dimensions[1] as f32,
+            self.dimensions[2] as f32,
+        );
+
+        Bounds {
+            min: self.origin,
+            max: self.origin + spacing * dimensions,
+        }
+    }
+
+    fn to_voxel_grid(&self, data: Vec<f32>) -> VoxelGrid {
+        VoxelGrid {
+            resolution: self.dimensions,
+            data: SparseGrid::from_dense(self.dimensions, &data),
+            bounds: self.bounds(),
+        }
+    }
+
+    pub fn decode(&self, bytes: &[u8]) -> Result<VoxelGrid, VolumeError> {
+        let bytes = self
+            .header_size
+            .checked_add(self.byte_count()?)
+            .and_then(|end| bytes.get(self.header_size..end))
+            .ok_or(VolumeError::Truncated)?;
+
+        let data = bytes
+            .chunks_exact(self.scalar_type.size())
+            .map(|sample| self.scalar_type.decode(sample, self.endian))
+            .collect();
+
+        Ok(self.to_voxel_grid(data))
+    }
+
+    pub fn load(&self, path: &Path) -> Result<VoxelGrid, VolumeError> {
+        self.decode(&std::fs::read(path)?)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum NrrdEncoding {
+    Raw,
+    Ascii,
+}
+
+#[derive(Clone, Debug)]
+pub struct NrrdHeader {
+    volume: RawVolume,
+    encoding: NrrdEncoding,
+    // Detached data file, relative to the header
+    data_file: Option<String>,
+    // -1 means the data is at the end of the file
+    byte_skip: i64,
+    // Where attached data starts
+    data_offset: usize,
+}
+
+impl NrrdHeader {
+    pub fn parse(bytes: &[u8]) -> Result<Self, VolumeError> {
+        if !bytes.starts_with(b"NRRD000") {
+            return Err(VolumeError::InvalidMagic);
+        }
+
+        let mut dimension = None;
+        let mut sizes = None;
+        let mut spacing = [1.0; 3];
+        let mut origin = Vec3::ZERO;
+        let mut scalar_type = None;
+        let mut endian = Endian::Little;
+        let mut encoding = NrrdEncoding::Raw;
+        let mut data_file = None;
+        let mut byte_skip = 0;
+
+        let mut cursor = 0;
+        let mut first = true;
+
+        // The header ends at the first empty line, or at the end of the file for a detached
+        // header
+        while cursor < bytes.len() {
+            let end = bytes[cursor..]
+                .iter()
+                .position(|b| *b == b'\n')
+                .map_or(bytes.len(), |i| cursor + i);
+
+            let line = String::from_utf8_lossy(&bytes[cursor..end]);
+            let line = line.trim_end_matches('\r');
+            cursor = (end + 1).min(bytes.len());
+
+            if first {
+                first = false;
+                continue;
+            }
+
+            if line.is_empty() {
+                break;
+            }
+
+            // Comments and key/value pairs are ignored
+            if line.starts_with('#') || line.contains(":=") {
+                continue;
+            }
+
+            let Some((field, value)) = line.split_once(": ") else {
+                return Err(VolumeError::InvalidHeader(format!("bad line {:?}", line)));
+            };
+
+            let value = value.trim();
+
+            match field.to_ascii_lowercase().as_str() {
+                "type" => {
+                    scalar_type = Some(ScalarType::from_nrrd(value).ok_or_else(|| {
+                        VolumeError::Unsupported(format!("sample type {:?}", value))
+                    })?);
+                }
+                "dimension" => dimension = value.parse::<usize>().ok(),
+                "sizes" => {
+                    sizes = Some(
+                        value
+                            .split_whitespace()
+                            .map(|size| size.parse::<usize>())
+                            .collect::<Result<Vec<usize>, _>>()
+                            .map_err(|_| VolumeError::InvalidHeader("sizes".to_string()))?,
+                    );
+                }
+                "spacings" => {
+                    for (axis, value) in value.split_whitespace().take(3).enumerate() {
+                        // Spacings can be nan for axes that aren't spatial
+                        if let Ok(value) = value.parse::<f32>() {
+                            if value.is_finite() && value != 0.0 {
+                                spacing[axis] = value.abs();
+                            }
+                        }
+                    }
+                }
+                // Only axis aligned volumes are supported, the length of each direction is its
+                // spacing
+                "space directions" => {
+                    for (axis, direction) in value.split_whitespace().take(3).enumerate() {
+                        if let Some(vector) = parse_vector(direction) {
+                            let length = vector.length();
+                            if length.is_finite() && length > 0.0 {
+                                spacing[axis] = length;
+                            }
+                        }
+                    }
+                }
+                "space origin" => {
+                    origin = parse_vector(value)
+                        .ok_or_else(|| VolumeError::InvalidHeader("space origin".to_string()))?;
+                }
+                "endian" => {
+                    endian = match value {
+                        "big" => Endian::Big,
+                        _ => Endian::Little,
+                    };
+                }
+                "encoding" => {
+                    encoding = match value {
+                        "raw" => NrrdEncoding::Raw,
+                        "ascii" | "text" | "txt" => NrrdEncoding::Ascii,
+                        _ => return Err(VolumeError::Unsupported(format!("{} encoding", value))),
+                    };
+                }
+                "data file" | "datafile" => {
+                    if value.starts_with("LIST") || value.contains('%') {
+                        return Err(VolumeError::Unsupported("multiple data files".to_string()));
+                    }
+                    data_file = Some(value.to_string());
+                }
+                "byte skip" | "byteskip" => {
+                    byte_skip = value
+                        .parse::<i64>()
+                        .map_err(|_| VolumeError::InvalidHeader("byte skip".to_string()))?;
+                }
+                _ => {}
+            }
+        }
+
+        if dimension != Some(3) {
+            return Err(VolumeError::Unsupported(format!(
+                "dimension {:?}, only 3D volumes can be meshed",
+                dimension
+            )));
+        }
+
+        let sizes = sizes.ok_or_else(|| VolumeError::InvalidHeader("missing sizes".to_string()))?;
+        if sizes.len() != 3 {
+            return Err(VolumeError::InvalidHeader("sizes".to_string()));
+        }
+
+        let scalar_type =
+            scalar_type.ok_or_else(|| VolumeError::InvalidHeader("missing type".to_string()))?;
+
+        Ok(Self {
+            volume: RawVolume {
+                dimensions: [sizes[0], sizes[1], sizes[2]],
+                spacing,
+                origin,
+                scalar_type,
+                endian,
+                header_size: 0,
+            },
+            encoding,
+            data_file,
+            byte_skip,
+            data_offset: cursor,
+        })
+    }
+
+    pub fn data_file(&self) -> Option<&str> {
+        self.data_file.as_deref()
+    }
+
+    // `data` is the rest of the header file for attached data, or the whole detached data file
+    pub fn decode(&self, data: &[u8]) -> Result<VoxelGrid, VolumeError> {
+        match self.encoding {
+            NrrdEncoding::Raw => {
+                let size = self.volume.byte_count()?;
+
+                let header_size = if self.byte_skip < 0 {
+                    data.len().checked_sub(size).ok_or(VolumeError::Truncated)?
+                } else {
+                    self.byte_skip as usize
+                };
+
+                RawVolume {
+                    header_size,
+                    ..self.volume.clone()
+                }
+                .decode(data)
+            }
+            NrrdEncoding::Ascii => {
+                let sample_count = self.volume.sample_count()?;
+
+                let data = String::from_utf8_lossy(data)
+                    .split_whitespace()
+                    .take(sample_count)
+                    .map(|value| value.parse::<f32>())
+                    .collect::<Result<Vec<f32>, _>>()
+                    .map_err(|_| VolumeError::InvalidHeader("ascii data".to_string()))?;
+
+                if data.len() < sample_count {
+                    return Err(VolumeError::Truncated);
+                }
+
+                Ok(self.volume.to_voxel_grid(data))
+            }
+        }
+    }
+}
+
+fn parse_vector(value: &str) -> Option<Vec3> {
+    let values = value
+        .trim_matches(|c| c == '(' || c == ')')
+        .split(',')
+        .map(|v| v.trim().parse::<f32>())
+        .collect::<Result<Vec<f32>, _>>()
+        .ok()?;
+
+    (values.len() == 3).then(|| Vec3::new(values[0], values[1], values[2]))
+}
+
+pub fn load_nrrd(path: &Path) -> Result<VoxelGrid, VolumeError> {
+    let bytes = std::fs::read(path)?;
+    let header = NrrdHeader::parse(&bytes)?;
+
+    match header.data_file() {
+        Some(data_file) => {
+            let data_path = path.parent().unwrap_or(Path::new("")).join(data_file);
+            header.decode(&std::fs::read(data_path)?)
+        }
+        None => header.decode(&bytes[header.data_offset..]),
+    }
+}
+
+#[derive(Default)]
+pub struct NrrdLoader;
+
+impl AssetLoader for NrrdLoader {
+    type Asset = VoxelFile;
+    type Settings = ();
+    type Error = VolumeError;
+
+    async fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader<'_>,
+        _settings: &'a (),
+        load_context: &'a mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+
+        let header = NrrdHeader::parse(&bytes)?;
+
+        let voxel_grid = match header.data_file() {
+            Some(data_file) => {
+                let data_path = load_context
+                    .path()
+                    .parent()
+                    .unwrap_or(Path::new(""))
+                    .join(data_file);
+
+                let data = load_context
+                    .read_asset_bytes(data_path)
+                    .await
+                    .map_err(|err| io::Error::new(io::ErrorKind::NotFound, err.to_string()))?;
+
+                header.decode(&data)?
+            }
+            None => header.decode(&bytes[header.data_offset..])?,
+        };
+
+        Ok(VoxelFile::Grid(voxel_grid))
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["nrrd", "nhdr"]
+    }
+}
+
+// Loaded volumes are applied to entities through `Handle<VoxelFile>` like saved grids, so this
+// needs the `VoxelFilePlugin`
+pub struct VolumePlugin;
+
+impl Plugin for VolumePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset_loader::<NrrdLoader>();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detached_header_ends_at_end_of_file() {
+        let header = b"NRRD0004\ntype: uchar\ndimension: 3\nsizes: 2 2 2\ndata file: volume.raw";
+        let header = NrrdHeader::parse(header).unwrap();
+
+        assert_eq!(header.data_file(), Some("volume.raw"));
+
+        let voxel_grid = header.decode(&[0, 255, 0, 0, 0, 0, 0, 0]).unwrap();
+        assert_eq!(voxel_grid.resolution, [2; 3]);
+        assert!(voxel_grid.get(1, 0, 0) > 0.5);
+    }
+
+    #[test]
+    fn oversized_volume_is_rejected() {
+        let size = usize::MAX / 2;
+        let header = format!("NRRD0004\ntype: float\ndimension: 3\nsizes: {size} {size} 2\n\n");
+        let header = NrrdHeader::parse(header.as_bytes()).unwrap();
+
+        assert!(matches!(
+            header.decode(&[0; 16]),
+            Err(VolumeError::Unsupported(_))
+        ));
+    }
+}
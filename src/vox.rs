@@ -0,0 +1,839 @@
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+};
+
+use bevy::{
+    app::{App, Plugin, Update},
+    asset::{io::Reader, Asset, AssetApp, AssetLoader, AsyncReadExt, LoadContext},
+    log::{error, info},
+    math::{IVec3, Vec3},
+    prelude::{Event, EventReader, Query, Res, Resource},
+    reflect::TypePath,
+    utils::HashMap,
+};
+
+use crate::{
+    channels::{ChannelInterpolation, ChannelSchema, ChannelTarget, VoxelChannel, VoxelChannels},
+    chunk::{Chunk, Voxel, CHUNK_SZ, CHUNK_SZ_2},
+    marching_cubes_cpu::{Bounds, VoxelGrid},
+    sparse::SparseGrid,
+};
+
+// Models in a .vox file can't be larger than this along any axis
+const MAX_MODEL_SIZE: i32 = 256;
+
+#[derive(Clone, Debug)]
+pub struct VoxModel {
+    pub size: [i32; 3],
+    // (x, y, z, palette index) in MagicaVoxel coordinates, z is up
+    pub voxels: Vec<[u8; 4]>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VoxTransform {
+    // Signed permutation matrix, row major
+    pub rotation: [[i32; 3]; 3],
+    pub translation: [i32; 3],
+}
+
+impl Default for VoxTransform {
+    fn default() -> Self {
+        Self {
+            rotation: [[1, 0, 0], [0, 1, 0], [0, 0, 1]],
+            translation: [0; 3],
+        }
+    }
+}
+
+impl VoxTransform {
+    fn apply(&self, point: [i32; 3]) -> [i32; 3] {
+        let r = &self.rotation;
+        [0, 1, 2].map(|i| {
+            r[i][0] * point[0] + r[i][1] * point[1] + r[i][2] * point[2] + self.translation[i]
+        })
+    }
+
+    fn then(&self, child: &VoxTransform) -> VoxTransform {
+        let rotation = [0, 1, 2].map(|i| {
+            [0, 1, 2].map(|j| {
+                (0..3)
+                    .map(|k| self.rotation[i][k] * child.rotation[k][j])
+                    .sum()
+            })
+        });
+        let translation = self.apply(child.translation);
+
+        VoxTransform {
+            rotation,
+            translation,
+        }
+    }
+
+    // Bits 0-1 and 2-3 are the column of the non zero entry in the first two rows, bits 4-6 the
+    // signs of the three rows
+    fn from_packed(packed: u8) -> Self {
+        let i0 = (packed & 3) as usize;
+        let i1 = ((packed >> 2) & 3) as usize;
+        let i2 = 3usize.saturating_sub(i0 + i1).min(2);
+
+        let mut rotation = [[0; 3]; 3];
+
+        for (row, (column, bit)) in [(i0, 4), (i1, 5), (i2, 6)].into_iter().enumerate() {
+            rotation[row][column] = if packed & (1 << bit) != 0 { -1 } else { 1 };
+        }
+
+        Self {
+            rotation,
+            translation: [0; 3],
+        }
+    }
+
+    fn to_packed(self) -> u8 {
+        let column = |row: usize| (0..3).find(|c| self.rotation[row][*c] != 0).unwrap_or(row);
+        let negative = |row: usize| self.rotation[row][column(row)] < 0;
+
+        (column(0) as u8)
+            | ((column(1) as u8) << 2)
+            | ((negative(0) as u8) << 4)
+            | ((negative(1) as u8) << 5)
+            | ((negative(2) as u8) << 6)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct VoxInstance {
+    pub model: usize,
+    pub transform: VoxTransform,
+}
+
+#[derive(Asset, TypePath, Clone, Debug)]
+pub struct VoxScene {
+    pub models: Vec<VoxModel>,
+    pub instances: Vec<VoxInstance>,
+    // Colour of each palette index, index 0 is empty space
+    pub palette: [[u8; 4]; 256],
+}
+
+#[derive(Debug)]
+pub enum VoxError {
+    Io(io::Error),
+    InvalidMagic,
+    Truncated,
+    InvalidChunk(String),
+}
+
+impl fmt::Display for VoxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VoxError::Io(err) => write!(f, "io error: {}", err),
+            VoxError::InvalidMagic => write!(f, "not a MagicaVoxel file"),
+            VoxError::Truncated => write!(f, "file is truncated"),
+            VoxError::InvalidChunk(reason) => write!(f, "invalid chunk: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for VoxError {}
+
+impl From<io::Error> for VoxError {
+    fn from(err: io::Error) -> Self {
+        VoxError::Io(err)
+    }
+}
+
+struct ByteReader<'a> {
+    bytes: &'a [u8],
+    cursor: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], VoxError> {
+        let bytes = self
+            .bytes
+            .get(self.cursor..self.cursor + count)
+            .ok_or(VoxError::Truncated)?;
+        self.cursor += count;
+        Ok(bytes)
+    }
+
+    fn i32(&mut self) -> Result<i32, VoxError> {
+        Ok(i32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> Result<String, VoxError> {
+        let length = self.i32()?.max(0) as usize;
+        Ok(String::from_utf8_lossy(self.take(length)?).into_owned())
+    }
+
+    fn dict(&mut self) -> Result<HashMap<String, String>, VoxError> {
+        let count = self.i32()?.max(0);
+        (0..count)
+            .map(|_| Ok((self.string()?, self.string()?)))
+            .collect()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.cursor >= self.bytes.len()
+    }
+}
+
+enum SceneNode {
+    Transform { child: i32, transform: VoxTransform },
+    Group { children: Vec<i32> },
+    Shape { models: Vec<usize> },
+}
+
+impl VoxScene {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, VoxError> {
+        let mut reader = ByteReader { bytes, cursor: 0 };
+
+        if reader.take(4)? != b"VOX " {
+            return Err(VoxError::InvalidMagic);
+        }
+
+        let _version = reader.i32()?;
+
+        if reader.take(4)? != b"MAIN" {
+            return Err(VoxError::InvalidChunk("expected MAIN".to_string()));
+        }
+
+        let main_content = reader.i32()?.max(0) as usize;
+        let main_children = reader.i32()?.max(0) as usize;
+        reader.take(main_content)?;
+
+        let mut children = ByteReader {
+            bytes: reader.take(main_children)?,
+            cursor: 0,
+        };
+
+        let mut models = Vec::new();
+        let mut size = [0; 3];
+        let mut palette = default_palette();
+        let mut nodes = HashMap::<i32, SceneNode>::default();
+
+        while !children.is_empty() {
+            let id = children.take(4)?;
+            let content_size = children.i32()?.max(0) as usize;
+            let children_size = children.i32()?.max(0) as usize;
+
+            let mut content = ByteReader {
+                bytes: children.take(content_size)?,
+                cursor: 0,
+            };
+            children.take(children_size)?;
+
+            match id {
+                b"SIZE" => {
+                    size = [content.i32()?, content.i32()?, content.i32()?];
+                }
+                b"XYZI" => {
+                    let count = content.i32()?.max(0) as usize;
+                    let voxels = content
+                        .take(count * 4)?
+                        .chunks_exact(4)
+                        .map(|voxel| [voxel[0], voxel[1], voxel[2], voxel[3]])
+                        .collect();
+
+                    models.push(VoxModel { size, voxels });
+                }
+                b"RGBA" => {
+                    // Entry i holds the colour of palette index i + 1
+                    for i in 0..255 {
+                        let color = content.take(4)?;
+                        palette[i + 1] = [color[0], color[1], color[2], color[3]];
+                    }
+                }
+                b"nTRN" => {
+                    let node_id = content.i32()?;
+                    let _attributes = content.dict()?;
+                    let child = content.i32()?;
+                    let _reserved = content.i32()?;
+                    let _layer = content.i32()?;
+                    let frames = content.i32()?;
+
+                    let mut transform = VoxTransform::default();
+
+                    // Only the first frame is used, animations are not supported
+                    if frames > 0 {
+                        let frame = content.dict()?;
+
+                        if let Some(rotation) = frame.get("_r") {
+                            let packed = rotation.trim().parse::<u8>().unwrap_or(4);
+                            transform = VoxTransform::from_packed(packed);
+                        }
+
+                        if let Some(translation) = frame.get("_t") {
+                            let values = translation
+                                .split_whitespace()
+                                .filter_map(|value| value.parse::<i32>().ok())
+                                .collect::<Vec<i32>>();
+
+                            if values.len() == 3 {
+                                transform.translation = [values[0], values[1], values[2]];
+                            }
+                        }
+                    }
+
+                    nodes.insert(node_id, SceneNode::Transform { child, transform });
+                }
+                b"nGRP" => {
+                    let node_id = content.i32()?;
+                    let _attributes = content.dict()?;
+                    let count = content.i32()?.max(0);
+                    let group_children =
+                        (0..count)
+                            .map(|_| content.i32())
+                            .collect::<Result<Vec<i32>, VoxError>>()?;
+
+                    nodes.insert(
+                        node_id,
+                        SceneNode::Group {
+                            children: group_children,
+                        },
+                    );
+                }
+                b"nSHP" => {
+                    let node_id = content.i32()?;
+                    let _attributes = content.dict()?;
+                    let count = content.i32()?.max(0);
+
+                    let mut shape_models = Vec::new();
+                    for _ in 0..count {
+                        shape_models.push(content.i32()?.max(0) as usize);
+                        let _model_attributes = content.dict()?;
+                    }
+
+                    nodes.insert(
+                        node_id,
+                        SceneNode::Shape {
+                            models: shape_models,
+                        },
+                    );
+                }
+                _ => {}
+            }
+        }
+
+        let mut instances = Vec::new();
+
+        if nodes.contains_key(&0) {
+            collect_instances(&nodes, 0, VoxTransform::default(), &mut instances, 0);
+        } else {
+            // Files without a scene graph place every model at the origin
+            instances = (0..models.len())
+                .map(|model| VoxInstance {
+                    model,
+                    transform: VoxTransform::default(),
+                })
+                .collect();
+        }
+
+        instances.retain(|instance| instance.model < models.len());
+
+        Ok(Self {
+            models,
+            instances,
+            palette,
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, VoxError> {
+        Self::from_bytes(&std::fs::read(path)?)
+    }
+
+    pub fn palette_colors(&self) -> Vec<[f32; 4]> {
+        self.palette
+            .iter()
+            .map(|color| color.map(|channel| channel as f32 / 255.0))
+            .collect()
+    }
+
+    // MagicaVoxel is z up, voxels are rotated and translated around the center of their model
+    fn world_voxels(&self) -> impl Iterator<Item = (IVec3, u8)> + '_ {
+        self.instances.iter().flat_map(move |instance| {
+            let model = &self.models[instance.model];
+            let pivot = model.size.map(|s| s / 2);
+
+            model.voxels.iter().map(move |[x, y, z, index]| {
+                let local = [
+                    *x as i32 - pivot[0],
+                    *y as i32 - pivot[1],
+                    *z as i32 - pivot[2],
+                ];
+                let [wx, wy, wz] = instance.transform.apply(local);
+
+                (IVec3::new(wx, wz, -wy), *index)
+            })
+        })
+    }
+
+    // Every instance merged into chunks, the palette index ends up in the voxel flags so the
+    // voxels are rendered as blocks
+    pub fn to_chunks(&self) -> Vec<Chunk> {
+        let mut chunks = HashMap::<IVec3, Chunk>::default();
+
+        for (position, index) in self.world_voxels() {
+            let chunk_position = position.div_euclid(IVec3::splat(CHUNK_SZ as i32));
+            let local = position
+                .rem_euclid(IVec3::splat(CHUNK_SZ as i32))
+                .as_uvec3();
+
+            let chunk = chunks
+                .entry(chunk_position)
+                .or_insert_with(|| Chunk::new(chunk_position));
+
+            chunk.voxels.set(
+                [local.x as usize, local.y as usize, local.z as usize],
+                Voxel {
+                    flags: index as u32,
+                    density: 1.0,
+                },
+            );
+        }
+
+        chunks
+            .into_values()
+            .map(|mut chunk| {
+                chunk.voxels.optimize();
+                chunk
+            })
+            .collect()
+    }
+
+    // A single model as a density grid with one unit per voxel, and the palette index of every
+    // sample in a "material" channel
+    pub fn to_voxel_grid(&self, model: usize) -> Option<(VoxelGrid, VoxelChannels)> {
+        let model = self.models.get(model)?;
+
+        // MagicaVoxel y becomes -z
+        let resolution = [
+            model.size[0].max(0) as usize,
+            model.size[2].max(0) as usize,
+            model.size[1].max(0) as usize,
+        ];
+
+        let mut data = vec![0.0; resolution.iter().product()];
+        let mut material_ids = vec![0.0; data.len()];
+
+        for [x, y, z, index] in model.voxels.iter() {
+            let (gx, gy, gz) = (
+                *x as usize,
+                *z as usize,
+                resolution[2] - 1 - (*y as usize).min(resolution[2] - 1),
+            );
+
+            if gx >= resolution[0] || gy >= resolution[1] {
+                continue;
+            }
+
+            let i = gx + gy * resolution[0] + gz * resolution[0] * resolution[1];
+            data[i] = 1.0;
+            material_ids[i] = *index as f32;
+        }
+
+        let voxel_grid = VoxelGrid {
+            resolution,
+            data: SparseGrid::from_dense(resolution, &data),
+            bounds: Bounds {
+                min: Vec3::ZERO,
+                max: Vec3::new(
+                    resolution[0] as f32,
+                    resolution[1] as f32,
+                    resolution[2] as f32,
+                ),
+            },
+        };
+
+        // Surface vertices lie between solid and empty samples, empty samples take the material of
+        // a solid neighbour so the nearest sample always has one
+        let mut dilated = material_ids.clone();
+
+        for z in 0..resolution[2] {
+            for y in 0..resolution[1] {
+                for x in 0..resolution[0] {
+                    let i = x + y * resolution[0] + z * resolution[0] * resolution[1];
+
+                    if material_ids[i] != 0.0 {
+                        continue;
+                    }
+
+                    let neighbours = [
+                        (x > 0).then(|| i - 1),
+                        (x + 1 < resolution[0]).then(|| i + 1),
+                        (y > 0).then(|| i - resolution[0]),
+                        (y + 1 < resolution[1]).then(|| i + resolution[0]),
+                        (z > 0).then(|| i - resolution[0] * resolution[1]),
+                        (z + 1 < resolution[2]).then(|| i + resolution[0] * resolution[1]),
+                    ];
+
+                    if let Some(material) = neighbours
+                        .into_iter()
+                        .flatten()
+                        .map(|n| material_ids[n])
+                        .find(|material| *material != 0.0)
+                    {
+                        dilated[i] = material;
+                    }
+                }
+            }
+        }
+
+        let schema = ChannelSchema {
+            channels: vec![VoxelChannel {
+                name: "material".to_string(),
+                default: 0.0,
+                interpolation: ChannelInterpolation::Nearest,
+                target: ChannelTarget::MaterialId,
+            }],
+        };
+
+        let mut channels = VoxelChannels::for_grid(schema, &voxel_grid);
+
+        if let Some(material) = channels.channel_mut("material") {
+            *material = SparseGrid::from_dense(resolution, &dilated);
+        }
+
+        Some((voxel_grid, channels))
+    }
+
+    // Voxels with non zero flags are exported with their flags as the palette index, smooth voxels
+    // above the iso level with palette index 1
+    pub fn from_chunks<'a>(
+        chunks: impl IntoIterator<Item = &'a Chunk>,
+        palette: [[u8; 4]; 256],
+    ) -> Self {
+        let mut voxels = Vec::new();
+
+        for chunk in chunks {
+            if chunk
+                .voxels
+                .uniform()
+                .is_some_and(|voxel| voxel.flags == 0 && voxel.density < 0.5)
+            {
+                continue;
+            }
+
+            let origin = chunk.position * CHUNK_SZ as i32;
+
+            for (i, voxel) in chunk.voxels.iter().enumerate() {
+                let index = if voxel.flags != 0 {
+                    voxel.flags.min(255) as u8
+                } else if voxel.density >= 0.5 {
+                    1
+                } else {
+                    continue;
+                };
+
+                let local = IVec3::new(
+                    (i % CHUNK_SZ) as i32,
+                    ((i / CHUNK_SZ) % CHUNK_SZ) as i32,
+                    (i / CHUNK_SZ_2) as i32,
+                );
+                let p = origin + local;
+
+                // Back to z up
+                voxels.push(([p.x, -p.z, p.y], index));
+            }
+        }
+
+        let mut models = Vec::new();
+        let mut instances = Vec::new();
+
+        let Some(min) = voxels
+            .iter()
+            .map(|(p, _)| *p)
+            .reduce(|a, b| [0, 1, 2].map(|i| a[i].min(b[i])))
+        else {
+            return Self {
+                models,
+                instances,
+                palette,
+            };
+        };
+
+        // Split into blocks no larger than a model can be
+        let mut blocks = HashMap::<[i32; 3], Vec<[u8; 4]>>::default();
+
+        for (p, index) in voxels {
+            let relative = [0, 1, 2].map(|i| p[i] - min[i]);
+            let block = relative.map(|r| r / MAX_MODEL_SIZE);
+            let local = relative.map(|r| (r % MAX_MODEL_SIZE) as u8);
+
+            blocks
+                .entry(block)
+                .or_default()
+                .push([local[0], local[1], local[2], index]);
+        }
+
+        for (block, block_voxels) in blocks {
+            let size = [0, 1, 2].map(|i| {
+                block_voxels
+                    .iter()
+                    .map(|voxel| voxel[i] as i32 + 1)
+                    .max()
+                    .unwrap_or(1)
+            });
+
+            let origin = [0, 1, 2].map(|i| min[i] + block[i] * MAX_MODEL_SIZE);
+
+            instances.push(VoxInstance {
+                model: models.len(),
+                transform: VoxTransform {
+                    translation: [0, 1, 2].map(|i| origin[i] + size[i] / 2),
+                    ..Default::default()
+                },
+            });
+            models.push(VoxModel {
+                size,
+                voxels: block_voxels,
+            });
+        }
+
+        Self {
+            models,
+            instances,
+            palette,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut children = Vec::new();
+
+        for model in self.models.iter() {
+            let mut size = Vec::new();
+            for value in model.size {
+                size.extend_from_slice(&value.to_le_bytes());
+            }
+            write_chunk(&mut children, b"SIZE", &size);
+
+            let mut xyzi = (model.voxels.len() as i32).to_le_bytes().to_vec();
+            for voxel in model.voxels.iter() {
+                xyzi.extend_from_slice(voxel);
+            }
+            write_chunk(&mut children, b"XYZI", &xyzi);
+        }
+
+        // Root transform 0, group 1, then a transform and shape node per instance
+        let mut node_id = 2i32;
+        let mut group_children = Vec::new();
+
+        let mut instance_nodes = Vec::new();
+
+        for instance in self.instances.iter() {
+            group_children.push(node_id);
+
+            let mut transform = Vec::new();
+            transform.extend_from_slice(&node_id.to_le_bytes());
+            write_dict(&mut transform, &[]);
+            transform.extend_from_slice(&(node_id + 1).to_le_bytes());
+            transform.extend_from_slice(&(-1i32).to_le_bytes());
+            transform.extend_from_slice(&0i32.to_le_bytes());
+            transform.extend_from_slice(&1i32.to_le_bytes());
+
+            let translation = instance.transform.translation;
+            write_dict(
+                &mut transform,
+                &[
+                    ("_r", instance.transform.to_packed().to_string()),
+                    (
+                        "_t",
+                        format!("{} {} {}", translation[0], translation[1], translation[2]),
+                    ),
+                ],
+            );
+
+            let mut shape = Vec::new();
+            shape.extend_from_slice(&(node_id + 1).to_le_bytes());
+            write_dict(&mut shape, &[]);
+            shape.extend_from_slice(&1i32.to_le_bytes());
+            shape.extend_from_slice(&(instance.model as i32).to_le_bytes());
+            write_dict(&mut shape, &[]);
+
+            instance_nodes.push((transform, shape));
+            node_id += 2;
+        }
+
+        let mut root = Vec::new();
+        root.extend_from_slice(&0i32.to_le_bytes());
+        write_dict(&mut root, &[]);
+        root.extend_from_slice(&1i32.to_le_bytes());
+        root.extend_from_slice(&(-1i32).to_le_bytes());
+        root.extend_from_slice(&0i32.to_le_bytes());
+        root.extend_from_slice(&1i32.to_le_bytes());
+        write_dict(&mut root, &[]);
+        write_chunk(&mut children, b"nTRN", &root);
+
+        let mut group = Vec::new();
+        group.extend_from_slice(&1i32.to_le_bytes());
+        write_dict(&mut group, &[]);
+        group.extend_from_slice(&(group_children.len() as i32).to_le_bytes());
+        for child in group_children.iter() {
+            group.extend_from_slice(&child.to_le_bytes());
+        }
+        write_chunk(&mut children, b"nGRP", &group);
+
+        for (transform, shape) in instance_nodes {
+            write_chunk(&mut children, b"nTRN", &transform);
+            write_chunk(&mut children, b"nSHP", &shape);
+        }
+
+        let mut rgba = Vec::with_capacity(256 * 4);
+        for i in 1..=256 {
+            rgba.extend_from_slice(&self.palette[i % 256]);
+        }
+        write_chunk(&mut children, b"RGBA", &rgba);
+
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"VOX ");
+        bytes.extend_from_slice(&150i32.to_le_bytes());
+        bytes.extend_from_slice(b"MAIN");
+        bytes.extend_from_slice(&0i32.to_le_bytes());
+        bytes.extend_from_slice(&(children.len() as i32).to_le_bytes());
+        bytes.extend_from_slice(&children);
+
+        bytes
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        std::fs::write(path, self.to_bytes())
+    }
+}
+
+fn collect_instances(
+    nodes: &HashMap<i32, SceneNode>,
+    node_id: i32,
+    parent: VoxTransform,
+    instances: &mut Vec<VoxInstance>,
+    depth: usize,
+) {
+    // Guards against cycles in malformed files
+    if depth > 64 {
+        return;
+    }
+
+    match nodes.get(&node_id) {
+        Some(SceneNode::Transform { child, transform }) => {
+            collect_instances(nodes, *child, parent.then(transform), instances, depth + 1);
+        }
+        Some(SceneNode::Group { children }) => {
+            for child in children.iter() {
+                collect_instances(nodes, *child, parent, instances, depth + 1);
+            }
+        }
+        Some(SceneNode::Shape { models }) => {
+            instances.extend(models.iter().map(|model| VoxInstance {
+                model: *model,
+                transform: parent,
+            }));
+        }
+        None => {}
+    }
+}
+
+fn write_chunk(bytes: &mut Vec<u8>, id: &[u8; 4], content: &[u8]) {
+    bytes.extend_from_slice(id);
+    bytes.extend_from_slice(&(content.len() as i32).to_le_bytes());
+    bytes.extend_from_slice(&0i32.to_le_bytes());
+    bytes.extend_from_slice(content);
+}
+
+fn write_dict(bytes: &mut Vec<u8>, entries: &[(&str, String)]) {
+    bytes.extend_from_slice(&(entries.len() as i32).to_le_bytes());
+
+    for (key, value) in entries {
+        for string in [*key, value.as_str()] {
+            bytes.extend_from_slice(&(string.len() as i32).to_le_bytes());
+            bytes.extend_from_slice(string.as_bytes());
+        }
+    }
+}
+
+// Used when a file has no RGBA chunk, a simple ramp instead of MagicaVoxel's built in palette
+fn default_palette() -> [[u8; 4]; 256] {
+    let mut palette = [[0; 4]; 256];
+
+    for (i, color) in palette.iter_mut().enumerate().skip(1) {
+        let value = i as u8;
+        *color = [value, value.wrapping_mul(3), value.wrapping_mul(7), 255];
+    }
+
+    palette
+}
+
+#[derive(Default)]
+pub struct VoxLoader;
+
+impl AssetLoader for VoxLoader {
+    type Asset = VoxScene;
+    type Settings = ();
+    type Error = VoxError;
+
+    async fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader<'_>,
+        _settings: &'a (),
+        _load_context: &'a mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+
+        VoxScene::from_bytes(&bytes)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["vox"]
+    }
+}
+
+// Writes every chunk to `VoxExportSettings::path`
+#[derive(Event, Clone, Copy, Debug, Default)]
+pub struct ExportVox;
+
+pub struct VoxPlugin;
+
+impl Plugin for VoxPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<VoxScene>()
+            .init_asset_loader::<VoxLoader>()
+            .init_resource::<VoxExportSettings>()
+            .add_event::<ExportVox>()
+            .add_systems(Update, export_vox_system);
+    }
+}
+
+#[derive(Resource, Clone, Debug)]
+pub struct VoxExportSettings {
+    pub path: PathBuf,
+    pub palette: [[u8; 4]; 256],
+}
+
+impl Default for VoxExportSettings {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::from("export.vox"),
+            palette: default_palette(),
+        }
+    }
+}
+
+pub fn export_vox_system(
+    settings: Res<VoxExportSettings>,
+    query: Query<&Chunk>,
+    mut requests: EventReader<ExportVox>,
+) {
+    if requests.read().count() == 0 {
+        return;
+    }
+
+    let scene = VoxScene::from_chunks(query.iter(), settings.palette);
+
+    match scene.save(&settings.path) {
+        Ok(()) => info!(
+            "Exported {} models to {:?}",
+            scene.models.len(),
+            settings.path
+        ),
+        Err(err) => error!("Failed to export {:?}: {}", settings.path, err),
+    }
+}
@@ -0,0 +1,729 @@
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+};
+
+use bevy::{
+    app::{App, Plugin, Update},
+    asset::{
+        io::{Reader, Writer},
+        processor::LoadAndSave,
+        saver::{AssetSaver, SavedAsset},
+        Asset, AssetApp, AssetLoader, Assets, AsyncReadExt, AsyncWriteExt, Handle, LoadContext,
+    },
+    core::Name,
+    log::{error, info, warn},
+    math::{IVec3, Vec3},
+    prelude::{Commands, Entity, Event, EventReader, Query, Res, Resource},
+    reflect::TypePath,
+};
+
+use crate::{
+    chunk::{Chunk, Voxel, CHUNK_SZ, CHUNK_SZ_3},
+    marching_cubes_cpu::{Bounds, VoxelGrid},
+    sparse::SparseGrid,
+    voxel_value::{VoxelValue, F16},
+};
+
+// Layout, all little endian:
+//
+// magic        4 bytes  "MCVX"
+// version      u16
+// value type   u8       0 = f32 density, 1 = voxel (u32 flags, f32 density), 2 = u8 density,
+//                       3 = i8 density, 4 = f16 density
+// compression  u8       0 = none, 1 = run length encoded u32 words
+// dimensions   3 x u32
+// position     3 x i32  chunk position, zero for grids
+// bounds       6 x f32  min and max, zero for chunks
+// payload size u32      in bytes after compression
+// payload
+const MAGIC: &[u8; 4] = b"MCVX";
+const VERSION: u16 = 1;
+const HEADER_SIZE: usize = 4 + 2 + 1 + 1 + 12 + 12 + 24 + 4;
+// Largest grid a file may describe, 512^3 samples. The dimensions are checked against it before
+// the payload is expanded, so a small corrupt file can't allocate gigabytes.
+const MAX_GRID_SAMPLES: usize = 1 << 27;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ValueType {
+    Density = 0,
+    Voxel = 1,
+    DensityU8 = 2,
+    DensityI8 = 3,
+    DensityF16 = 4,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Compression {
+    None = 0,
+    RunLength = 1,
+}
+
+// Grids keep the value type they were saved with
+#[derive(Asset, TypePath, Clone)]
+pub enum VoxelFile {
+    Grid(VoxelGrid),
+    GridU8(VoxelGrid<u8>),
+    GridI8(VoxelGrid<i8>),
+    GridF16(VoxelGrid<F16>),
+    Chunk(Chunk),
+}
+
+// Values of the grids in a voxel file, every sample is stored in one u32 word
+pub trait VoxelFileValue: VoxelValue {
+    const VALUE_TYPE: ValueType;
+
+    fn to_word(self) -> u32;
+    fn from_word(word: u32) -> Self;
+}
+
+impl VoxelFileValue for f32 {
+    const VALUE_TYPE: ValueType = ValueType::Density;
+
+    fn to_word(self) -> u32 {
+        self.to_bits()
+    }
+
+    fn from_word(word: u32) -> Self {
+        f32::from_bits(word)
+    }
+}
+
+impl VoxelFileValue for u8 {
+    const VALUE_TYPE: ValueType = ValueType::DensityU8;
+
+    fn to_word(self) -> u32 {
+        self as u32
+    }
+
+    fn from_word(word: u32) -> Self {
+        word as u8
+    }
+}
+
+impl VoxelFileValue for i8 {
+    const VALUE_TYPE: ValueType = ValueType::DensityI8;
+
+    fn to_word(self) -> u32 {
+        self as u8 as u32
+    }
+
+    fn from_word(word: u32) -> Self {
+        word as u8 as i8
+    }
+}
+
+impl VoxelFileValue for F16 {
+    const VALUE_TYPE: ValueType = ValueType::DensityF16;
+
+    fn to_word(self) -> u32 {
+        self.0 as u32
+    }
+
+    fn from_word(word: u32) -> Self {
+        F16(word as u16)
+    }
+}
+
+type GridHeader = (ValueType, [u32; 3], IVec3, Bounds, Vec<u32>);
+
+fn grid_words<V: VoxelFileValue>(voxel_grid: &VoxelGrid<V>) -> GridHeader {
+    (
+        V::VALUE_TYPE,
+        voxel_grid.resolution.map(|r| r as u32),
+        IVec3::ZERO,
+        voxel_grid.bounds,
+        voxel_grid.data.iter().map(V::to_word).collect(),
+    )
+}
+
+fn grid_from_words<V: VoxelFileValue>(
+    dimensions: [usize; 3],
+    bounds: [f32; 6],
+    words: Vec<u32>,
+) -> VoxelGrid<V> {
+    VoxelGrid {
+        resolution: dimensions,
+        data: SparseGrid::from_dense(
+            dimensions,
+            &words.into_iter().map(V::from_word).collect::<Vec<V>>(),
+        ),
+        bounds: Bounds {
+            min: Vec3::new(bounds[0], bounds[1], bounds[2]),
+            max: Vec3::new(bounds[3], bounds[4], bounds[5]),
+        },
+    }
+}
+
+#[derive(Debug)]
+pub enum VoxelFileError {
+    Io(io::Error),
+    InvalidMagic,
+    UnsupportedVersion(u16),
+    InvalidHeader(&'static str),
+    Truncated,
+}
+
+impl fmt::Display for VoxelFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VoxelFileError::Io(err) => write!(f, "io error: {}", err),
+            VoxelFileError::InvalidMagic => write!(f, "not a voxel file"),
+            VoxelFileError::UnsupportedVersion(version) => {
+                write!(f, "unsupported version {}", version)
+            }
+            VoxelFileError::InvalidHeader(reason) => write!(f, "invalid header: {}", reason),
+            VoxelFileError::Truncated => write!(f, "file is truncated"),
+        }
+    }
+}
+
+impl std::error::Error for VoxelFileError {}
+
+impl From<io::Error> for VoxelFileError {
+    fn from(err: io::Error) -> Self {
+        VoxelFileError::Io(err)
+    }
+}
+
+impl VoxelFile {
+    pub fn to_bytes(&self, compression: Compression) -> Vec<u8> {
+        let (value_type, dimensions, position, bounds, words) = match self {
+            VoxelFile::Grid(voxel_grid) => grid_words(voxel_grid),
+            VoxelFile::GridU8(voxel_grid) => grid_words(voxel_grid),
+            VoxelFile::GridI8(voxel_grid) => grid_words(voxel_grid),
+            VoxelFile::GridF16(voxel_grid) => grid_words(voxel_grid),
+            VoxelFile::Chunk(chunk) => (
+                ValueType::Voxel,
+                [CHUNK_SZ as u32; 3],
+                chunk.position,
+                Bounds {
+                    min: Vec3::ZERO,
+                    max: Vec3::ZERO,
+                },
+                chunk
+                    .voxels
+                    .iter()
+                    .flat_map(|voxel| [voxel.flags, voxel.density.to_bits()])
+                    .collect::<Vec<u32>>(),
+            ),
+        };
+
+        let payload = match compression {
+            Compression::None => words.iter().flat_map(|word| word.to_le_bytes()).collect(),
+            Compression::RunLength => run_length_encode(&words),
+        };
+
+        let mut bytes = Vec::with_capacity(HEADER_SIZE + payload.len());
+
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.push(value_type as u8);
+        bytes.push(compression as u8);
+
+        for dimension in dimensions {
+            bytes.extend_from_slice(&dimension.to_le_bytes());
+        }
+
+        for coordinate in position.to_array() {
+            bytes.extend_from_slice(&coordinate.to_le_bytes());
+        }
+
+        for value in bounds
+            .min
+            .to_array()
+            .into_iter()
+            .chain(bounds.max.to_array())
+        {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+
+        bytes.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&payload);
+
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, VoxelFileError> {
+        if bytes.len() < HEADER_SIZE {
+            return Err(VoxelFileError::Truncated);
+        }
+
+        if &bytes[0..4] != MAGIC {
+            return Err(VoxelFileError::InvalidMagic);
+        }
+
+        let mut cursor = 4;
+
+        let read_u32 = |cursor: &mut usize| {
+            let value = u32::from_le_bytes(bytes[*cursor..*cursor + 4].try_into().unwrap());
+            *cursor += 4;
+            value
+        };
+
+        let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+        cursor += 2;
+
+        if version != VERSION {
+            return Err(VoxelFileError::UnsupportedVersion(version));
+        }
+
+        let value_type = match bytes[cursor] {
+            0 => ValueType::Density,
+            1 => ValueType::Voxel,
+            2 => ValueType::DensityU8,
+            3 => ValueType::DensityI8,
+            4 => ValueType::DensityF16,
+            _ => return Err(VoxelFileError::InvalidHeader("unknown value type")),
+        };
+        let compression = match bytes[cursor + 1] {
+            0 => Compression::None,
+            1 => Compression::RunLength,
+            _ => return Err(VoxelFileError::InvalidHeader("unknown compression")),
+        };
+        cursor += 2;
+
+        let dimensions = [0; 3].map(|_: u32| read_u32(&mut cursor) as usize);
+        let position = [0; 3].map(|_: u32| read_u32(&mut cursor) as i32);
+        let bounds = [0; 6].map(|_: u32| f32::from_bits(read_u32(&mut cursor)));
+        let payload_size = read_u32(&mut cursor) as usize;
+
+        let payload = cursor
+            .checked_add(payload_size)
+            .and_then(|end| bytes.get(cursor..end))
+            .ok_or(VoxelFileError::Truncated)?;
+
+        let (words_per_value, max_samples) = match value_type {
+            ValueType::Voxel => (2, CHUNK_SZ_3),
+            _ => (1, MAX_GRID_SAMPLES),
+        };
+
+        if value_type == ValueType::Voxel && dimensions != [CHUNK_SZ; 3] {
+            return Err(VoxelFileError::InvalidHeader("chunk size mismatch"));
+        }
+
+        let sample_count = dimensions
+            .iter()
+            .try_fold(1usize, |count, size| count.checked_mul(*size))
+            .filter(|count| *count <= max_samples)
+            .ok_or(VoxelFileError::InvalidHeader("dimensions too large"))?;
+        let word_count = sample_count * words_per_value;
+
+        let words = match compression {
+            Compression::None => {
+                if payload.len() != word_count * 4 {
+                    return Err(VoxelFileError::Truncated);
+                }
+
+                payload
+                    .chunks_exact(4)
+                    .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+                    .collect()
+            }
+            Compression::RunLength => run_length_decode(payload, word_count)?,
+        };
+
+        match value_type {
+            ValueType::Density => Ok(VoxelFile::Grid(grid_from_words(dimensions, bounds, words))),
+            ValueType::DensityU8 => Ok(VoxelFile::GridU8(grid_from_words(
+                dimensions, bounds, words,
+            ))),
+            ValueType::DensityI8 => Ok(VoxelFile::GridI8(grid_from_words(
+                dimensions, bounds, words,
+            ))),
+            ValueType::DensityF16 => Ok(VoxelFile::GridF16(grid_from_words(
+                dimensions, bounds, words,
+            ))),
+            ValueType::Voxel => {
+                let mut chunk = Chunk::new(IVec3::from_array(position));
+                chunk.voxels = SparseGrid::from_dense(
+                    [CHUNK_SZ; 3],
+                    &words
+                        .chunks_exact(2)
+                        .map(|pair| Voxel {
+                            flags: pair[0],
+                            density: f32::from_bits(pair[1]),
+                        })
+                        .collect::<Vec<Voxel>>(),
+                );
+
+                Ok(VoxelFile::Chunk(chunk))
+            }
+        }
+    }
+
+    // The grid of the file converted to `V`, None for chunks
+    pub fn to_grid<V: VoxelValue>(&self) -> Option<VoxelGrid<V>> {
+        match self {
+            VoxelFile::Grid(voxel_grid) => Some(voxel_grid.convert()),
+            VoxelFile::GridU8(voxel_grid) => Some(voxel_grid.convert()),
+            VoxelFile::GridI8(voxel_grid) => Some(voxel_grid.convert()),
+            VoxelFile::GridF16(voxel_grid) => Some(voxel_grid.convert()),
+            VoxelFile::Chunk(_) => None,
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        std::fs::write(path, self.to_bytes(Compression::RunLength))
+    }
+
+    pub fn load(path: &Path) -> Result<Self, VoxelFileError> {
+        Self::from_bytes(&std::fs::read(path)?)
+    }
+}
+
+// Pairs of (run length, word), voxel data is mostly long runs of empty space
+fn run_length_encode(words: &[u32]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+
+    let mut i = 0;
+    while i < words.len() {
+        let word = words[i];
+        let mut run = 1;
+
+        while i + run < words.len() && words[i + run] == word && run < u32::MAX as usize {
+            run += 1;
+        }
+
+        bytes.extend_from_slice(&(run as u32).to_le_bytes());
+        bytes.extend_from_slice(&word.to_le_bytes());
+
+        i += run;
+    }
+
+    bytes
+}
+
+// Runs past `word_count` are rejected before they are expanded, a corrupt run length would
+// otherwise allocate gigabytes. Runs that end early leave the file short of samples.
+fn run_length_decode(bytes: &[u8], word_count: usize) -> Result<Vec<u32>, VoxelFileError> {
+    if bytes.len() % 8 != 0 {
+        return Err(VoxelFileError::Truncated);
+    }
+
+    let mut words = Vec::new();
+
+    for pair in bytes.chunks_exact(8) {
+        let run = u32::from_le_bytes(pair[0..4].try_into().unwrap()) as usize;
+        let word = u32::from_le_bytes(pair[4..8].try_into().unwrap());
+
+        if run > word_count - words.len() {
+            return Err(VoxelFileError::InvalidHeader("runs exceed the dimensions"));
+        }
+
+        words.extend(std::iter::repeat(word).take(run));
+    }
+
+    if words.len() != word_count {
+        return Err(VoxelFileError::Truncated);
+    }
+
+    Ok(words)
+}
+
+#[derive(Default)]
+pub struct VoxelFileLoader;
+
+impl AssetLoader for VoxelFileLoader {
+    type Asset = VoxelFile;
+    type Settings = ();
+    type Error = VoxelFileError;
+
+    async fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader<'_>,
+        _settings: &'a (),
+        _load_context: &'a mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+
+        VoxelFile::from_bytes(&bytes)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["mcvx"]
+    }
+}
+
+#[derive(Default)]
+pub struct VoxelFileSaver;
+
+impl AssetSaver for VoxelFileSaver {
+    type Asset = VoxelFile;
+    type Settings = ();
+    type OutputLoader = VoxelFileLoader;
+    type Error = io::Error;
+
+    async fn save<'a>(
+        &'a self,
+        writer: &'a mut Writer,
+        asset: SavedAsset<'a, Self::Asset>,
+        _settings: &'a Self::Settings,
+    ) -> Result<(), Self::Error> {
+        writer
+            .write_all(&asset.to_bytes(Compression::RunLength))
+            .await
+    }
+}
+
+// Rewrites voxel files run length encoded when the app processes its assets
+pub type VoxelFileProcessor = LoadAndSave<VoxelFileLoader, VoxelFileSaver>;
+
+// Saves every chunk and named grid into `VoxelSaveSettings::directory`
+#[derive(Event, Clone, Copy, Debug, Default)]
+pub struct SaveVoxels;
+
+pub struct VoxelFilePlugin;
+
+impl Plugin for VoxelFilePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<VoxelFile>()
+            .init_asset_loader::<VoxelFileLoader>()
+            .register_asset_processor(VoxelFileProcessor::from(VoxelFileSaver))
+            .set_default_asset_processor::<VoxelFileProcessor>("mcvx")
+            .init_resource::<VoxelSaveSettings>()
+            .add_event::<SaveVoxels>()
+            .add_systems(Update, (save_voxels_system, apply_voxel_files_system));
+    }
+}
+
+#[derive(Resource, Clone, Debug)]
+pub struct VoxelSaveSettings {
+    // Chunks are written as chunk_x_y_z.mcvx and grids as grid_<name>.mcvx inside of it, so the
+    // files can be matched up with the same chunks and grids in the next session. Grids without a
+    // `Name` aren't saved.
+    pub directory: PathBuf,
+}
+
+impl Default for VoxelSaveSettings {
+    fn default() -> Self {
+        Self {
+            directory: PathBuf::from("assets/saves"),
+        }
+    }
+}
+
+type SavedVoxelsQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Option<&'static Name>,
+        Option<&'static Chunk>,
+        Option<&'static VoxelGrid>,
+        Option<&'static VoxelGrid<u8>>,
+        Option<&'static VoxelGrid<i8>>,
+        Option<&'static VoxelGrid<F16>>,
+    ),
+>;
+
+pub fn save_voxels_system(
+    settings: Res<VoxelSaveSettings>,
+    query: SavedVoxelsQuery,
+    mut requests: EventReader<SaveVoxels>,
+) {
+    if requests.read().count() == 0 {
+        return;
+    }
+
+    let mut saved = 0;
+
+    for (name, chunk, grid_f32, grid_u8, grid_i8, grid_f16) in query.iter() {
+        let mut files = Vec::new();
+
+        let grid = grid_f32
+            .map(|voxel_grid| VoxelFile::Grid(voxel_grid.clone()))
+            .or_else(|| grid_u8.map(|voxel_grid| VoxelFile::GridU8(voxel_grid.clone())))
+            .or_else(|| grid_i8.map(|voxel_grid| VoxelFile::GridI8(voxel_grid.clone())))
+            .or_else(|| grid_f16.map(|voxel_grid| VoxelFile::GridF16(voxel_grid.clone())));
+
+        match (grid, name) {
+            (Some(grid), Some(name)) => {
+                let path = settings
+                    .directory
+                    .join(format!("grid_{}.mcvx", file_stem(name)));
+                files.push((path, grid));
+            }
+            (Some(_), None) => warn!("Skipped saving a voxel grid without a Name"),
+            _ => {}
+        }
+
+        if let Some(chunk) = chunk {
+            let path = settings.directory.join(format!(
+                "chunk_{}_{}_{}.mcvx",
+                chunk.position.x, chunk.position.y, chunk.position.z
+            ));
+            files.push((path, VoxelFile::Chunk(chunk.clone())));
+        }
+
+        for (path, file) in files {
+            match file.save(&path) {
+                Ok(()) => saved += 1,
+                Err(err) => error!("Failed to save {:?}: {}", path, err),
+            }
+        }
+    }
+
+    info!("Saved {} voxel files to {:?}", saved, settings.directory);
+}
+
+// Characters other than letters, digits, '-' and '_' are replaced so any name gives a valid file
+fn file_stem(name: &Name) -> String {
+    name.as_str()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+// Replaces the voxels of an entity once the voxel file it has a handle to is loaded
+pub fn apply_voxel_files_system(
+    mut commands: Commands,
+    query: Query<(Entity, &Handle<VoxelFile>, Option<&Chunk>)>,
+    files: Res<Assets<VoxelFile>>,
+) {
+    for (entity, handle, chunk) in query.iter() {
+        let Some(file) = files.get(handle) else {
+            continue;
+        };
+
+        let mut entity_commands = commands.entity(entity);
+
+        match file {
+            VoxelFile::Grid(voxel_grid) => {
+                entity_commands.insert(voxel_grid.clone());
+            }
+            VoxelFile::GridU8(voxel_grid) => {
+                entity_commands.insert(voxel_grid.clone());
+            }
+            VoxelFile::GridI8(voxel_grid) => {
+                entity_commands.insert(voxel_grid.clone());
+            }
+            VoxelFile::GridF16(voxel_grid) => {
+                entity_commands.insert(voxel_grid.clone());
+            }
+            VoxelFile::Chunk(loaded) => {
+                // Keep the level of detail the chunk already has
+                let mut loaded = loaded.clone();
+                if let Some(chunk) = chunk {
+                    loaded.lod = chunk.lod;
+                }
+                entity_commands.insert(loaded);
+            }
+        }
+
+        entity_commands.remove::<Handle<VoxelFile>>();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grid() -> VoxelFile {
+        let values = (0..64).map(|i| (i / 16) as f32).collect::<Vec<f32>>();
+
+        VoxelFile::Grid(VoxelGrid {
+            resolution: [4; 3],
+            data: SparseGrid::from_dense([4; 3], &values),
+            bounds: Bounds {
+                min: Vec3::ZERO,
+                max: Vec3::ONE,
+            },
+        })
+    }
+
+    #[test]
+    fn run_length_round_trip() {
+        let bytes = grid().to_bytes(Compression::RunLength);
+
+        let Ok(VoxelFile::Grid(voxel_grid)) = VoxelFile::from_bytes(&bytes) else {
+            panic!("the grid should load");
+        };
+
+        assert_eq!(voxel_grid.resolution, [4; 3]);
+        assert_eq!(voxel_grid.get(0, 0, 3), 3.0);
+    }
+
+    #[test]
+    fn quantized_grids_keep_their_value_type() {
+        let VoxelFile::Grid(voxel_grid) = grid() else {
+            unreachable!();
+        };
+
+        let voxel_grid = voxel_grid.convert::<i8>();
+        let file = VoxelFile::GridI8(voxel_grid.clone());
+
+        for compression in [Compression::None, Compression::RunLength] {
+            let Ok(VoxelFile::GridI8(loaded)) = VoxelFile::from_bytes(&file.to_bytes(compression))
+            else {
+                panic!("the i8 grid should load");
+            };
+
+            assert!(loaded.data.iter().eq(voxel_grid.data.iter()));
+        }
+
+        let file = VoxelFile::GridF16(voxel_grid.convert());
+        let loaded = VoxelFile::from_bytes(&file.to_bytes(Compression::RunLength)).unwrap();
+
+        assert!(matches!(loaded, VoxelFile::GridF16(_)));
+        assert_eq!(loaded.to_grid::<f32>().unwrap().get(0, 0, 0), 0.0);
+        assert_eq!(loaded.to_grid::<u8>().unwrap().get(0, 0, 3), 1.0);
+    }
+
+    #[test]
+    fn huge_dimensions_are_rejected_before_decoding() {
+        let mut bytes = grid().to_bytes(Compression::RunLength);
+
+        // 2048^3 samples made of a single run
+        for axis in 0..3 {
+            let offset = 8 + axis * 4;
+            bytes[offset..offset + 4].copy_from_slice(&2048u32.to_le_bytes());
+        }
+        bytes[HEADER_SIZE..HEADER_SIZE + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+
+        assert!(matches!(
+            VoxelFile::from_bytes(&bytes),
+            Err(VoxelFileError::InvalidHeader(_))
+        ));
+    }
+
+    #[test]
+    fn runs_ending_early_are_rejected() {
+        let mut bytes = grid().to_bytes(Compression::RunLength);
+
+        // Drop the last run
+        bytes.truncate(bytes.len() - 8);
+        let payload_size = (bytes.len() - HEADER_SIZE) as u32;
+        bytes[HEADER_SIZE - 4..HEADER_SIZE].copy_from_slice(&payload_size.to_le_bytes());
+
+        assert!(matches!(
+            VoxelFile::from_bytes(&bytes),
+            Err(VoxelFileError::Truncated)
+        ));
+    }
+
+    #[test]
+    fn run_past_the_dimensions_is_rejected() {
+        let mut bytes = grid().to_bytes(Compression::RunLength);
+
+        // The first run of the payload claims every word there can be
+        bytes[HEADER_SIZE..HEADER_SIZE + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+
+        assert!(matches!(
+            VoxelFile::from_bytes(&bytes),
+            Err(VoxelFileError::InvalidHeader(_))
+        ));
+    }
+}
@@ -0,0 +1,116 @@
+// Storage type of the samples of a VoxelGrid. Meshers interpolate in f32, so every type converts
+// to and from it; quantized types map their range onto the densities the meshers expect.
+pub trait VoxelValue: Copy + PartialEq + Send + Sync + 'static {
+    // Densities the type is meant to hold, edits keep values within them. Floats can store more,
+    // but like the meshers they use densities from 0 to 1.
+    const MIN_DENSITY: f32;
+    const MAX_DENSITY: f32;
+
+    fn to_f32(self) -> f32;
+    fn from_f32(value: f32) -> Self;
+}
+
+impl VoxelValue for f32 {
+    const MIN_DENSITY: f32 = 0.0;
+    const MAX_DENSITY: f32 = 1.0;
+
+    fn to_f32(self) -> f32 {
+        self
+    }
+
+    fn from_f32(value: f32) -> Self {
+        value
+    }
+}
+
+// 0 to 255 covers densities from 0 to 1
+impl VoxelValue for u8 {
+    const MIN_DENSITY: f32 = 0.0;
+    const MAX_DENSITY: f32 = 1.0;
+
+    fn to_f32(self) -> f32 {
+        self as f32 / 255.0
+    }
+
+    fn from_f32(value: f32) -> Self {
+        (value.clamp(0.0, 1.0) * 255.0).round() as u8
+    }
+}
+
+// -127 to 127 covers densities from -1 to 1, for signed distances
+impl VoxelValue for i8 {
+    const MIN_DENSITY: f32 = -1.0;
+    const MAX_DENSITY: f32 = 1.0;
+
+    fn to_f32(self) -> f32 {
+        (self as f32 / 127.0).max(-1.0)
+    }
+
+    fn from_f32(value: f32) -> Self {
+        (value.clamp(-1.0, 1.0) * 127.0).round() as i8
+    }
+}
+
+// IEEE 754 half precision float
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct F16(pub u16);
+
+impl VoxelValue for F16 {
+    const MIN_DENSITY: f32 = 0.0;
+    const MAX_DENSITY: f32 = 1.0;
+
+    fn to_f32(self) -> f32 {
+        let bits = self.0 as u32;
+        let sign = (bits & 0x8000) << 16;
+        let exponent = (bits >> 10) & 0x1f;
+        let mantissa = bits & 0x3ff;
+
+        let bits = match exponent {
+            // Zero and subnormals
+            0 => {
+                let magnitude = mantissa as f32 * 2f32.powi(-24);
+                return if sign != 0 { -magnitude } else { magnitude };
+            }
+            // Infinity and NaN
+            0x1f => sign | 0x7f80_0000 | (mantissa << 13),
+            _ => sign | ((exponent + 127 - 15) << 23) | (mantissa << 13),
+        };
+
+        f32::from_bits(bits)
+    }
+
+    fn from_f32(value: f32) -> Self {
+        let bits = value.to_bits();
+        let sign = (bits >> 16) & 0x8000;
+        let exponent = ((bits >> 23) & 0xff) as i32;
+        let mantissa = bits & 0x7f_ffff;
+
+        if exponent == 0xff {
+            let nan = if mantissa != 0 { 0x200 } else { 0 };
+            return F16((sign | 0x7c00 | nan) as u16);
+        }
+
+        let half_exponent = exponent - 127 + 15;
+
+        if half_exponent >= 0x1f {
+            return F16((sign | 0x7c00) as u16);
+        }
+
+        if half_exponent <= 0 {
+            if half_exponent < -10 {
+                return F16(sign as u16);
+            }
+
+            let mantissa = mantissa | 0x80_0000;
+            let shift = (14 - half_exponent) as u32;
+            let round = (mantissa >> (shift - 1)) & 1;
+
+            return F16((sign | ((mantissa >> shift) + round)) as u16);
+        }
+
+        // Rounding can carry into the exponent, which still gives the right result
+        let round = (mantissa >> 12) & 1;
+
+        F16(((sign | ((half_exponent as u32) << 10) | (mantissa >> 13)) + round) as u16)
+    }
+}